@@ -23,7 +23,7 @@ use tracing::error;
 use crate::{
     apps::proxy::ProxyError,
     appstate::{ConfigFile, ConfigState},
-    auth::AdminToken,
+    auth::ScopedAdminToken,
     configuration::{HostType, config_or_error},
     utils::{is_default, option_vec_trim_remove_empties, string_trim, vec_trim_remove_empties},
 };
@@ -82,6 +82,14 @@ pub struct App {
     pub subdomains: Option<Vec<String>>,
     #[serde(default, skip_serializing_if = "is_default")]
     pub forward_user_mail: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub require_client_cert: bool,
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "vec_trim_remove_empties"
+    )]
+    pub tags: Vec<String>,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -226,21 +234,31 @@ where
 
 pub async fn get_apps(
     State(config_file): State<ConfigFile>,
-    _admin: AdminToken,
+    admin: ScopedAdminToken,
 ) -> Result<Json<Vec<App>>, (StatusCode, &'static str)> {
     let config = config_or_error(&config_file).await?;
-    // Return all the apps as Json
-    Ok(Json(config.apps))
+    // Return all the apps managed by the admin as Json
+    Ok(Json(
+        config
+            .apps
+            .into_iter()
+            .filter(|a| admin.app_allowed(a))
+            .collect(),
+    ))
 }
 
 pub async fn delete_app(
     State(config_file): State<ConfigFile>,
-    _admin: AdminToken,
+    admin: ScopedAdminToken,
     Path(app_id): Path<usize>,
 ) -> Result<impl IntoResponse, impl IntoResponse> {
     let mut config = config_or_error(&config_file).await?;
     // Find the app
-    if let Some(pos) = config.apps.iter().position(|a| a.id == app_id) {
+    if let Some(pos) = config
+        .apps
+        .iter()
+        .position(|a| a.id == app_id && admin.app_allowed(a))
+    {
         // It is an existing app, delete it
         config.apps.remove(pos);
     } else {
@@ -258,11 +276,22 @@ pub async fn delete_app(
 pub async fn add_app(
     State(config_file): State<ConfigFile>,
     State(config): State<ConfigState>,
-    _admin: AdminToken,
+    admin: ScopedAdminToken,
     Json(payload): Json<App>,
 ) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
     // Clone the config
     let mut config = (*config).clone();
+    // A delegated admin can only update the apps of its scope, without changing where they are served and proxied to
+    if !admin.is_global() {
+        let existing = config.apps.iter().find(|a| a.id == payload.id);
+        if !admin.app_allowed(&payload)
+            || existing.is_none_or(|a| {
+                !admin.app_allowed(a) || a.host != payload.host || a.target != payload.target
+            })
+        {
+            return Err((StatusCode::FORBIDDEN, "app is outside of the admin scope"));
+        }
+    }
     // Find the app
     if let Some(app) = config.apps.iter_mut().find(|a| a.id == payload.id) {
         *app = payload;
@@ -1,6 +1,9 @@
-use crate::configuration::{Config, HostType};
 #[cfg(target_os = "linux")]
 use crate::jail::Jail;
+use crate::{
+    auth::{cookie_keys::PreviousKey, share_registry::ShareRegistry},
+    configuration::{Config, HostType},
+};
 use axum::{body::Body, extract::FromRef};
 use axum_extra::extract::cookie::Key;
 use http::Request;
@@ -13,13 +16,19 @@ use maxminddb::Reader;
 use rustls::ClientConfig;
 use std::{
     collections::HashMap,
-    sync::{Arc, OnceLock},
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
 };
+use tokio::sync::broadcast::Sender;
 
 pub type OptionalMaxMindReader = Option<&'static Reader<Vec<u8>>>;
 pub type ConfigMap = Arc<HashMap<String, HostType>>;
 pub type ConfigFile = Arc<String>;
 pub type ConfigState = Arc<Config>;
+pub type PreviousCookieKeys = Arc<Vec<PreviousKey>>;
+pub type Shares = Arc<ShareRegistry>;
 pub struct Client(
     pub hyper_util::client::legacy::Client<HttpsConnector<HttpConnector<TokioHickoryResolver>>, Body>,
 );
@@ -39,16 +48,49 @@ impl Clone for InsecureSkipVerifyClient {
     }
 }
 
+/// Asks the server to reload its configuration, coalescing the requests made within a short delay
+#[derive(Clone)]
+pub struct Reloader {
+    tx: Sender<()>,
+    scheduled: Arc<AtomicBool>,
+}
+
+impl Reloader {
+    const DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+    pub fn new(tx: Sender<()>) -> Self {
+        Self {
+            tx,
+            scheduled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn schedule(&self) {
+        if self.scheduled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let reloader = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Self::DELAY).await;
+            reloader.scheduled.store(false, Ordering::SeqCst);
+            let _ = reloader.tx.send(());
+        });
+    }
+}
+
 pub static MAXMIND_READER: OnceLock<Reader<Vec<u8>>> = OnceLock::new();
 
 #[derive(Clone)]
 pub struct AppState {
     key: Key,
+    previous_keys: PreviousCookieKeys,
     config: ConfigState,
     config_map: ConfigMap,
     config_file: ConfigFile,
+    shares: Shares,
     client: Client,
     insecure_skip_verify_client: InsecureSkipVerifyClient,
+    reloader: Reloader,
     #[cfg(target_os = "linux")]
     pub jail: Option<Arc<Jail>>,
 }
@@ -56,9 +98,12 @@ pub struct AppState {
 impl AppState {
     pub(crate) fn new(
         key: Key,
+        previous_keys: Vec<PreviousKey>,
         config: ConfigState,
         config_map: ConfigMap,
         config_file: String,
+        shares: Shares,
+        reloader: Reloader,
         #[cfg(target_os = "linux")] jail: Option<Arc<Jail>>,
     ) -> Self {
         if let Ok(r) = maxminddb::Reader::open_readfile("GeoLite2-City.mmdb") {
@@ -94,11 +139,14 @@ impl AppState {
 
         AppState {
             key,
+            previous_keys: Arc::new(previous_keys),
             config,
             config_map,
             config_file: Arc::new(config_file),
+            shares,
             client: Client(client),
             insecure_skip_verify_client: InsecureSkipVerifyClient(unsecure_client),
+            reloader,
             #[cfg(target_os = "linux")]
             jail,
         }
@@ -111,6 +159,12 @@ impl FromRef<AppState> for Key {
     }
 }
 
+impl FromRef<AppState> for PreviousCookieKeys {
+    fn from_ref(state: &AppState) -> Self {
+        Arc::clone(&state.previous_keys)
+    }
+}
+
 impl FromRef<AppState> for ConfigState {
     fn from_ref(state: &AppState) -> Self {
         Arc::clone(&state.config)
@@ -129,6 +183,12 @@ impl FromRef<AppState> for ConfigFile {
     }
 }
 
+impl FromRef<AppState> for Shares {
+    fn from_ref(state: &AppState) -> Self {
+        Arc::clone(&state.shares)
+    }
+}
+
 impl FromRef<AppState> for Client {
     fn from_ref(state: &AppState) -> Self {
         state.client.clone()
@@ -141,6 +201,12 @@ impl FromRef<AppState> for InsecureSkipVerifyClient {
     }
 }
 
+impl FromRef<AppState> for Reloader {
+    fn from_ref(state: &AppState) -> Self {
+        state.reloader.clone()
+    }
+}
+
 impl tower_service::Service<Request<Body>> for Client {
     type Response = Response<Incoming>;
     type Error = hyper_util::client::legacy::Error;
@@ -0,0 +1,530 @@
+use super::{
+    ScopedAdminToken, password,
+    user::{REDACTED, SkippedLine, User, UserInfo},
+};
+use crate::{
+    appstate::ConfigFile,
+    configuration::{Config, config_or_error},
+    utils::{option_string_trim, random_string, string_trim, vec_trim_remove_empties},
+};
+use axum::{
+    Json,
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+};
+use http::{
+    StatusCode,
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const GENERATED_PASSWORD_LENGTH: usize = 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl BulkFormat {
+    /// CSV for the files with a .csv extension, JSON otherwise
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension() {
+            Some(e) if e.eq_ignore_ascii_case("csv") => BulkFormat::Csv,
+            _ => BulkFormat::Json,
+        }
+    }
+}
+
+/// A user as described in an import or export file.
+/// In CSV files the roles are separated by semicolons (or commas in a quoted field).
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkUser {
+    #[serde(deserialize_with = "string_trim")]
+    pub login: String,
+    #[serde(default, deserialize_with = "string_trim")]
+    pub given_name: String,
+    #[serde(default, deserialize_with = "string_trim")]
+    pub family_name: String,
+    #[serde(default, deserialize_with = "string_trim")]
+    pub email: String,
+    #[serde(default, deserialize_with = "vec_trim_remove_empties")]
+    pub roles: Vec<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "option_string_trim"
+    )]
+    pub password: Option<String>,
+}
+
+/// The CSV flavor of a bulk user, as CSV fields cannot hold lists
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CsvUser {
+    login: String,
+    #[serde(default)]
+    given_name: String,
+    #[serde(default)]
+    family_name: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    roles: String,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+impl From<CsvUser> for BulkUser {
+    fn from(user: CsvUser) -> Self {
+        BulkUser {
+            login: user.login,
+            given_name: user.given_name,
+            family_name: user.family_name,
+            email: user.email,
+            roles: user
+                .roles
+                .split([';', ','])
+                .map(|r| r.trim().to_owned())
+                .filter(|r| !r.is_empty())
+                .collect(),
+            password: user.password.filter(|p| !p.is_empty()),
+        }
+    }
+}
+
+impl From<BulkUser> for CsvUser {
+    fn from(user: BulkUser) -> Self {
+        CsvUser {
+            login: user.login,
+            given_name: user.given_name,
+            family_name: user.family_name,
+            email: user.email,
+            roles: user.roles.join(";"),
+            password: user.password,
+        }
+    }
+}
+
+impl From<&User> for BulkUser {
+    fn from(user: &User) -> Self {
+        let info = user.info.clone().unwrap_or_default();
+        BulkUser {
+            login: user.login.clone(),
+            given_name: info.given_name,
+            family_name: info.family_name,
+            email: info.email,
+            roles: user.roles.clone(),
+            password: Some(REDACTED.to_owned()),
+        }
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct GeneratedPassword {
+    pub login: String,
+    /// Not generated on dry runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct BulkImportReport {
+    pub dry_run: bool,
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    /// Lines of the CSV file, or entries of the JSON array, that were not imported
+    pub skipped: Vec<SkippedLine>,
+    /// Passwords of the created users that had none, to be sent to them
+    pub generated_passwords: Vec<GeneratedPassword>,
+}
+
+/// Parse the users of a CSV (with a header line) or JSON file, giving their line (or entry number) along with them
+pub fn parse_users(
+    content: &str,
+    format: BulkFormat,
+) -> Result<Vec<(usize, Result<BulkUser, String>)>, (StatusCode, &'static str)> {
+    match format {
+        BulkFormat::Json => {
+            let entries = serde_json::from_str::<Vec<serde_json::Value>>(content)
+                .map_err(|_| (StatusCode::BAD_REQUEST, "expected a JSON array of users"))?;
+            Ok(entries
+                .into_iter()
+                .enumerate()
+                .map(|(i, e)| (i + 1, serde_json::from_value(e).map_err(|e| e.to_string())))
+                .collect())
+        }
+        BulkFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .flexible(true)
+                .from_reader(content.as_bytes());
+            let headers = reader
+                .headers()
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        "could not read the CSV header line",
+                    )
+                })?
+                .clone();
+            Ok(reader
+                .records()
+                .enumerate()
+                .map(|(i, record)| {
+                    // Line numbers start at one, after the header line
+                    let line = record
+                        .as_ref()
+                        .ok()
+                        .and_then(|r| r.position())
+                        .map_or(i + 2, |p| usize::try_from(p.line()).unwrap_or(i + 2));
+                    let user = record
+                        .and_then(|r| r.deserialize::<CsvUser>(Some(&headers)))
+                        .map(BulkUser::from)
+                        .map_err(|e| e.to_string());
+                    (line, user)
+                })
+                .collect())
+        }
+    }
+}
+
+/// Create or update the users of the file in the configuration.
+/// Existing users keep their password, their roles and each of their info if none is given,
+/// new users without password get a generated one.
+/// The `allowed` predicate tells if the importer can manage a user.
+pub fn import_users(
+    config: &mut Config,
+    content: &str,
+    format: BulkFormat,
+    dry_run: bool,
+    allowed: impl Fn(&User, &Config) -> bool,
+) -> Result<BulkImportReport, (StatusCode, &'static str)> {
+    let mut report = BulkImportReport {
+        dry_run,
+        ..Default::default()
+    };
+    let mut seen: Vec<String> = Vec::new();
+    for (line, entry) in parse_users(content, format)? {
+        let skip = |reason: &str| SkippedLine {
+            line,
+            reason: reason.to_owned(),
+        };
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(reason) => {
+                report.skipped.push(skip(&reason));
+                continue;
+            }
+        };
+        if entry.login.is_empty() {
+            report.skipped.push(skip("login is required"));
+            continue;
+        }
+        if seen.contains(&entry.login) {
+            report.skipped.push(skip("duplicate login"));
+            continue;
+        }
+        seen.push(entry.login.clone());
+
+        let existing = config.users.iter().position(|u| u.login == entry.login);
+        let mut user = existing
+            .and_then(|pos| config.users.get(pos))
+            .cloned()
+            .unwrap_or_default();
+        let mut info = user.info.take().unwrap_or_default();
+        for (field, value) in [
+            (&mut info.given_name, entry.given_name),
+            (&mut info.family_name, entry.family_name),
+            (&mut info.email, entry.email),
+        ] {
+            if !value.is_empty() {
+                *field = value;
+            }
+        }
+        user.login = entry.login;
+        user.info = (info != UserInfo::default()).then_some(info);
+        if !entry.roles.is_empty() {
+            user.roles = entry.roles;
+        }
+        if !allowed(&user, config)
+            || existing
+                .and_then(|pos| config.users.get(pos))
+                .is_some_and(|u| !allowed(u, config))
+        {
+            report
+                .skipped
+                .push(skip("user is outside of the admin scope"));
+            continue;
+        }
+
+        // A redacted password comes from an export : it is not changed
+        let new_password = entry.password.filter(|p| p != REDACTED);
+        let new_password = match (new_password, existing) {
+            (Some(p), _) => Some(p),
+            (None, Some(_)) => None,
+            (None, None) => {
+                let generated = (!dry_run).then(|| random_string(GENERATED_PASSWORD_LENGTH));
+                report.generated_passwords.push(GeneratedPassword {
+                    login: user.login.clone(),
+                    password: generated.clone(),
+                });
+                generated
+            }
+        };
+        if let Some(new_password) = new_password
+            && !dry_run
+        {
+            user.password = password::hash(&new_password, &config.argon2)
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "password hash failed"))?;
+        }
+
+        match existing.and_then(|pos| config.users.get_mut(pos)) {
+            Some(existing) => {
+                report.updated.push(user.login.clone());
+                *existing = user;
+            }
+            None => {
+                report.created.push(user.login.clone());
+                config.users.push(user);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Write the users in the given format, with their passwords redacted
+pub fn export_users<'a>(
+    users: impl Iterator<Item = &'a User>,
+    format: BulkFormat,
+) -> Result<String, (StatusCode, &'static str)> {
+    let users = users.map(BulkUser::from);
+    match format {
+        BulkFormat::Json => serde_json::to_string_pretty(&users.collect::<Vec<_>>())
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "could not export users")),
+        BulkFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            for user in users {
+                writer
+                    .serialize(CsvUser::from(user))
+                    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "could not export users"))?;
+            }
+            writer
+                .into_inner()
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "could not export users"))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BulkImportQuery {
+    #[serde(default)]
+    format: BulkFormat,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Import users from a CSV or JSON body, only reporting what would be done on dry runs
+pub async fn bulk_import_users(
+    State(config_file): State<ConfigFile>,
+    admin: ScopedAdminToken,
+    Query(params): Query<BulkImportQuery>,
+    body: String,
+) -> Result<Json<BulkImportReport>, (StatusCode, &'static str)> {
+    let mut config = config_or_error(&config_file).await?;
+    let report = import_users(&mut config, &body, params.format, params.dry_run, |u, c| {
+        admin.user_visible(u) && admin.user_allowed(u, c)
+    })?;
+    if !params.dry_run {
+        config
+            .to_file_or_internal_server_error(&config_file)
+            .await?;
+    }
+    Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+pub struct BulkExportQuery {
+    #[serde(default)]
+    format: BulkFormat,
+}
+
+/// Export the users visible by the admin as a CSV or JSON file
+pub async fn bulk_export_users(
+    State(config_file): State<ConfigFile>,
+    admin: ScopedAdminToken,
+    Query(params): Query<BulkExportQuery>,
+) -> Result<Response, (StatusCode, &'static str)> {
+    let config = config_or_error(&config_file).await?;
+    let body = export_users(
+        config.users.iter().filter(|u| admin.user_visible(u)),
+        params.format,
+    )?;
+    let (content_type, filename) = match params.format {
+        BulkFormat::Json => ("application/json", "users.json"),
+        BulkFormat::Csv => ("text/csv; charset=utf-8", "users.csv"),
+    };
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_owned()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let content = "login,given_name,family_name,email,roles,password
+jdoe, John ,Doe,john.doe@atrium.io,\"USERS,STUDENTS\",
+asmith,,,,USERS;TEACHERS,secret
+,missing,login,,,
+";
+        let users = parse_users(content, BulkFormat::Csv).unwrap();
+        assert_eq!(
+            users,
+            vec![
+                (
+                    2,
+                    Ok(BulkUser {
+                        login: "jdoe".to_owned(),
+                        given_name: "John".to_owned(),
+                        family_name: "Doe".to_owned(),
+                        email: "john.doe@atrium.io".to_owned(),
+                        roles: vec!["USERS".to_owned(), "STUDENTS".to_owned()],
+                        password: None,
+                    })
+                ),
+                (
+                    3,
+                    Ok(BulkUser {
+                        login: "asmith".to_owned(),
+                        roles: vec!["USERS".to_owned(), "TEACHERS".to_owned()],
+                        password: Some("secret".to_owned()),
+                        ..Default::default()
+                    })
+                ),
+                (
+                    4,
+                    Ok(BulkUser {
+                        given_name: "missing".to_owned(),
+                        family_name: "login".to_owned(),
+                        ..Default::default()
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_and_export() {
+        let mut config = Config {
+            users: vec![User {
+                login: "jdoe".to_owned(),
+                password: "hash".to_owned(),
+                roles: vec!["USERS".to_owned()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let content = r#"[
+            {"login": "jdoe", "given_name": "John", "roles": ["USERS", "STUDENTS"], "password": "REDACTED"},
+            {"login": "asmith"},
+            {"login": "asmith"},
+            {"login": "admin", "roles": ["ADMINS"]},
+            {"roles": ["USERS"]}
+        ]"#;
+
+        // A dry run reports without changing anything
+        let report = import_users(
+            &mut config.clone(),
+            content,
+            BulkFormat::Json,
+            true,
+            |u, _| !u.roles.contains(&"ADMINS".to_owned()),
+        )
+        .unwrap();
+        assert_eq!(report.created, vec!["asmith"]);
+        assert_eq!(report.updated, vec!["jdoe"]);
+        assert_eq!(
+            report.skipped.iter().map(|s| s.line).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(report.generated_passwords.len(), 1);
+        assert!(
+            report
+                .generated_passwords
+                .iter()
+                .all(|g| g.password.is_none())
+        );
+
+        let report = import_users(&mut config, content, BulkFormat::Json, false, |u, _| {
+            !u.roles.contains(&"ADMINS".to_owned())
+        })
+        .unwrap();
+        assert!(
+            report
+                .generated_passwords
+                .iter()
+                .all(|g| g.password.is_some())
+        );
+        // The redacted password is kept
+        assert!(
+            config
+                .users
+                .iter()
+                .any(|u| u.login == "jdoe" && u.password == "hash")
+        );
+        assert!(
+            config
+                .users
+                .iter()
+                .any(|u| u.login == "asmith" && u.password.starts_with("$argon2"))
+        );
+
+        let csv = export_users(config.users.iter(), BulkFormat::Csv).unwrap();
+        assert_eq!(
+            csv,
+            "login,given_name,family_name,email,roles,password
+jdoe,John,,,USERS;STUDENTS,REDACTED
+asmith,,,,,REDACTED
+"
+        );
+
+        // The empty columns do not erase what the existing users have
+        import_users(
+            &mut config,
+            "login,given_name,family_name,email,roles,password\njdoe,,,jdoe@atrium.io,,\n",
+            BulkFormat::Csv,
+            false,
+            |_, _| true,
+        )
+        .unwrap();
+        let jdoe = config
+            .users
+            .iter()
+            .find(|u| u.login == "jdoe")
+            .expect("imported user");
+        assert_eq!(jdoe.roles, vec!["USERS", "STUDENTS"]);
+        assert_eq!(
+            jdoe.info,
+            Some(UserInfo {
+                given_name: "John".to_owned(),
+                email: "jdoe@atrium.io".to_owned(),
+                ..Default::default()
+            })
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use crate::{
+    appstate::ConfigState,
+    auth::token::{seal, unseal},
+    configuration::Config,
+    errors::ErrResponse,
+    utils::random_string,
+};
+use axum::{
+    Json,
+    extract::{ConnectInfo, Query, State},
+};
+use axum_extra::extract::PrivateCookieJar;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::LazyLock,
+};
+use time::OffsetDateTime;
+
+const CHALLENGE_TOKEN: &str = "ATRIUM_CHALLENGE";
+/// Seconds during which a challenge can be solved and used
+pub const CHALLENGE_VALIDITY: i64 = 300;
+/// Maximum length of a solution, to keep the verification cheap
+const MAX_SOLUTION_LENGTH: usize = 64;
+
+/// Recent login failures by IP and by login, as unix timestamps
+static FAILURES: LazyLock<DashMap<FailureKey, Vec<i64>>> = LazyLock::new(DashMap::new);
+
+/// Nonces of the challenges already used -> expiration
+static USED_CHALLENGES: LazyLock<DashMap<String, i64>> = LazyLock::new(DashMap::new);
+
+#[derive(PartialEq, Eq, Hash)]
+enum FailureKey {
+    Ip(IpAddr),
+    Login(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChallengeToken {
+    nonce: String,
+    difficulty: u32,
+    expires: i64,
+}
+
+#[derive(Deserialize)]
+pub struct ChallengeQuery {
+    #[serde(default)]
+    login: String,
+}
+
+/// A challenge to solve before logging in, `challenge` is None if no proof of work is required
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChallengeResponse {
+    pub challenge: Option<String>,
+    pub difficulty: u32,
+}
+
+/// Record a failed login from `ip` for `login`
+pub(crate) fn report_failure(config: &Config, ip: IpAddr, login: &str, now: i64) {
+    record_failure(
+        config,
+        [
+            FailureKey::Ip(ip.to_canonical()),
+            FailureKey::Login(login.to_owned()),
+        ],
+        now,
+    );
+}
+
+/// Record a failed basic authentication from `ip`, only the IP is blamed as anyone can give the login of someone else
+pub(crate) fn report_ip_failure(config: &Config, ip: IpAddr, now: i64) {
+    record_failure(config, [FailureKey::Ip(ip.to_canonical())], now);
+}
+
+fn record_failure(config: &Config, keys: impl IntoIterator<Item = FailureKey>, now: i64) {
+    if config.login_challenge.is_none() {
+        return;
+    }
+    let find_time = i64::try_from(config.jail.find_time).unwrap_or(i64::MAX);
+    for key in keys {
+        let mut entry = FAILURES.entry(key).or_default();
+        entry.retain(|t| now - t <= find_time);
+        entry.push(now);
+    }
+    // Forget about the quiet IPs and logins
+    FAILURES.retain(|_, failures| failures.last().is_some_and(|t| now - t <= find_time));
+}
+
+/// Forget the failures of a login once it has succeeded, the failures of the IP are kept
+pub(crate) fn report_success(login: &str) {
+    FAILURES.remove(&FailureKey::Login(login.to_owned()));
+}
+
+fn failure_count(config: &Config, key: &FailureKey, now: i64) -> usize {
+    let find_time = i64::try_from(config.jail.find_time).unwrap_or(i64::MAX);
+    FAILURES.get(key).map_or(0, |failures| {
+        failures.iter().filter(|t| now - *t <= find_time).count()
+    })
+}
+
+/// Number of leading zero bits required from the hash of the solution, 0 if no challenge is required
+pub(crate) fn required_difficulty(config: &Config, ip: IpAddr, login: &str, now: i64) -> u32 {
+    difficulty(config, ip, Some(login), now)
+}
+
+/// Whether an IP failed too often to authenticate without solving a challenge, for the clients that cannot solve one.
+/// The failures of the login are not counted, they would let anyone lock a user out.
+pub(crate) fn ip_throttled(config: &Config, ip: IpAddr, now: i64) -> bool {
+    difficulty(config, ip, None, now) > 0
+}
+
+fn difficulty(config: &Config, ip: IpAddr, login: Option<&str>, now: i64) -> u32 {
+    let Some(challenge_config) = &config.login_challenge else {
+        return 0;
+    };
+    let ip = ip.to_canonical();
+    if config.jail.whitelist.contains(&ip) {
+        return 0;
+    }
+    let failures = failure_count(config, &FailureKey::Ip(ip), now).max(login.map_or(0, |login| {
+        failure_count(config, &FailureKey::Login(login.to_owned()), now)
+    }));
+    let failures = u32::try_from(failures).unwrap_or(u32::MAX);
+    if failures < challenge_config.after_failures {
+        return 0;
+    }
+    challenge_config
+        .difficulty
+        .saturating_add(failures - challenge_config.after_failures)
+        .min(challenge_config.max_difficulty)
+        .max(1)
+}
+
+fn leading_zero_bits(challenge: &str, solution: &str) -> u32 {
+    let digest: [u8; 32] = Sha256::digest(format!("{challenge}:{solution}").as_bytes()).into();
+    let mut bits = 0;
+    for byte in digest {
+        bits += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Find a solution to a challenge, as a client would do
+pub fn solve(challenge: &str, difficulty: u32) -> String {
+    (0u64..)
+        .map(|n| n.to_string())
+        .find(|solution| leading_zero_bits(challenge, solution) >= difficulty)
+        .unwrap_or_default()
+}
+
+/// Check that the solution of a challenge of at least `difficulty` is right, the challenge cannot be used again
+pub(crate) fn verify(
+    jar: &PrivateCookieJar,
+    challenge: Option<&str>,
+    solution: Option<&str>,
+    difficulty: u32,
+    now: i64,
+) -> bool {
+    let (Some(challenge), Some(solution)) = (challenge, solution) else {
+        return false;
+    };
+    let Some(token) = unseal::<ChallengeToken>(jar, &[], CHALLENGE_TOKEN, challenge) else {
+        return false;
+    };
+    if token.expires < now
+        || token.difficulty < difficulty
+        || solution.len() > MAX_SOLUTION_LENGTH
+        || leading_zero_bits(challenge, solution) < token.difficulty
+    {
+        return false;
+    }
+    USED_CHALLENGES.retain(|_, expires| *expires >= now);
+    USED_CHALLENGES.insert(token.nonce, token.expires).is_none()
+}
+
+/// Give the challenge to solve before logging in as `login` from this IP
+pub async fn login_challenge(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    jar: PrivateCookieJar,
+    State(config): State<ConfigState>,
+    Query(query): Query<ChallengeQuery>,
+) -> Result<Json<ChallengeResponse>, ErrResponse> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let difficulty = required_difficulty(&config, addr.ip(), &query.login, now);
+    if difficulty == 0 {
+        return Ok(Json(ChallengeResponse {
+            challenge: None,
+            difficulty,
+        }));
+    }
+    let challenge = seal(
+        jar,
+        CHALLENGE_TOKEN,
+        &ChallengeToken {
+            nonce: random_string(32),
+            difficulty,
+            expires: now + CHALLENGE_VALIDITY,
+        },
+    )?;
+    Ok(Json(ChallengeResponse {
+        challenge: Some(challenge),
+        difficulty,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::LoginChallengeConfig;
+    use axum_extra::extract::cookie::Key;
+    use http::HeaderMap;
+
+    fn config() -> Config {
+        Config {
+            login_challenge: Some(LoginChallengeConfig {
+                after_failures: 2,
+                difficulty: 4,
+                max_difficulty: 6,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_required_difficulty() {
+        let config = config();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let login = "test_required_difficulty";
+        assert_eq!(required_difficulty(&config, ip, login, 1000), 0);
+        report_failure(&config, ip, login, 1000);
+        assert_eq!(required_difficulty(&config, ip, login, 1000), 0);
+        report_failure(&config, ip, login, 1000);
+        assert_eq!(required_difficulty(&config, ip, login, 1000), 4);
+        // The difficulty grows with the failures, up to the maximum
+        for _ in 0..5 {
+            report_failure(&config, ip, login, 1000);
+        }
+        assert_eq!(required_difficulty(&config, ip, login, 1000), 6);
+        // The login failures are forgotten after a success, but not the IP ones
+        report_success(login);
+        assert_eq!(required_difficulty(&config, ip, "other", 1000), 6);
+        let other_ip: IpAddr = "203.0.113.8".parse().unwrap();
+        assert_eq!(required_difficulty(&config, other_ip, login, 1000), 0);
+        // The failures are only counted during the jail find time
+        assert_eq!(
+            required_difficulty(&config, ip, login, 1000 + 1 + config.jail.find_time as i64),
+            0
+        );
+        // No challenge without configuration
+        assert_eq!(required_difficulty(&Config::default(), ip, login, 1000), 0);
+    }
+
+    #[test]
+    fn test_ip_throttled() {
+        let config = config();
+        let ip: IpAddr = "203.0.113.9".parse().unwrap();
+        let login = "test_ip_throttled";
+        // The failures for a login from elsewhere do not throttle the basic authentication of the user
+        for _ in 0..3 {
+            report_failure(&config, "203.0.113.10".parse().unwrap(), login, 1000);
+        }
+        assert!(required_difficulty(&config, ip, login, 1000) > 0);
+        assert!(!ip_throttled(&config, ip, 1000));
+        report_ip_failure(&config, ip, 1000);
+        assert!(!ip_throttled(&config, ip, 1000));
+        report_ip_failure(&config, ip, 1000);
+        assert!(ip_throttled(&config, ip, 1000));
+        assert_eq!(required_difficulty(&config, ip, "other", 1000), 4);
+    }
+
+    #[test]
+    fn test_verify() {
+        let jar = PrivateCookieJar::from_headers(&HeaderMap::new(), Key::generate());
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let challenge = seal(
+            jar.clone(),
+            CHALLENGE_TOKEN,
+            &ChallengeToken {
+                nonce: random_string(32),
+                difficulty: 8,
+                expires: now + CHALLENGE_VALIDITY,
+            },
+        )
+        .unwrap();
+        let solution = solve(&challenge, 8);
+        assert!(leading_zero_bits(&challenge, &solution) >= 8);
+        // The challenge must be at least as hard as required
+        assert!(!verify(&jar, Some(&challenge), Some(&solution), 9, now));
+        assert!(!verify(&jar, Some(&challenge), None, 8, now));
+        assert!(!verify(
+            &jar,
+            Some(&challenge),
+            Some(&solution),
+            8,
+            now + CHALLENGE_VALIDITY + 1
+        ));
+        assert!(verify(&jar, Some(&challenge), Some(&solution), 8, now));
+        // A challenge can only be used once
+        assert!(!verify(&jar, Some(&challenge), Some(&solution), 8, now));
+    }
+}
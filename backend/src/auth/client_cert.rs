@@ -0,0 +1,312 @@
+use crate::{
+    auth::{
+        User,
+        roles::resolve_roles,
+        user::{UserToken, user_to_token},
+    },
+    configuration::{ClientCertConfig, Config},
+    errors::Error,
+    utils::{is_default, string_trim, vec_trim_remove_empties},
+};
+use axum_server::accept::Accept;
+use futures::{TryFutureExt, future::MapOk};
+use http::Request;
+use rustls::{
+    ConfigBuilder, RootCertStore, ServerConfig,
+    server::{WantsServerCert, WebPkiClientVerifier, danger::ClientCertVerifier},
+};
+use rustls_pki_types::{CertificateDer, pem::PemObject};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+use time::OffsetDateTime;
+use tokio_util::compat::Compat;
+use tower_service::Service;
+use tracing::error;
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+/// Identity given to the clients presenting a certificate whose subject common name or one of the alternative names (DNS, email or URI) is `name`
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct ClientCertIdentity {
+    #[serde(deserialize_with = "string_trim")]
+    pub name: String,
+    /// Local user the client is authenticated as, its roles are added to the roles below
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "string_trim"
+    )]
+    pub login: String,
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "vec_trim_remove_empties"
+    )]
+    pub roles: Vec<String>,
+}
+
+/// The names of the verified certificate presented by the client, inserted in the request extensions
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    pub names: Vec<String>,
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let mut names: Vec<String> = cert
+            .subject()
+            .iter_common_name()
+            .filter_map(|cn| cn.as_str().ok())
+            .map(str::to_owned)
+            .collect();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                if let GeneralName::DNSName(n) | GeneralName::RFC822Name(n) | GeneralName::URI(n) =
+                    name
+                {
+                    names.push((*n).to_owned());
+                }
+            }
+        }
+        Some(Self { names })
+    }
+
+    /// Create a session for the first identity of the configuration matching the certificate
+    pub(crate) fn to_user_token(&self, config: &Config) -> Option<UserToken> {
+        let identity = config
+            .client_cert_config
+            .as_ref()?
+            .identities
+            .iter()
+            .find(|i| self.names.iter().any(|n| n.eq_ignore_ascii_case(&i.name)))?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut token = if identity.login.is_empty() {
+            user_to_token(
+                &User {
+                    login: identity.name.clone(),
+                    ..Default::default()
+                },
+                config,
+            )
+        } else {
+            let user = config
+                .users
+                .iter()
+                .find(|u| u.login == identity.login && u.is_active(now))?;
+            user_to_token(user, config)
+        };
+        for role in resolve_roles(&token.login, &identity.roles, config) {
+            if !token.roles.contains(&role) {
+                token.roles.push(role);
+            }
+        }
+        // Like with basic auth, the certificate is sent with every request and no xsrf token is needed
+        token.xsrf_token = None;
+        Some(token)
+    }
+}
+
+/// Build the rustls server configuration, verifying the client certificates against the configured authorities if any.
+/// The clients without certificate are still accepted, the apps requiring one reject them.
+pub fn server_config_builder(
+    config: &Config,
+) -> Result<ConfigBuilder<ServerConfig, WantsServerCert>, Error> {
+    let builder = ServerConfig::builder();
+    Ok(match &config.client_cert_config {
+        Some(client_cert_config) => {
+            builder.with_client_cert_verifier(client_cert_verifier(client_cert_config)?)
+        }
+        None => builder.with_no_client_auth(),
+    })
+}
+
+fn client_cert_verifier(config: &ClientCertConfig) -> Result<Arc<dyn ClientCertVerifier>, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(&config.ca_file).map_err(|e| {
+        error!("could not read client certificate authorities: {e}");
+        Error("could not read client certificate authorities")
+    })? {
+        let cert = cert.map_err(|e| {
+            error!("could not parse client certificate authority: {e}");
+            Error("could not parse client certificate authority")
+        })?;
+        roots.add(cert).map_err(|e| {
+            error!("invalid client certificate authority: {e}");
+            Error("invalid client certificate authority")
+        })?;
+    }
+    WebPkiClientVerifier::builder(Arc::new(roots))
+        .allow_unauthenticated()
+        .build()
+        .map_err(|e| {
+            error!("could not build client certificate verifier: {e}");
+            Error("could not build client certificate verifier")
+        })
+}
+
+/// TLS streams giving access to the certificates presented by the client
+pub trait PeerCertificates {
+    fn peer_certificates(&self) -> Option<&[CertificateDer<'static>]>;
+}
+
+impl<I> PeerCertificates for tokio_rustls::server::TlsStream<I> {
+    fn peer_certificates(&self) -> Option<&[CertificateDer<'static>]> {
+        self.get_ref().1.peer_certificates()
+    }
+}
+
+/// The streams of the let's encrypt acceptor
+impl<I> PeerCertificates for Compat<rustls_acme::futures_rustls::server::TlsStream<I>> {
+    fn peer_certificates(&self) -> Option<&[CertificateDer<'static>]> {
+        self.get_ref().get_ref().1.peer_certificates()
+    }
+}
+
+/// Acceptor wrapping a TLS acceptor to make the client certificate available to the requests of the connection
+#[derive(Debug, Clone)]
+pub struct ClientCertAcceptor<A> {
+    inner: A,
+}
+
+impl<A> ClientCertAcceptor<A> {
+    pub fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+type WithCertificate<T, S> = fn((T, S)) -> (T, ClientCertService<S>);
+
+fn with_certificate<T: PeerCertificates, S>(
+    (stream, service): (T, S),
+) -> (T, ClientCertService<S>) {
+    let certificate = stream
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(|cert| ClientCertificate::from_der(cert.as_ref()));
+    (
+        stream,
+        ClientCertService {
+            inner: service,
+            certificate,
+        },
+    )
+}
+
+impl<A, I, S> Accept<I, S> for ClientCertAcceptor<A>
+where
+    A: Accept<I, S>,
+    A::Stream: PeerCertificates,
+{
+    type Stream = A::Stream;
+    type Service = ClientCertService<A::Service>;
+    type Future = MapOk<A::Future, WithCertificate<A::Stream, A::Service>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        self.inner
+            .accept(stream, service)
+            .map_ok(with_certificate as WithCertificate<A::Stream, A::Service>)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientCertService<S> {
+    inner: S,
+    certificate: Option<ClientCertificate>,
+}
+
+impl<S, B> Service<Request<B>> for ClientCertService<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        if let Some(certificate) = &self.certificate {
+            req.extensions_mut().insert(certificate.clone());
+        }
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            client_cert_config: Some(ClientCertConfig {
+                ca_file: "ca.pem".to_owned(),
+                identities: vec![
+                    ClientCertIdentity {
+                        name: "kiosk.atrium.io".to_owned(),
+                        roles: vec!["KIOSKS".to_owned()],
+                        ..Default::default()
+                    },
+                    ClientCertIdentity {
+                        name: "backup@atrium.io".to_owned(),
+                        login: "backup".to_owned(),
+                        roles: vec!["BACKUPS".to_owned()],
+                    },
+                ],
+            }),
+            users: vec![User {
+                login: "backup".to_owned(),
+                roles: vec!["USERS".to_owned()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_role_identity() {
+        let certificate = ClientCertificate {
+            names: vec!["Kiosk 1".to_owned(), "KIOSK.atrium.io".to_owned()],
+        };
+        let token = certificate.to_user_token(&config()).unwrap();
+        assert_eq!(token.login, "kiosk.atrium.io");
+        assert_eq!(token.roles, vec!["KIOSKS".to_owned()]);
+        assert_eq!(token.xsrf_token, None);
+    }
+
+    #[test]
+    fn test_user_identity() {
+        let certificate = ClientCertificate {
+            names: vec!["backup@atrium.io".to_owned()],
+        };
+        let token = certificate.to_user_token(&config()).unwrap();
+        assert_eq!(token.login, "backup");
+        assert_eq!(token.roles, vec!["USERS".to_owned(), "BACKUPS".to_owned()]);
+        // An expired user cannot be authenticated with a certificate
+        let mut config = config();
+        if let Some(user) = config.users.first_mut() {
+            user.expires_at = Some(0);
+        }
+        assert_eq!(certificate.to_user_token(&config), None);
+    }
+
+    #[test]
+    fn test_unknown_certificate() {
+        let certificate = ClientCertificate {
+            names: vec!["other.atrium.io".to_owned()],
+        };
+        assert_eq!(certificate.to_user_token(&config()), None);
+    }
+
+    #[cfg(feature = "self_signed")]
+    #[test]
+    fn test_certificate_names() {
+        let cert = rcgen::generate_simple_self_signed(vec!["kiosk.atrium.io".to_owned()]).unwrap();
+        let certificate = ClientCertificate::from_der(cert.cert.der()).unwrap();
+        assert!(certificate.names.contains(&"kiosk.atrium.io".to_owned()));
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{
+    appstate::{ConfigFile, ConfigState, PreviousCookieKeys, Reloader},
+    auth::{AUTH_COOKIE, AdminToken, UserToken},
+    configuration::{Config, PreviousCookieKey, config_or_error},
+    utils::random_string,
+};
+use axum::{
+    extract::{Query, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::{
+    PrivateCookieJar,
+    cookie::{Cookie, Key, SameSite},
+};
+use http::{
+    HeaderMap, HeaderValue, StatusCode,
+    header::{COOKIE, SET_COOKIE},
+};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+/// Days during which the previous key is still accepted after a rotation, if not given
+pub const DEFAULT_GRACE_DAYS: i64 = 30;
+
+/// A former cookie key, still accepted to decrypt until it expires
+#[derive(Clone)]
+pub struct PreviousKey {
+    key: Key,
+    expires_at: i64,
+}
+
+/// Load the previous keys of the configuration that are not expired
+pub(crate) fn previous_keys(config: &Config, now: i64) -> Vec<PreviousKey> {
+    config
+        .previous_cookie_keys
+        .iter()
+        .filter(|k| k.expires_at > now)
+        .filter_map(|k| {
+            Key::try_from(k.key.as_bytes()).ok().map(|key| PreviousKey {
+                key,
+                expires_at: k.expires_at,
+            })
+        })
+        .collect()
+}
+
+/// Decrypt a cookie with the current key of the jar, or with one of the previous keys
+pub(crate) fn decrypt(
+    jar: &PrivateCookieJar,
+    previous_keys: &[PreviousKey],
+    cookie: Cookie<'static>,
+) -> Option<Cookie<'static>> {
+    jar.decrypt(cookie.clone())
+        .or_else(|| decrypt_with_previous_keys(previous_keys, cookie))
+}
+
+fn decrypt_with_previous_keys(
+    previous_keys: &[PreviousKey],
+    cookie: Cookie<'static>,
+) -> Option<Cookie<'static>> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    previous_keys
+        .iter()
+        .filter(|k| k.expires_at > now)
+        .find_map(|k| {
+            PrivateCookieJar::from_headers(&HeaderMap::new(), k.key.clone()).decrypt(cookie.clone())
+        })
+}
+
+/// Make a new key the current one, the current key is kept for `grace_days` and the expired ones are dropped
+pub(crate) fn rotate(config: &mut Config, grace_days: i64, now: i64) {
+    config.previous_cookie_keys.retain(|k| k.expires_at > now);
+    if let Some(key) = config.cookie_key.replace(random_string(64)) {
+        config.previous_cookie_keys.insert(
+            0,
+            PreviousCookieKey {
+                key,
+                expires_at: now + grace_days.max(0) * 24 * 3600,
+            },
+        );
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RotateQuery {
+    grace_days: Option<i64>,
+}
+
+pub async fn rotate_cookie_key(
+    State(config_file): State<ConfigFile>,
+    State(reloader): State<Reloader>,
+    admin: AdminToken,
+    Query(query): Query<RotateQuery>,
+) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
+    let mut config = config_or_error(&config_file).await?;
+    rotate(
+        &mut config,
+        query.grace_days.unwrap_or(DEFAULT_GRACE_DAYS),
+        OffsetDateTime::now_utc().unix_timestamp(),
+    );
+    config
+        .to_file_or_internal_server_error(&config_file)
+        .await?;
+    info!("COOKIE KEY ROTATED by {}", admin.0.login);
+    // The new key is used once the configuration is reloaded
+    reloader.schedule();
+    Ok((StatusCode::OK, "cookie key rotated successfully"))
+}
+
+/// Re-encrypt with the current key a session cookie encrypted with a previous key,
+/// so that the request is authenticated and the browser gets the new cookie
+pub async fn reencrypt_cookie_middleware(
+    State(config): State<ConfigState>,
+    State(previous_keys): State<PreviousCookieKeys>,
+    jar: PrivateCookieJar,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if previous_keys.is_empty() || jar.get(AUTH_COOKIE).is_some() {
+        return next.run(req).await;
+    }
+    let Some(set_cookie) = reencrypt_auth_cookie(&config, &previous_keys, jar, &mut req) else {
+        return next.run(req).await;
+    };
+    let mut res = next.run(req).await;
+    // Do not override a session cookie set by the handler (login, logout...)
+    let auth_cookie_prefix = format!("{AUTH_COOKIE}=");
+    if !res
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .any(|h| h.as_bytes().starts_with(auth_cookie_prefix.as_bytes()))
+    {
+        res.headers_mut().append(SET_COOKIE, set_cookie);
+    }
+    res
+}
+
+/// Replace the session cookie of the request by its re-encrypted version, and give the header to set it in the browser
+fn reencrypt_auth_cookie(
+    config: &Config,
+    previous_keys: &[PreviousKey],
+    jar: PrivateCookieJar,
+    req: &mut Request,
+) -> Option<HeaderValue> {
+    let cookie = axum_extra::extract::CookieJar::from_headers(req.headers())
+        .get(AUTH_COOKIE)?
+        .clone();
+    let decrypted = decrypt_with_previous_keys(previous_keys, cookie)?;
+    let user_token = UserToken::from_json(decrypted.value()).ok()?;
+    let max_age = user_token.expires - OffsetDateTime::now_utc().unix_timestamp();
+    let cookie = Cookie::build((AUTH_COOKIE, decrypted.value().to_owned()))
+        .domain(config.hostname.clone())
+        .path("/")
+        .same_site(SameSite::Lax)
+        .secure(config.tls_mode.is_secure())
+        .max_age(Duration::seconds(max_age))
+        .http_only(true)
+        .build();
+    let response = jar.add(cookie).into_response();
+    let set_cookie = response.headers().get(SET_COOKIE)?.clone();
+    let (_, encrypted) = set_cookie
+        .to_str()
+        .ok()?
+        .split(';')
+        .next()?
+        .split_once('=')?;
+    let encrypted = encrypted.to_owned();
+
+    // Rewrite the cookie in the request headers
+    let headers = req.headers_mut();
+    let rewritten: Vec<HeaderValue> = headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .map(|h| {
+            h.split(';')
+                .map(|c| match c.trim().split_once('=') {
+                    Some((name, _)) if name == AUTH_COOKIE => format!("{AUTH_COOKIE}={encrypted}"),
+                    _ => c.trim().to_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join("; ")
+        })
+        .filter_map(|h| HeaderValue::from_str(&h).ok())
+        .collect();
+    headers.remove(COOKIE);
+    for h in rewritten {
+        headers.append(COOKIE, h);
+    }
+    Some(set_cookie)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        let mut config = Config {
+            cookie_key: Some(random_string(64)),
+            previous_cookie_keys: vec![PreviousCookieKey {
+                key: random_string(64),
+                expires_at: 50,
+            }],
+            ..Default::default()
+        };
+        let old_key = config.cookie_key.clone().unwrap();
+        rotate(&mut config, 1, 100);
+        assert_ne!(config.cookie_key, Some(old_key.clone()));
+        // The expired key is dropped and the former current key is kept for the grace period
+        assert_eq!(
+            config.previous_cookie_keys,
+            vec![PreviousCookieKey {
+                key: old_key,
+                expires_at: 100 + 24 * 3600,
+            }]
+        );
+        assert_eq!(previous_keys(&config, 100).len(), 1);
+        assert_eq!(previous_keys(&config, 100 + 24 * 3600).len(), 0);
+    }
+
+    #[test]
+    fn test_decrypt_with_previous_key() {
+        let old_key = random_string(64);
+        let mut config = Config {
+            cookie_key: Some(old_key.clone()),
+            ..Default::default()
+        };
+        let old_jar =
+            PrivateCookieJar::from_headers(&HeaderMap::new(), Key::from(old_key.as_bytes()));
+        let encrypted = old_jar
+            .add(Cookie::new("TEST", "value"))
+            .into_response()
+            .headers()
+            .get(SET_COOKIE)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split(';').next())
+            .map(str::to_owned)
+            .unwrap();
+        let cookie = Cookie::parse_encoded(encrypted).unwrap();
+
+        rotate(&mut config, 1, OffsetDateTime::now_utc().unix_timestamp());
+        let new_jar = PrivateCookieJar::from_headers(
+            &HeaderMap::new(),
+            Key::from(config.cookie_key.as_ref().unwrap().as_bytes()),
+        );
+        assert_eq!(new_jar.decrypt(cookie.clone()), None);
+        let previous = previous_keys(&config, OffsetDateTime::now_utc().unix_timestamp());
+        assert_eq!(
+            decrypt(&new_jar, &previous, cookie.clone()).map(|c| c.value().to_owned()),
+            Some("value".to_owned())
+        );
+        // After the grace period, the previous key is not accepted anymore
+        let previous = previous_keys(
+            &config,
+            OffsetDateTime::now_utc().unix_timestamp() + 24 * 3600 + 1,
+        );
+        assert_eq!(decrypt(&new_jar, &previous, cookie), None);
+    }
+}
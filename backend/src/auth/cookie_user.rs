@@ -1,7 +1,7 @@
 use std::convert::Infallible;
 
 use super::user::{AUTH_COOKIE, UserToken};
-use crate::appstate::ConfigState;
+use crate::appstate::{ConfigState, Shares};
 use axum::{
     extract::{FromRef, FromRequestParts, OptionalFromRequestParts},
     response::{IntoResponse, Response},
@@ -17,6 +17,7 @@ where
     S: Send + Sync,
     Key: FromRef<S>,
     ConfigState: FromRef<S>,
+    Shares: FromRef<S>,
     crate::OptionalJail: FromRef<S>,
 {
     type Rejection = Response;
@@ -30,6 +31,8 @@ where
         if let Some(cookie) = jar.get(AUTH_COOKIE) {
             let serialized_user_token = cookie.value();
             let user_token = UserToken::from_json(serialized_user_token)
+                .and_then(|t| t.check_not_revoked(&ConfigState::from_ref(state)))
+                .and_then(|t| t.check_share(&Shares::from_ref(state)))
                 .map_err(|e| (e.0, e.1).into_response())?;
             return Ok(CookieUserToken(user_token));
         }
@@ -43,6 +46,7 @@ where
     S: Send + Sync,
     Key: FromRef<S>,
     ConfigState: FromRef<S>,
+    Shares: FromRef<S>,
     crate::OptionalJail: FromRef<S>,
 {
     type Rejection = Infallible;
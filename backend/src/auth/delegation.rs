@@ -0,0 +1,280 @@
+use super::{
+    roles::resolve_roles,
+    user::{ADMINS_ROLE, User, UserToken},
+};
+use crate::{
+    apps::App,
+    appstate::{ConfigFile, ConfigState, PreviousCookieKeys, Shares},
+    configuration::Config,
+    davs::model::Dav,
+    utils::{is_default, string_trim, vec_trim_remove_empties},
+};
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::Key;
+use http::{StatusCode, request::Parts};
+use serde::{Deserialize, Serialize};
+
+/// Gives the holders of a role the administration of a subset of the apps and davs,
+/// and of the users having the roles listed in the scope
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminScope {
+    #[serde(deserialize_with = "string_trim")]
+    pub role: String,
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "vec_trim_remove_empties"
+    )]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub apps: Vec<usize>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub davs: Vec<usize>,
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "vec_trim_remove_empties"
+    )]
+    pub roles: Vec<String>,
+}
+
+impl AdminScope {
+    /// Merge every scope given by the roles of the user
+    fn for_roles(user_roles: &[String], config: &Config) -> Option<Self> {
+        let mut scopes = config
+            .admin_scopes
+            .iter()
+            .filter(|s| user_roles.contains(&s.role))
+            .peekable();
+        scopes.peek()?;
+        Some(scopes.fold(AdminScope::default(), |mut acc, s| {
+            acc.tags.extend(s.tags.iter().cloned());
+            acc.apps.extend(s.apps.iter().copied());
+            acc.davs.extend(s.davs.iter().copied());
+            // The admin role can never be delegated
+            acc.roles.extend(
+                s.roles
+                    .iter()
+                    .filter(|r| r.as_str() != ADMINS_ROLE)
+                    .cloned(),
+            );
+            acc
+        }))
+    }
+
+    fn has_tag(&self, tags: &[String]) -> bool {
+        tags.iter().any(|t| self.tags.contains(t))
+    }
+
+    fn allows_roles(&self, roles: &[String]) -> bool {
+        roles.iter().all(|r| self.roles.contains(r))
+    }
+
+    /// Opening a service to the admins too does not give anything more to the delegated admin
+    fn allows_service_roles(&self, roles: &[String]) -> bool {
+        roles
+            .iter()
+            .all(|r| r == ADMINS_ROLE || self.roles.contains(r))
+    }
+
+    pub fn manages_app(&self, app: &App) -> bool {
+        (self.apps.contains(&app.id) || self.has_tag(&app.tags))
+            && self.allows_service_roles(&app.roles)
+    }
+
+    pub fn manages_dav(&self, dav: &Dav) -> bool {
+        (self.davs.contains(&dav.id) || self.has_tag(&dav.tags))
+            && self.allows_service_roles(&dav.roles)
+    }
+
+    /// A user is visible if it holds one of the delegated roles
+    pub fn sees_user(&self, user: &User) -> bool {
+        user.roles.iter().any(|r| self.roles.contains(r))
+    }
+
+    /// A user is managed if all its roles, including the ones given by groups and the role hierarchy,
+    /// can be obtained from the delegated roles
+    pub fn manages_user(&self, user: &User, config: &Config) -> bool {
+        let reachable = resolve_roles("", &self.roles, config);
+        self.allows_roles(&user.roles)
+            && resolve_roles(&user.login, &user.roles, config)
+                .iter()
+                .all(|r| reachable.contains(r))
+    }
+}
+
+/// An administrator, either global (no scope) or delegated to a scope
+#[derive(Clone)]
+pub struct ScopedAdminToken(pub(crate) UserToken, pub(crate) Option<AdminScope>);
+
+impl ScopedAdminToken {
+    pub fn app_allowed(&self, app: &App) -> bool {
+        self.1.as_ref().is_none_or(|s| s.manages_app(app))
+    }
+
+    pub fn dav_allowed(&self, dav: &Dav) -> bool {
+        self.1.as_ref().is_none_or(|s| s.manages_dav(dav))
+    }
+
+    pub fn user_visible(&self, user: &User) -> bool {
+        self.1.as_ref().is_none_or(|s| s.sees_user(user))
+    }
+
+    pub fn user_allowed(&self, user: &User, config: &Config) -> bool {
+        self.1.as_ref().is_none_or(|s| s.manages_user(user, config))
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.1.is_none()
+    }
+}
+
+/// Tells if the given effective roles give access to the administration, globally or delegated
+pub fn can_administrate(roles: &[String], config: &Config) -> bool {
+    roles.contains(&ADMINS_ROLE.to_owned()) || AdminScope::for_roles(roles, config).is_some()
+}
+
+impl<S> FromRequestParts<S> for ScopedAdminToken
+where
+    S: Send + Sync,
+    Key: FromRef<S>,
+    ConfigState: FromRef<S>,
+    ConfigFile: FromRef<S>,
+    PreviousCookieKeys: FromRef<S>,
+    Shares: FromRef<S>,
+    crate::OptionalJail: FromRef<S>,
+{
+    type Rejection = Response;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = <UserToken as FromRequestParts<S>>::from_request_parts(parts, state).await?;
+        if user.share.is_some() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "share token cannot be used to access admin API",
+            )
+                .into_response());
+        }
+        if user.impersonator.is_some() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "impersonated session cannot be used to access admin API",
+            )
+                .into_response());
+        }
+        if user.roles.contains(&ADMINS_ROLE.to_owned()) {
+            return Ok(ScopedAdminToken(user, None));
+        }
+        let config = ConfigState::from_ref(state);
+        match AdminScope::for_roles(&user.roles, &config) {
+            Some(scope) => Ok(ScopedAdminToken(user, Some(scope))),
+            None => Err((StatusCode::UNAUTHORIZED, "user is not in admin group").into_response()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::roles::Group;
+
+    fn config() -> Config {
+        Config {
+            admin_scopes: vec![
+                AdminScope {
+                    role: "TEAM_A_ADMINS".to_owned(),
+                    tags: vec!["team-a".to_owned()],
+                    apps: vec![2],
+                    roles: vec!["TEAM_A".to_owned(), ADMINS_ROLE.to_owned()],
+                    ..Default::default()
+                },
+                AdminScope {
+                    role: "FILES_ADMINS".to_owned(),
+                    davs: vec![1],
+                    roles: vec!["FILES".to_owned()],
+                    ..Default::default()
+                },
+            ],
+            groups: vec![Group {
+                name: "admins".to_owned(),
+                members: vec!["boss".to_owned()],
+                roles: vec![ADMINS_ROLE.to_owned()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_scope_merge() {
+        let config = config();
+        assert!(AdminScope::for_roles(&["USERS".to_owned()], &config).is_none());
+        let scope = AdminScope::for_roles(
+            &["TEAM_A_ADMINS".to_owned(), "FILES_ADMINS".to_owned()],
+            &config,
+        )
+        .unwrap();
+        assert_eq!(scope.apps, vec![2]);
+        assert_eq!(scope.davs, vec![1]);
+        // The admin role is never delegated
+        assert_eq!(scope.roles, vec!["TEAM_A".to_owned(), "FILES".to_owned()]);
+    }
+
+    #[test]
+    fn test_scope_apps_and_davs() {
+        let config = config();
+        let scope = AdminScope::for_roles(&["TEAM_A_ADMINS".to_owned()], &config).unwrap();
+        let tagged = App {
+            id: 5,
+            tags: vec!["team-a".to_owned()],
+            roles: vec!["TEAM_A".to_owned()],
+            ..Default::default()
+        };
+        assert!(scope.manages_app(&tagged));
+        assert!(scope.manages_app(&App {
+            id: 2,
+            roles: vec![ADMINS_ROLE.to_owned(), "TEAM_A".to_owned()],
+            ..Default::default()
+        }));
+        assert!(!scope.manages_app(&App {
+            id: 3,
+            ..Default::default()
+        }));
+        // Roles outside of the scope cannot be given to a managed app
+        assert!(!scope.manages_app(&App {
+            roles: vec!["OTHERS".to_owned()],
+            ..tagged
+        }));
+        assert!(!scope.manages_dav(&Dav {
+            id: 1,
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_scope_users() {
+        let config = config();
+        let scope = AdminScope::for_roles(&["TEAM_A_ADMINS".to_owned()], &config).unwrap();
+        let member = User {
+            login: "member".to_owned(),
+            roles: vec!["TEAM_A".to_owned()],
+            ..Default::default()
+        };
+        assert!(scope.sees_user(&member));
+        assert!(scope.manages_user(&member, &config));
+        let shared = User {
+            roles: vec!["TEAM_A".to_owned(), "OTHERS".to_owned()],
+            ..member.clone()
+        };
+        assert!(scope.sees_user(&shared));
+        assert!(!scope.manages_user(&shared, &config));
+        // A user getting more roles through a group is not managed
+        let boss = User {
+            login: "boss".to_owned(),
+            ..member
+        };
+        assert!(!scope.manages_user(&boss, &config));
+    }
+}
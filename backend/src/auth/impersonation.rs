@@ -0,0 +1,156 @@
+use crate::{
+    appstate::{ConfigState, MAXMIND_READER},
+    auth::{
+        AUTH_COOKIE, AuthResponse, ScopedAdminToken, UserToken, delegation::can_administrate,
+        user::session_cookie, user_to_token,
+    },
+    extract::Host,
+    logger::city_from_ip,
+};
+use axum::{
+    Json,
+    extract::{ConnectInfo, Path, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use axum_extra::extract::PrivateCookieJar;
+use http::{StatusCode, header::HOST};
+use std::net::SocketAddr;
+use time::OffsetDateTime;
+use tracing::info;
+
+/// Maximum duration of an impersonated session, in seconds
+pub const IMPERSONATION_DURATION: i64 = 3600;
+
+/// Open a session as another user for support, the admin session is kept inside to be restored afterwards.
+/// The impersonated session cannot access the admin API.
+pub async fn impersonate(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    jar: PrivateCookieJar,
+    State(config): State<ConfigState>,
+    admin: ScopedAdminToken,
+    host: Host,
+    Path(user_login): Path<String>,
+) -> Result<(PrivateCookieJar, Json<AuthResponse>), (StatusCode, &'static str)> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let user = config
+        .users
+        .iter()
+        .find(|u| u.login == user_login && admin.user_allowed(u, &config))
+        .ok_or((StatusCode::BAD_REQUEST, "user does not exist"))?;
+    if !user.is_active(now) {
+        return Err((StatusCode::BAD_REQUEST, "user account has expired"));
+    }
+    if user.login == admin.0.login {
+        return Err((StatusCode::BAD_REQUEST, "cannot impersonate yourself"));
+    }
+    let mut user_token = user_to_token(user, &config);
+    // The impersonated session never outlives the admin session
+    user_token.expires = user_token
+        .expires
+        .min(now + IMPERSONATION_DURATION)
+        .min(admin.0.expires);
+    user_token.impersonator = Some(Box::new(admin.0));
+    let cookie = session_cookie(&user_token, &host, &config)?;
+    info!(
+        "IMPERSONATION START of {} by {} from {}",
+        user.login,
+        impersonator_login(&user_token),
+        city_from_ip(addr, MAXMIND_READER.get())
+    );
+    Ok((
+        jar.add(cookie),
+        Json(AuthResponse {
+            is_admin: false,
+            xsrf_token: user_token.xsrf_token,
+        }),
+    ))
+}
+
+/// Go back to the session of the admin who started the impersonation
+pub async fn stop_impersonation(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    jar: PrivateCookieJar,
+    State(config): State<ConfigState>,
+    user: UserToken,
+    host: Host,
+) -> Result<(PrivateCookieJar, Json<AuthResponse>), (StatusCode, &'static str)> {
+    let login = user.login;
+    let admin = user
+        .impersonator
+        .ok_or((StatusCode::BAD_REQUEST, "session is not impersonated"))?;
+    let admin = admin.check_expires()?.check_not_revoked(&config)?;
+    let cookie = session_cookie(&admin, &host, &config)?;
+    info!(
+        "IMPERSONATION END of {} by {} from {}",
+        login,
+        admin.login,
+        city_from_ip(addr, MAXMIND_READER.get())
+    );
+    Ok((
+        jar.add(cookie),
+        Json(AuthResponse {
+            is_admin: can_administrate(&admin.roles, &config),
+            xsrf_token: admin.xsrf_token,
+        }),
+    ))
+}
+
+fn impersonator_login(user_token: &UserToken) -> &str {
+    user_token
+        .impersonator
+        .as_ref()
+        .map_or("", |i| i.login.as_str())
+}
+
+/// Log every request made with an impersonated session
+pub async fn impersonation_audit_middleware(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    jar: PrivateCookieJar,
+    req: Request,
+    next: Next,
+) -> Response {
+    if let Some(cookie) = jar.get(AUTH_COOKIE)
+        && let Ok(user_token) = serde_json::from_str::<UserToken>(cookie.value())
+        && user_token.impersonator.is_some()
+    {
+        info!(
+            "IMPERSONATED REQUEST as {} by {}: {} {}{} from {}",
+            user_token.login,
+            impersonator_login(&user_token),
+            req.method(),
+            req.headers()
+                .get(HOST)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or_default(),
+            req.uri(),
+            city_from_ip(addr, MAXMIND_READER.get())
+        );
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_impersonator_login() {
+        let mut user_token = UserToken {
+            login: "user".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(impersonator_login(&user_token), "");
+        user_token.impersonator = Some(Box::new(UserToken {
+            login: "admin".to_owned(),
+            ..Default::default()
+        }));
+        assert_eq!(impersonator_login(&user_token), "admin");
+        // The impersonator is kept through the cookie serialization
+        let serialized = serde_json::to_string(&user_token).unwrap();
+        assert_eq!(
+            serde_json::from_str::<UserToken>(&serialized).unwrap(),
+            user_token
+        );
+    }
+}
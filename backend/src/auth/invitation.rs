@@ -0,0 +1,214 @@
+use super::{
+    ScopedAdminToken,
+    delegation::can_administrate,
+    token::{seal, unseal},
+    user::{AuthResponse, User, UserInfo, create_user_cookie, hash_password, user_to_token},
+};
+use crate::{
+    appstate::{ConfigFile, ConfigState, MAXMIND_READER, PreviousCookieKeys},
+    configuration::{Config, config_or_error},
+    extract::Host,
+    utils::{string_trim, vec_trim_remove_empties},
+};
+use axum::{
+    Json,
+    extract::{ConnectInfo, State},
+};
+use axum_extra::extract::PrivateCookieJar;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info};
+
+static INVITATION_TOKEN: &str = "ATRIUM_INVITATION";
+const DEFAULT_VALID_FOR_DAYS: i64 = 7;
+const MAX_VALID_FOR_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvitationRequest {
+    #[serde(deserialize_with = "string_trim")]
+    pub login: String,
+    #[serde(default, deserialize_with = "vec_trim_remove_empties")]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub info: Option<UserInfo>,
+    /// When the created account expires, as a unix timestamp
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// How long the link can be used, from 1 to 365 days, defaults to 7 days
+    #[serde(default)]
+    pub valid_for_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct InvitationToken {
+    id: String,
+    user: User,
+    expires: i64,
+}
+
+/// An invitation already used, remembered until it expires so that it cannot create its user again
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsumedInvitation {
+    pub id: String,
+    pub expires: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitationResponse {
+    pub url: String,
+    pub expires: i64,
+}
+
+#[derive(Deserialize)]
+pub struct AcceptInvitation {
+    pub token: String,
+    pub password: String,
+}
+
+/// Create an invitation link allowing its holder to create the described user with a password of their choice
+pub async fn create_invitation(
+    State(config): State<ConfigState>,
+    admin: ScopedAdminToken,
+    jar: PrivateCookieJar,
+    Json(payload): Json<InvitationRequest>,
+) -> Result<Json<InvitationResponse>, (StatusCode, &'static str)> {
+    if payload.login.is_empty() {
+        return Err((StatusCode::NOT_ACCEPTABLE, "login is required"));
+    }
+    let valid_for_days = payload.valid_for_days.unwrap_or(DEFAULT_VALID_FOR_DAYS);
+    if !(1..=MAX_VALID_FOR_DAYS).contains(&valid_for_days) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "invitation must be valid from 1 to 365 days",
+        ));
+    }
+    if config.users.iter().any(|u| u.login == payload.login) {
+        return Err((StatusCode::CONFLICT, "user already exists"));
+    }
+    let user = User {
+        login: payload.login,
+        roles: payload.roles,
+        info: payload.info,
+        expires_at: payload.expires_at,
+        ..Default::default()
+    };
+    if !admin.is_global() && (!admin.user_visible(&user) || !admin.user_allowed(&user, &config)) {
+        return Err((StatusCode::FORBIDDEN, "user is outside of the admin scope"));
+    }
+    let expires = OffsetDateTime::now_utc()
+        .checked_add(Duration::days(valid_for_days))
+        .ok_or((StatusCode::BAD_REQUEST, "invitation expiry is out of range"))?
+        .unix_timestamp();
+    let token = seal(
+        jar,
+        INVITATION_TOKEN,
+        &InvitationToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            user,
+            expires,
+        },
+    )?;
+    Ok(Json(InvitationResponse {
+        url: format!(
+            "{}/invitation/invitation.html?token={}",
+            config.full_domain(),
+            urlencoding::encode(&token)
+        ),
+        expires,
+    }))
+}
+
+/// Create the invited user with the chosen password, and log them in
+pub async fn accept_invitation(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    jar: PrivateCookieJar,
+    State(config): State<ConfigState>,
+    State(config_file): State<ConfigFile>,
+    State(previous_keys): State<PreviousCookieKeys>,
+    #[cfg(target_os = "linux")] State(jail): State<crate::OptionalJail>,
+    host: Host,
+    Json(payload): Json<AcceptInvitation>,
+) -> Result<(PrivateCookieJar, Json<AuthResponse>), (StatusCode, &'static str)> {
+    let Some(invitation) =
+        unseal::<InvitationToken>(&jar, &previous_keys, INVITATION_TOKEN, &payload.token)
+            .filter(|i| i.expires >= OffsetDateTime::now_utc().unix_timestamp())
+    else {
+        #[cfg(target_os = "linux")]
+        if let Some(jail) = jail {
+            jail.report_failure(addr.ip()).await;
+        }
+        return Err((StatusCode::FORBIDDEN, "invitation is invalid or expired"));
+    };
+    if payload.password.trim().is_empty() {
+        return Err((StatusCode::NOT_ACCEPTABLE, "password is required"));
+    }
+
+    let mut user = invitation.user;
+    user.password = payload.password;
+    hash_password(&mut user, &config.argon2)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "password hash failed"))?;
+
+    // The invitation can only be used once, even if its user is removed afterwards
+    let mut file_config = config_or_error(&config_file).await?;
+    if file_config
+        .consumed_invitations
+        .iter()
+        .any(|i| i.id == invitation.id)
+        || file_config.users.iter().any(|u| u.login == user.login)
+    {
+        return Err((StatusCode::CONFLICT, "invitation was already used"));
+    }
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    file_config
+        .consumed_invitations
+        .retain(|i| i.expires >= now);
+    file_config.consumed_invitations.push(ConsumedInvitation {
+        id: invitation.id,
+        expires: invitation.expires,
+    });
+    file_config.users.push(user.clone());
+    file_config
+        .to_file_or_internal_server_error(&config_file)
+        .await?;
+    info!("INVITATION ACCEPTED by {}", user.login);
+
+    let user_token = user_to_token(&user, &config);
+    let cookie = create_user_cookie(
+        &user_token,
+        &host,
+        &config,
+        addr,
+        MAXMIND_READER.get(),
+        &user,
+    )?;
+    Ok((
+        jar.add(cookie),
+        Json(AuthResponse {
+            is_admin: can_administrate(&user_token.roles, &config),
+            xsrf_token: user_token.xsrf_token,
+        }),
+    ))
+}
+
+/// Remove the users whose account has expired from the configuration file, the disabled accounts are kept to be enabled again.
+/// The consumed invitations are forgotten once expired too.
+pub async fn remove_expired_users(config_file: &str) {
+    let Ok(mut config) = Config::from_file(config_file).await else {
+        error!("could not read configuration to remove expired users");
+        return;
+    };
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let count = config.users.len();
+    let invitations_count = config.consumed_invitations.len();
+    config.users.retain(|u| u.disabled || !u.is_expired(now));
+    config.consumed_invitations.retain(|i| i.expires >= now);
+    if config.users.len() == count && config.consumed_invitations.len() == invitations_count {
+        return;
+    }
+    match config.to_file(config_file).await {
+        Ok(_) => info!("{} EXPIRED USERS REMOVED", count - config.users.len()),
+        Err(e) => error!("could not save configuration after removing expired users: {e}"),
+    }
+}
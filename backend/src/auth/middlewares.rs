@@ -1,19 +1,26 @@
 use super::user::UserToken;
 use crate::{
     apps::AppWithUri,
-    appstate::{ConfigState, MAXMIND_READER},
-    auth::{AUTH_COOKIE, cookie_user::CookieUserToken},
+    appstate::{ConfigState, MAXMIND_READER, Shares},
+    auth::{
+        AUTH_COOKIE,
+        client_cert::ClientCertificate,
+        cookie_user::CookieUserToken,
+        share::{share_unlocked, unlock_page_url},
+        share_registry::ShareAccess,
+    },
     configuration::HostType,
+    davs::file_request::{FileRequest, session_uploads},
     extract::Host,
     headers::XSRFToken,
     logger::city_from_ip,
-    utils::is_path_within_base,
+    utils::{extract_query_pairs, is_path_within_base, random_string},
 };
 use axum::{
     body::Body,
     extract::{ConnectInfo, Request, State},
     middleware::Next,
-    response::{IntoResponse, Response},
+    response::{IntoResponse, Redirect, Response},
 };
 use axum_extra::{
     TypedHeader,
@@ -24,12 +31,15 @@ use axum_extra::{
 };
 use http::{
     HeaderValue, Method, StatusCode,
-    header::{COOKIE, InvalidHeaderValue, LOCATION, SET_COOKIE},
+    header::{COOKIE, InvalidHeaderValue, LOCATION, RANGE, SET_COOKIE},
 };
 use std::{net::SocketAddr, path::PathBuf};
-use tracing::info;
+use time::{Duration, OffsetDateTime};
+use tracing::{error, info};
 
 pub static AUTHENTICATED_USER_MAIL_HEADER: &str = "Remote-User";
+/// Cookie identifying the session of a file request recipient
+const FILE_REQUEST_COOKIE: &str = "ATRIUM_FILE_REQUEST";
 
 #[derive(Debug, Clone, Copy)]
 pub enum AuthError {
@@ -45,6 +55,9 @@ pub async fn auth_middleware(
     mut req: Request,
     next: Next,
 ) -> Response {
+    if host_type.require_client_cert() && req.extensions().get::<ClientCertificate>().is_none() {
+        return (StatusCode::FORBIDDEN, "client certificate required").into_response();
+    }
     if host_type.secured() {
         let hostname = host.as_str();
         let domain = hostname.split(':').next().unwrap_or_default();
@@ -98,10 +111,13 @@ pub async fn auth_middleware(
 
 pub async fn dav_auth_middleware(
     #[cfg(target_os = "linux")] State(jail): State<crate::OptionalJail>,
+    State(config): State<ConfigState>,
+    State(shares): State<Shares>,
     mut app: HostType,
     host: Host,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     user: Option<UserToken>,
+    mut jar: PrivateCookieJar,
     mut req: Request,
     next: Next,
 ) -> Response {
@@ -138,6 +154,36 @@ pub async fn dav_auth_middleware(
         };
     }
 
+    // Apply the protections of the share link : password and download limit
+    let mut counted_share = None;
+    if let Some(user) = &user
+        && let Some(share_id) = user.share.as_ref().and_then(|s| s.id.as_ref())
+        && let Some(record) = shares.get(share_id)
+    {
+        if !record.password.is_empty() && !share_unlocked(&jar, share_id) {
+            info!("FILE ACCESS DENIED (share password required): {log_str}");
+            // Browsers are sent to the page asking the password
+            if method == Method::GET
+                && let Some(token) = query
+                    .map(extract_query_pairs)
+                    .and_then(|q| q.get("token").map(|t| (*t).to_owned()))
+            {
+                return Redirect::to(&unlock_page_url(&config, &token, path)).into_response();
+            }
+            return (StatusCode::FORBIDDEN, "share password required").into_response();
+        }
+        if record.max_downloads.is_some() && is_download(&req) {
+            if !shares.reserve_download(share_id) {
+                info!("FILE ACCESS DENIED (share download limit reached): {log_str}");
+                return (StatusCode::GONE, "share download limit reached").into_response();
+            }
+            counted_share = Some(share_id.clone());
+        } else if record.is_exhausted() {
+            info!("FILE ACCESS DENIED (share download limit reached): {log_str}");
+            return (StatusCode::GONE, "share download limit reached").into_response();
+        }
+    }
+
     let unlogged_methods = [
         Method::OPTIONS,
         Method::HEAD,
@@ -146,10 +192,39 @@ pub async fn dav_auth_middleware(
         Method::from_bytes(b"PROPFIND").expect("infallible"),
     ];
 
-    if !unlogged_methods.contains(method) && query.is_none_or(|q| q != "diskusage") {
+    let logged = !unlogged_methods.contains(method) && query.is_none_or(|q| q != "diskusage");
+    if logged {
         info!("FILE ACCESS: {log_str}");
     }
 
+    // The logged requests made with a share link are recorded in its access history
+    let mut access = None;
+    if logged
+        && let Some(user) = &user
+        && let Some(share_id) = user.share.as_ref().and_then(|s| s.id.as_ref())
+    {
+        access = Some(ShareAccess {
+            share_id: share_id.clone(),
+            at: OffsetDateTime::now_utc().unix_timestamp(),
+            by: user.login.clone(),
+            method: method.to_string(),
+            path: urlencoding::decode(path).map_or_else(|_| path.to_owned(), |p| p.into_owned()),
+            from: city_from_ip(addr, MAXMIND_READER.get()),
+            status: 0,
+            notify: is_download(&req) || method == Method::PUT,
+        });
+    }
+
+    // A file request can only write to a dav that is writable in the configuration,
+    // its recipient is refused rather than given a read access to the folder
+    if let Some(user) = &user
+        && user.share.as_ref().is_some_and(|s| s.upload_only)
+        && !matches!(&app, HostType::Dav(dav) if dav.writable)
+    {
+        info!("FILE ACCESS DENIED (file request on a read only dav): {log_str}");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
     // If we have a non writable share, alter the host so that is not writable
     if let Some(user) = &user
         && let Some(share) = &user.share
@@ -160,7 +235,67 @@ pub async fn dav_auth_middleware(
         req.extensions_mut().insert(app);
     }
 
-    next.run(req).await
+    // A file request only shows what was uploaded during the session, which is kept in a cookie
+    if let Some(user) = &user
+        && let Some(share) = &user.share
+        && share.upload_only
+        && let Some(share_id) = &share.id
+    {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let session = if let Some(cookie) = jar.get(FILE_REQUEST_COOKIE) {
+            cookie.value().to_owned()
+        } else {
+            let session = random_string(32);
+            jar = jar.add(
+                Cookie::build((FILE_REQUEST_COOKIE, session.clone()))
+                    .path("/")
+                    .same_site(SameSite::Lax)
+                    .secure(config.tls_mode.is_secure())
+                    .max_age(Duration::seconds(user.expires - now))
+                    .http_only(true)
+                    .build(),
+            );
+            session
+        };
+        req.extensions_mut().insert(FileRequest {
+            root: share.path.clone(),
+            prefix: share.upload_prefix.clone(),
+            max_upload_size: share.max_upload_size,
+            uploaded: session_uploads(&format!("{share_id}:{session}"), user.expires, now),
+        });
+    }
+
+    // Every download is counted, so it gets the whole content : the ranges could get it piece by piece
+    if counted_share.is_some() {
+        req.headers_mut().remove(RANGE);
+    }
+
+    let res = next.run(req).await;
+    if let Some(share_id) = counted_share {
+        if res.status().is_success() {
+            if let Err(e) = shares.save().await {
+                error!("could not save the share downloads: {}", e.0);
+            }
+        } else {
+            shares.release_download(&share_id);
+        }
+    }
+    if let Some(mut access) = access {
+        access.status = res.status().as_u16();
+        let smtp = config.smtp_config.clone();
+        tokio::spawn(async move { shares.report_access(access, smtp.as_ref()).await });
+    }
+    (jar, res).into_response()
+}
+
+/// A request getting the content of a file or a folder, the searches and the disk usages are not downloads
+fn is_download(req: &Request) -> bool {
+    req.method() == Method::GET
+        && req
+            .uri()
+            .query()
+            .map(extract_query_pairs)
+            .is_none_or(|q| !q.contains_key("q") && !q.contains_key("diskusage"))
 }
 
 pub async fn xsrf_middleware(
@@ -1,9 +1,22 @@
+pub mod bulk;
+pub mod challenge;
+pub mod client_cert;
+pub mod cookie_keys;
 pub mod cookie_user;
+pub mod delegation;
+pub mod impersonation;
+pub mod invitation;
 pub mod middlewares;
+pub mod password;
+pub mod reset;
+pub mod roles;
 pub mod share;
+pub mod share_registry;
+pub(crate) mod token;
 pub mod user;
 
 pub use cookie_user::*;
+pub use delegation::ScopedAdminToken;
 pub use middlewares::*;
 pub use share::*;
 pub use user::*;
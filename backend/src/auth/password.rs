@@ -0,0 +1,248 @@
+use crate::configuration::Argon2Config;
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    password_hash::SaltString,
+};
+use chacha20poly1305::aead::OsRng;
+
+/// The password hash formats accepted when verifying a password.
+/// Only Argon2 is ever produced, the others are kept to allow migrating users from other systems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashScheme {
+    Argon2,
+    Bcrypt,
+    Sha256Crypt,
+    Sha512Crypt,
+}
+
+impl HashScheme {
+    pub fn detect(hash: &str) -> Option<Self> {
+        if hash.starts_with("$argon2") {
+            Some(HashScheme::Argon2)
+        } else if ["$2a$", "$2b$", "$2y$"].iter().any(|p| hash.starts_with(p)) {
+            Some(HashScheme::Bcrypt)
+        } else if hash.starts_with("$5$") {
+            Some(HashScheme::Sha256Crypt)
+        } else if hash.starts_with("$6$") {
+            Some(HashScheme::Sha512Crypt)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Valid,
+    ValidNeedsRehash,
+    Invalid,
+}
+
+#[derive(Debug)]
+pub struct UnsupportedHash;
+
+pub fn argon2_hasher(config: &Argon2Config) -> Result<Argon2<'static>, argon2::Error> {
+    let params = Params::new(
+        config.memory_cost,
+        config.time_cost,
+        config.parallelism,
+        None,
+    )?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+pub fn hash(password: &str, config: &Argon2Config) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(argon2_hasher(config)?
+        .hash_password(password.trim().as_bytes(), &salt)?
+        .to_string())
+}
+
+/// The new hash of a password verified against an outdated hash.
+/// The hashes are made of the trimmed password, so a password with surrounding whitespace keeps its hash to stay usable.
+pub fn rehash(password: &str, config: &Argon2Config) -> Option<String> {
+    if password != password.trim() {
+        return None;
+    }
+    hash(password, config).ok()
+}
+
+pub fn verify(
+    password: &str,
+    hash: &str,
+    config: &Argon2Config,
+) -> Result<Verification, UnsupportedHash> {
+    let scheme = HashScheme::detect(hash).ok_or(UnsupportedHash)?;
+    let valid = match scheme {
+        HashScheme::Argon2 => {
+            let parsed_hash = PasswordHash::new(hash).map_err(|_| UnsupportedHash)?;
+            // The verifier takes the algorithm and the cost parameters from the hash itself
+            if Argon2::default()
+                .verify_password(password.as_bytes(), &parsed_hash)
+                .is_err()
+            {
+                return Ok(Verification::Invalid);
+            }
+            return Ok(if argon2_is_current(&parsed_hash, config) {
+                Verification::Valid
+            } else {
+                Verification::ValidNeedsRehash
+            });
+        }
+        HashScheme::Bcrypt => pwhash::bcrypt::verify(password, hash),
+        HashScheme::Sha256Crypt => pwhash::sha256_crypt::verify(password, hash),
+        HashScheme::Sha512Crypt => pwhash::sha512_crypt::verify(password, hash),
+    };
+    Ok(if valid {
+        Verification::ValidNeedsRehash
+    } else {
+        Verification::Invalid
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct HtpasswdEntry {
+    pub login: String,
+    pub hash: String,
+}
+
+/// Parse the content of an htpasswd file, giving for each meaningful line either an entry or the reason it was rejected
+pub fn parse_htpasswd(content: &str) -> Vec<(usize, Result<HtpasswdEntry, &'static str>)> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(idx, line)| {
+            let entry = match line.trim().split_once(':') {
+                Some((login, hash)) if !login.trim().is_empty() => {
+                    if HashScheme::detect(hash.trim()).is_some() {
+                        Ok(HtpasswdEntry {
+                            login: login.trim().to_owned(),
+                            hash: hash.trim().to_owned(),
+                        })
+                    } else {
+                        Err("unsupported hash format")
+                    }
+                }
+                _ => Err("malformed line"),
+            };
+            (idx + 1, entry)
+        })
+        .collect()
+}
+
+fn argon2_is_current(parsed_hash: &PasswordHash<'_>, config: &Argon2Config) -> bool {
+    if parsed_hash.algorithm != Algorithm::Argon2id.ident() {
+        return false;
+    }
+    match Params::try_from(parsed_hash) {
+        Ok(params) => {
+            params.m_cost() == config.memory_cost
+                && params.t_cost() == config.time_cost
+                && params.p_cost() == config.parallelism
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BCRYPT: &str = "$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe";
+    const SHA256_CRYPT: &str = "$5$atriumsalt$R9yYfoUdl99VmG4YqmajT.Bnf1AtFNitHk6xQi0NdQ8";
+    const SHA512_CRYPT: &str = "$6$atriumsalt$uhNx09knIpprVvK2fet4fA1UlJuKm1X1RN6IXNg8bbhGEMATnwz0HCWR6WxLqAkXxsJsV2EppGH.xXrPKdXHR/";
+    const OLD_ARGON2: &str = "$argon2id$v=19$m=4096,t=3,p=1$ZH9ZFCT6YjYQpxkNt3SQgQ$g3DQawMEWlU1rnMAserFAzUg3Lg2O80s8eH+PrvmUo0";
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(HashScheme::detect(OLD_ARGON2), Some(HashScheme::Argon2));
+        assert_eq!(HashScheme::detect(BCRYPT), Some(HashScheme::Bcrypt));
+        assert_eq!(
+            HashScheme::detect(SHA256_CRYPT),
+            Some(HashScheme::Sha256Crypt)
+        );
+        assert_eq!(
+            HashScheme::detect(SHA512_CRYPT),
+            Some(HashScheme::Sha512Crypt)
+        );
+        assert_eq!(HashScheme::detect("$apr1$abc$def"), None);
+        assert_eq!(HashScheme::detect("password"), None);
+    }
+
+    #[test]
+    fn test_verify_foreign_hashes_need_rehash() {
+        let config = Argon2Config::default();
+        for h in [BCRYPT, SHA256_CRYPT, SHA512_CRYPT] {
+            assert_eq!(
+                verify("password", h, &config).unwrap(),
+                Verification::ValidNeedsRehash
+            );
+            assert_eq!(verify("wrong", h, &config).unwrap(), Verification::Invalid);
+        }
+        assert!(verify("password", "$apr1$abc$def", &config).is_err());
+    }
+
+    #[test]
+    fn test_verify_argon2_parameters() {
+        let config = Argon2Config::default();
+        assert_eq!(
+            verify("password", OLD_ARGON2, &config).unwrap(),
+            Verification::ValidNeedsRehash
+        );
+        let config = Argon2Config {
+            memory_cost: 4096,
+            time_cost: 3,
+            parallelism: 1,
+        };
+        assert_eq!(
+            verify("password", OLD_ARGON2, &config).unwrap(),
+            Verification::Valid
+        );
+        let new_hash = hash(" password ", &Argon2Config::default()).unwrap();
+        assert_eq!(
+            verify("password", &new_hash, &Argon2Config::default()).unwrap(),
+            Verification::Valid
+        );
+    }
+
+    #[test]
+    fn test_rehash() {
+        let config = Argon2Config::default();
+        let new_hash = rehash("password", &config).unwrap();
+        assert_eq!(
+            verify("password", &new_hash, &config).unwrap(),
+            Verification::Valid
+        );
+        // The trimmed hash would lock out a legacy user whose password has surrounding whitespace
+        assert_eq!(rehash(" password ", &config), None);
+    }
+
+    #[test]
+    fn test_parse_htpasswd() {
+        let content = format!(
+            "# comment\n\nalice:{BCRYPT}\nbob:$apr1$abc$def\n  carol : {SHA512_CRYPT}\nmalformed\n"
+        );
+        assert_eq!(
+            parse_htpasswd(&content),
+            vec![
+                (
+                    3,
+                    Ok(HtpasswdEntry {
+                        login: "alice".to_owned(),
+                        hash: BCRYPT.to_owned()
+                    })
+                ),
+                (4, Err("unsupported hash format")),
+                (
+                    5,
+                    Ok(HtpasswdEntry {
+                        login: "carol".to_owned(),
+                        hash: SHA512_CRYPT.to_owned()
+                    })
+                ),
+                (6, Err("malformed line")),
+            ]
+        );
+    }
+}
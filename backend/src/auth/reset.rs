@@ -0,0 +1,137 @@
+use super::{
+    ScopedAdminToken,
+    token::{seal, unseal},
+    user::{REVOKED_SESSIONS, hash_password},
+};
+use crate::{
+    appstate::{ConfigFile, ConfigState, PreviousCookieKeys},
+    configuration::config_or_error,
+};
+use axum::{
+    Json,
+    extract::{ConnectInfo, Path, State},
+};
+use axum_extra::extract::PrivateCookieJar;
+use base64ct::Encoding;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+
+static RESET_TOKEN: &str = "ATRIUM_PASSWORD_RESET";
+const RESET_VALIDITY_HOURS: i64 = 24;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ResetToken {
+    login: String,
+    /// Fingerprint of the password hash at the time of the reset request : changing the password consumes the token
+    fingerprint: String,
+    expires: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetResponse {
+    pub url: String,
+    pub expires: i64,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPassword {
+    pub token: String,
+    pub password: String,
+}
+
+fn fingerprint(password_hash: &str) -> String {
+    let digest: [u8; 32] = Sha256::digest(password_hash.as_bytes()).into();
+    base64ct::Base64::encode_string(&digest)
+}
+
+/// Create a single use link allowing a user to choose a new password
+pub async fn create_reset_link(
+    State(config_file): State<ConfigFile>,
+    State(config): State<ConfigState>,
+    admin: ScopedAdminToken,
+    jar: PrivateCookieJar,
+    Path(user_login): Path<String>,
+) -> Result<Json<ResetResponse>, (StatusCode, &'static str)> {
+    let file_config = config_or_error(&config_file).await?;
+    let user = file_config
+        .users
+        .iter()
+        .find(|u| u.login == user_login && admin.user_allowed(u, &file_config))
+        .ok_or((StatusCode::BAD_REQUEST, "user does not exist"))?;
+    let expires =
+        (OffsetDateTime::now_utc() + Duration::hours(RESET_VALIDITY_HOURS)).unix_timestamp();
+    let token = seal(
+        jar,
+        RESET_TOKEN,
+        &ResetToken {
+            login: user.login.clone(),
+            fingerprint: fingerprint(&user.password),
+            expires,
+        },
+    )?;
+    info!(
+        "PASSWORD RESET LINK created for {} by {}",
+        user.login, admin.0.login
+    );
+    Ok(Json(ResetResponse {
+        url: format!(
+            "{}/reset/reset.html?token={}",
+            config.full_domain(),
+            urlencoding::encode(&token)
+        ),
+        expires,
+    }))
+}
+
+/// Set the new password of the user of a reset link, and revoke the sessions opened with the previous one
+pub async fn reset_password(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    jar: PrivateCookieJar,
+    State(config_file): State<ConfigFile>,
+    State(previous_keys): State<PreviousCookieKeys>,
+    #[cfg(target_os = "linux")] State(jail): State<crate::OptionalJail>,
+    Json(payload): Json<ResetPassword>,
+) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let mut config = config_or_error(&config_file).await?;
+    let Some(user) = unseal::<ResetToken>(&jar, &previous_keys, RESET_TOKEN, &payload.token)
+        .filter(|t| t.expires >= now)
+        .and_then(|t| {
+            config
+                .users
+                .iter()
+                .position(|u| u.login == t.login && fingerprint(&u.password) == t.fingerprint)
+        })
+        .and_then(|pos| config.users.get_mut(pos))
+    else {
+        #[cfg(target_os = "linux")]
+        if let Some(jail) = jail {
+            jail.report_failure(addr.ip()).await;
+        }
+        info!("PASSWORD RESET ERROR from {} : invalid link", addr.ip());
+        return Err((
+            StatusCode::FORBIDDEN,
+            "reset link is invalid, expired or already used",
+        ));
+    };
+    if payload.password.trim().is_empty() {
+        return Err((StatusCode::NOT_ACCEPTABLE, "password is required"));
+    }
+
+    user.password = payload.password;
+    hash_password(user, &config.argon2)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "password hash failed"))?;
+    user.sessions_valid_after = Some(now);
+    let login = user.login.clone();
+    config
+        .to_file_or_internal_server_error(&config_file)
+        .await?;
+    REVOKED_SESSIONS.insert(login.clone(), now);
+    info!("PASSWORD RESET for {login}");
+
+    Ok((StatusCode::OK, "password changed successfully"))
+}
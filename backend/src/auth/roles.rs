@@ -0,0 +1,157 @@
+use crate::{
+    configuration::Config,
+    utils::{is_default, string_trim, vec_trim_remove_empties},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A named set of users sharing the same roles.
+/// The members of the nested groups are members of this group too.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    #[serde(deserialize_with = "string_trim")]
+    pub name: String,
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "vec_trim_remove_empties"
+    )]
+    pub members: Vec<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "vec_trim_remove_empties"
+    )]
+    pub groups: Vec<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "vec_trim_remove_empties"
+    )]
+    pub roles: Vec<String>,
+}
+
+/// Maps a role to the roles it implies (e.g. ADMINS implies USERS)
+pub type RoleHierarchy = BTreeMap<String, Vec<String>>;
+
+/// Compute the effective roles of a user : its own roles, the roles of the groups it belongs to
+/// (directly or through nested groups), and every role implied by those through the role hierarchy.
+pub fn resolve_roles(login: &str, roles: &[String], config: &Config) -> Vec<String> {
+    // Find the groups the user belongs to, following nested groups until nothing changes
+    let mut groups: BTreeSet<&str> = config
+        .groups
+        .iter()
+        .filter(|g| g.members.iter().any(|m| m == login))
+        .map(|g| g.name.as_str())
+        .collect();
+    loop {
+        let before = groups.len();
+        for group in config.groups.iter() {
+            if group.groups.iter().any(|g| groups.contains(g.as_str())) {
+                groups.insert(group.name.as_str());
+            }
+        }
+        if groups.len() == before {
+            break;
+        }
+    }
+
+    let mut pending: Vec<&str> = roles
+        .iter()
+        .map(String::as_str)
+        .chain(
+            config
+                .groups
+                .iter()
+                .filter(|g| groups.contains(g.name.as_str()))
+                .flat_map(|g| g.roles.iter().map(String::as_str)),
+        )
+        .collect();
+    pending.reverse();
+
+    // Expand the roles with the hierarchy, keeping the order in which they were found
+    let mut resolved: Vec<String> = Vec::new();
+    while let Some(role) = pending.pop() {
+        if resolved.iter().any(|r| r == role) {
+            continue;
+        }
+        resolved.push(role.to_owned());
+        if let Some(implied) = config.role_hierarchy.get(role) {
+            pending.extend(implied.iter().rev().map(String::as_str));
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            groups: vec![
+                Group {
+                    name: "staff".to_owned(),
+                    members: vec!["alice".to_owned()],
+                    groups: vec!["interns".to_owned()],
+                    roles: vec!["USERS".to_owned()],
+                },
+                Group {
+                    name: "interns".to_owned(),
+                    members: vec!["bob".to_owned()],
+                    groups: vec!["staff".to_owned()],
+                    roles: vec!["TRAINEES".to_owned()],
+                },
+                Group {
+                    name: "company".to_owned(),
+                    groups: vec!["staff".to_owned()],
+                    roles: vec!["NEWSLETTER".to_owned()],
+                    ..Default::default()
+                },
+            ],
+            role_hierarchy: RoleHierarchy::from([
+                ("ADMINS".to_owned(), vec!["USERS".to_owned()]),
+                ("USERS".to_owned(), vec!["GUESTS".to_owned()]),
+                ("GUESTS".to_owned(), vec!["ADMINS".to_owned()]),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    fn sorted(mut roles: Vec<String>) -> Vec<String> {
+        roles.sort();
+        roles
+    }
+
+    #[test]
+    fn test_resolve_roles_without_groups() {
+        let config = Config {
+            role_hierarchy: RoleHierarchy::from([("ADMINS".to_owned(), vec!["USERS".to_owned()])]),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_roles("carol", &["ADMINS".to_owned()], &config),
+            vec!["ADMINS".to_owned(), "USERS".to_owned()]
+        );
+        assert_eq!(
+            resolve_roles("carol", &["USERS".to_owned()], &config),
+            vec!["USERS".to_owned()]
+        );
+        assert!(resolve_roles("carol", &[], &config).is_empty());
+    }
+
+    #[test]
+    fn test_resolve_roles_with_nested_groups_and_cycles() {
+        let config = config();
+        // bob is an intern, interns are part of staff (and staff of interns), staff is part of company
+        assert_eq!(
+            sorted(resolve_roles("bob", &[], &config)),
+            vec!["ADMINS", "GUESTS", "NEWSLETTER", "TRAINEES", "USERS"]
+        );
+        // unknown users only get their own roles expanded
+        assert_eq!(
+            sorted(resolve_roles("dave", &["OTHER".to_owned()], &config)),
+            vec!["OTHER"]
+        );
+    }
+}
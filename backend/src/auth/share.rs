@@ -1,20 +1,30 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf};
 
 use axum::{
     Json,
-    extract::{Request, State},
+    extract::{ConnectInfo, Request, State},
     middleware::Next,
     response::IntoResponse,
 };
-use axum_extra::extract::{PrivateCookieJar, cookie::Cookie};
+use axum_extra::extract::{
+    PrivateCookieJar,
+    cookie::{Cookie, SameSite},
+};
 use http::StatusCode;
 use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
+use tracing::info;
 
 use crate::{
-    appstate::ConfigState,
-    auth::{AUTH_COOKIE, UserToken, check_user_has_role, decrypt_user_token},
-    utils::{is_path_within_base, random_string},
+    appstate::{ConfigState, MAXMIND_READER, PreviousCookieKeys, Shares},
+    auth::{
+        AUTH_COOKIE, UserToken, check_user_has_role, decrypt_user_token,
+        password::{self, Verification},
+        share_registry::ShareRecord,
+    },
+    configuration::{Config, TlsMode},
+    logger::city_from_ip,
+    utils::{is_default, is_path_within_base, random_string},
 };
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -25,8 +35,42 @@ pub struct Share {
     pub share_for_days: Option<i64>,
     #[serde(default)]
     pub writable: bool,
+    /// Id of the share in the registry, set by the server
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub id: Option<String>,
+    /// File request : files can be uploaded, but only the ones uploaded during the session can be seen
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub upload_only: bool,
+    /// Prefix added to the name of the files uploaded to a file request
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub upload_prefix: Option<String>,
+    /// Maximum size in bytes of a file uploaded to a file request
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_upload_size: Option<u64>,
 }
 
+/// A share asked by a user, with the optional protections of the share link
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareRequest {
+    #[serde(flatten)]
+    pub share: Share,
+    /// Password to give on the share page before the link can be used
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub password: Option<String>,
+    /// Number of file downloads after which the link cannot be used anymore
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_downloads: Option<u32>,
+    /// Shortcut for a single download
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub one_time: bool,
+    /// Notify the creator by e-mail of the downloads and uploads made with the share link
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub notify: bool,
+}
+
+/// Lifetime of the download tokens, given when no sharing duration is asked. They are too short lived to be recorded.
+pub const DOWNLOAD_TOKEN_SECONDS: i64 = 2;
+
 #[derive(Serialize, Deserialize)]
 pub struct ShareResponse {
     pub token: String,
@@ -35,10 +79,61 @@ pub struct ShareResponse {
 
 pub async fn get_share_token(
     State(config): State<ConfigState>,
+    State(shares): State<Shares>,
     user: UserToken,
     jar: PrivateCookieJar,
-    Json(share): Json<Share>,
+    Json(request): Json<ShareRequest>,
 ) -> Result<PrivateCookieJar, StatusCode> {
+    let ShareRequest {
+        mut share,
+        password,
+        max_downloads,
+        one_time,
+        notify,
+    } = request;
+    let max_downloads = if one_time { Some(1) } else { max_downloads };
+    let password = password.filter(|p| !p.trim().is_empty());
+    let protected = password.is_some() || max_downloads.is_some();
+    // Only the recorded share links can be protected
+    if protected && share.share_for_days.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Only the recorded share links can notify their creator, at the e-mail of the user
+    let notify_email = if notify {
+        let email = user
+            .info
+            .as_ref()
+            .map(|i| i.email.clone())
+            .filter(|e| !e.is_empty());
+        if share.share_for_days.is_none() || config.smtp_config.is_none() || email.is_none() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        email
+    } else {
+        None
+    };
+    // File requests are recorded share links, that cannot be used to modify the existing files
+    share.upload_prefix = share
+        .upload_prefix
+        .map(|p| p.trim().to_owned())
+        .filter(|p| !p.is_empty());
+    if share.upload_only {
+        if share.share_for_days.is_none()
+            || share.writable
+            || share
+                .upload_prefix
+                .as_ref()
+                .is_some_and(|p| p.contains(['/', '\\']) || p == "." || p == "..")
+        {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    } else if share.upload_prefix.is_some() || share.max_upload_size.is_some() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // A share token would not keep track of the impersonation
+    if user.impersonator.is_some() {
+        return Err(StatusCode::FORBIDDEN);
+    }
     // Get the dav from the config map
     let to_share = config
         .davs
@@ -52,6 +147,10 @@ pub async fn get_share_token(
                 ) == share.hostname
         })
         .ok_or(StatusCode::FORBIDDEN)?;
+    // A file request writes to the dav, which must be writable
+    if share.upload_only && !to_share.writable {
+        return Err(StatusCode::FORBIDDEN);
+    }
     // Check that the user is allowed to access the wanted share
     if !&to_share.secured || check_user_has_role(&user, &to_share.roles) {
         // If it's already a share token, check that the new share is not more permissive
@@ -65,6 +164,20 @@ pub async fn get_share_token(
             if !existing_share.writable && share.writable {
                 return Err(StatusCode::FORBIDDEN);
             }
+            // The recipient of a file request cannot see the folder, so cannot share it
+            if existing_share.upload_only {
+                return Err(StatusCode::FORBIDDEN);
+            }
+            // The protections of a share link cannot be escaped by sharing it again
+            if share.share_for_days.is_some()
+                && existing_share
+                    .id
+                    .as_ref()
+                    .and_then(|id| shares.get(id))
+                    .is_some_and(|s| s.is_protected())
+            {
+                return Err(StatusCode::FORBIDDEN);
+            }
         }
 
         // Create a token with the required information
@@ -76,7 +189,9 @@ pub async fn get_share_token(
         let expires = share
             .share_for_days
             .as_ref()
-            .map_or(Duration::seconds(2), |d| Duration::days(*d));
+            .map_or(Duration::seconds(DOWNLOAD_TOKEN_SECONDS), |d| {
+                Duration::days(*d)
+            });
         let mut expires_timestamp = (OffsetDateTime::now_utc() + expires).unix_timestamp();
 
         // If it's already a share token, the new token cannot last longer than the original one
@@ -84,6 +199,39 @@ pub async fn get_share_token(
             expires_timestamp = user.expires;
         }
 
+        // Record the share links so that they can be listed and revoked,
+        // the download tokens given to a share link keep its id so that its protections still apply
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        share.id = user.share.as_ref().and_then(|s| s.id.clone());
+        if share.share_for_days.is_some() {
+            let password = password
+                .map(|p| password::hash(&p, &config.argon2))
+                .transpose()
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+                .unwrap_or_default();
+            let id = uuid::Uuid::new_v4().to_string();
+            shares
+                .insert(ShareRecord {
+                    id: id.clone(),
+                    created_by: user.login.clone(),
+                    created_at: now,
+                    dav_id: to_share.id,
+                    hostname: share.hostname.clone(),
+                    path: share.path.clone(),
+                    share_with: share.share_with.clone(),
+                    writable: share.writable,
+                    upload_only: share.upload_only,
+                    expires: expires_timestamp,
+                    password,
+                    max_downloads,
+                    downloads: 0,
+                    notify_email,
+                })
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            share.id = Some(id);
+        }
+
         let share_token = UserToken {
             login: share_login,
             roles: user.roles,
@@ -91,6 +239,8 @@ pub async fn get_share_token(
             share: Some(share),
             expires: expires_timestamp,
             info: None,
+            issued_at: now,
+            impersonator: None,
         };
         let encoded =
             serde_json::to_string(&share_token).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
@@ -119,7 +269,7 @@ pub async fn cookie_to_body(
             .split_once("=")
             .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
         let plain_token =
-            decrypt_user_token(encrypted_token.0, &jar, encrypted_token.1).map_err(|e| e.0)?;
+            decrypt_user_token(encrypted_token.0, &jar, &[], encrypted_token.1).map_err(|e| e.0)?;
         let res = Json(ShareResponse {
             token: encrypted_token.1.to_owned(),
             xsrf_token: plain_token.xsrf_token,
@@ -129,3 +279,124 @@ pub async fn cookie_to_body(
         Err(parts.status)
     }
 }
+
+/// Name of the cookie telling that the password of a share has been given
+pub(crate) fn unlock_cookie_name(share_id: &str) -> String {
+    format!("ATRIUM_SHARE_{share_id}")
+}
+
+/// Tell if the password of a share has been given by the browser
+pub(crate) fn share_unlocked(jar: &PrivateCookieJar, share_id: &str) -> bool {
+    jar.get(&unlock_cookie_name(share_id))
+        .is_some_and(|c| c.value() == share_id)
+}
+
+/// Page asking the password of a share before going to `path` with the share token
+pub(crate) fn unlock_page_url(config: &Config, token: &str, path: &str) -> String {
+    format!(
+        "{}/share/share.html?token={}&path={}",
+        config.full_domain(),
+        urlencoding::encode(token),
+        urlencoding::encode(path)
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ShareUnlock {
+    token: String,
+    password: String,
+    #[serde(default)]
+    path: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ShareUnlockResponse {
+    /// Where to go with the share token now that the share is unlocked
+    pub url: String,
+}
+
+/// Check the password of a share link, and remember in the browser that it was given
+pub async fn unlock_share(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    jar: PrivateCookieJar,
+    State(config): State<ConfigState>,
+    State(shares): State<Shares>,
+    State(previous_keys): State<PreviousCookieKeys>,
+    #[cfg(target_os = "linux")] State(jail): State<crate::OptionalJail>,
+    Json(payload): Json<ShareUnlock>,
+) -> Result<(PrivateCookieJar, Json<ShareUnlockResponse>), (StatusCode, &'static str)> {
+    let user_token = decrypt_user_token(AUTH_COOKIE, &jar, &previous_keys, &payload.token)
+        .and_then(|t| t.check_not_revoked(&config))
+        .and_then(|t| t.check_share(&shares))?;
+    let record = user_token
+        .share
+        .as_ref()
+        .and_then(|s| s.id.as_ref())
+        .and_then(|id| shares.get(id))
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "share is not protected by a password",
+        ))?;
+    if record.password.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "share is not protected by a password",
+        ));
+    }
+    if !matches!(
+        password::verify(&payload.password, &record.password, &config.argon2),
+        Ok(Verification::Valid | Verification::ValidNeedsRehash)
+    ) {
+        #[cfg(target_os = "linux")]
+        if let Some(jail) = jail {
+            jail.report_failure(addr.ip()).await;
+        }
+        info!(
+            "SHARE UNLOCK FAILURE for {} from {}",
+            record.id,
+            city_from_ip(addr, MAXMIND_READER.get())
+        );
+        return Err((StatusCode::UNAUTHORIZED, "wrong share password"));
+    }
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let cookie = Cookie::build((unlock_cookie_name(&record.id), record.id.clone()))
+        .domain(config.domain.clone())
+        .path("/")
+        .same_site(SameSite::Lax)
+        .secure(config.tls_mode.is_secure())
+        .max_age(Duration::seconds(record.expires - now))
+        .http_only(true)
+        .build();
+    // Go back to the asked (url encoded) path if it is within the share
+    let within_share = urlencoding::decode(&payload.path)
+        .is_ok_and(|p| is_path_within_base(&PathBuf::from(p.as_ref()), &record.path));
+    let path = if payload.path.starts_with('/') && within_share {
+        payload.path
+    } else {
+        record
+            .path
+            .to_string_lossy()
+            .split('/')
+            .map(urlencoding::encode)
+            .collect::<Vec<_>>()
+            .join("/")
+    };
+    let url = format!(
+        "{}://{}{}{}?token={}",
+        config.scheme(),
+        record.hostname,
+        if config.tls_mode == TlsMode::No {
+            format!(":{}", config.http_port)
+        } else {
+            String::new()
+        },
+        path,
+        payload.token
+    );
+    info!(
+        "SHARE UNLOCKED: {} from {}",
+        record.id,
+        city_from_ip(addr, MAXMIND_READER.get())
+    );
+    Ok((jar.add(cookie), Json(ShareUnlockResponse { url })))
+}
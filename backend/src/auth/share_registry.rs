@@ -0,0 +1,622 @@
+use crate::{
+    appstate::{ConfigState, Shares},
+    auth::{REDACTED, ScopedAdminToken, UserToken},
+    configuration::SmtpConfig,
+    errors::{ErrResponse, Error},
+    mail::send_mail,
+    utils::is_default,
+};
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use dashmap::DashMap;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{Arc, LazyLock},
+};
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info};
+
+/// Registries by configuration file, so that a registry is loaded only once and survives the configuration reloads
+static REGISTRIES: LazyLock<DashMap<String, Shares>> = LazyLock::new(DashMap::new);
+
+/// A share link given by a user, recorded so that it can be listed and revoked
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareRecord {
+    pub id: String,
+    pub created_by: String,
+    pub created_at: i64,
+    pub dav_id: usize,
+    pub hostname: String,
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub share_with: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub writable: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub upload_only: bool,
+    pub expires: i64,
+    /// Hash of the password asked before the link can be used
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub password: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_downloads: Option<u32>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub downloads: u32,
+    /// E-mail of the creator of the share, notified of the downloads and uploads
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub notify_email: Option<String>,
+}
+
+/// A request made with a share link, recorded in the access history of the share
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareAccess {
+    pub share_id: String,
+    pub at: i64,
+    /// Login of the share token, telling who the share was given to
+    pub by: String,
+    pub method: String,
+    pub path: String,
+    /// Location or IP of the client
+    pub from: String,
+    pub status: u16,
+    /// Whether the creator of the share is notified of this access (downloads and uploads)
+    #[serde(skip)]
+    pub notify: bool,
+}
+
+impl ShareAccess {
+    /// Subject and body of the e-mail telling the creator of the share about a successful download or upload
+    fn notification(&self, record: &ShareRecord) -> Option<(String, String)> {
+        if !self.notify || !(200..300).contains(&self.status) {
+            return None;
+        }
+        let action = if self.method == "PUT" {
+            "uploaded"
+        } else {
+            "downloaded"
+        };
+        Some((
+            format!("Share accessed : {}", self.path),
+            format!(
+                "{} {} {} on {} from {}.\n\nShare {} of {} given by {}.",
+                self.by,
+                action,
+                self.path,
+                record.hostname,
+                self.from,
+                record.id,
+                record.path.display(),
+                record.created_by
+            ),
+        ))
+    }
+}
+
+impl ShareRecord {
+    pub fn is_protected(&self) -> bool {
+        !self.password.is_empty() || self.max_downloads.is_some()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.max_downloads.is_some_and(|m| self.downloads >= m)
+    }
+
+    fn redacted(mut self) -> Self {
+        if !self.password.is_empty() {
+            self.password = REDACTED.to_owned();
+        }
+        self
+    }
+}
+
+/// The shares given by the users, persisted beside the configuration file
+pub struct ShareRegistry {
+    file: PathBuf,
+    history_file: PathBuf,
+    shares: DashMap<String, ShareRecord>,
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl ShareRegistry {
+    /// Get the registry of a configuration file, loading it from `<configuration>.shares.yaml` the first time.
+    /// The accesses to the shares are appended to `<configuration>.share_history.jsonl`.
+    pub async fn for_config_file(config_file: &str) -> Result<Shares, Error> {
+        if let Some(registry) = REGISTRIES.get(config_file) {
+            return Ok(Arc::clone(registry.value()));
+        }
+        let file = std::path::Path::new(config_file).with_extension("shares.yaml");
+        let shares = match tokio::fs::read_to_string(&file).await {
+            Ok(data) => serde_yaml_ng::from_str::<Vec<ShareRecord>>(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let registry = Arc::new(Self {
+            history_file: std::path::Path::new(config_file).with_extension("share_history.jsonl"),
+            file,
+            shares: shares.into_iter().map(|s| (s.id.clone(), s)).collect(),
+            write_lock: tokio::sync::Mutex::new(()),
+        });
+        Ok(Arc::clone(
+            REGISTRIES
+                .entry(config_file.to_owned())
+                .or_insert(registry)
+                .value(),
+        ))
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.shares.contains_key(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<ShareRecord> {
+        self.shares.get(id).map(|s| s.value().clone())
+    }
+
+    /// The recorded shares, oldest first
+    pub fn list(&self) -> Vec<ShareRecord> {
+        let mut shares: Vec<ShareRecord> = self.shares.iter().map(|s| s.value().clone()).collect();
+        shares.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+        shares
+    }
+
+    /// Record a share, the expired ones are dropped
+    pub async fn insert(&self, share: ShareRecord) -> Result<(), Error> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        self.shares.retain(|_, s| s.expires >= now);
+        self.shares.insert(share.id.clone(), share);
+        self.save().await
+    }
+
+    /// Count a download of a share, unless its download limit is reached
+    pub fn reserve_download(&self, id: &str) -> bool {
+        let Some(mut share) = self.shares.get_mut(id) else {
+            return false;
+        };
+        if share.is_exhausted() {
+            return false;
+        }
+        share.downloads += 1;
+        true
+    }
+
+    /// Give back a download that did not happen
+    pub fn release_download(&self, id: &str) {
+        if let Some(mut share) = self.shares.get_mut(id) {
+            share.downloads = share.downloads.saturating_sub(1);
+        }
+    }
+
+    /// Revoke a share with its history, giving it back if it existed
+    pub async fn remove(&self, id: &str) -> Result<Option<ShareRecord>, Error> {
+        let removed = self.shares.remove(id).map(|(_, s)| s);
+        if removed.is_some() {
+            self.save().await?;
+            self.prune_history().await?;
+        }
+        Ok(removed)
+    }
+
+    /// Drop the expired shares, and the history of the shares that are not recorded anymore
+    pub async fn purge(&self) -> Result<(), Error> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let count = self.shares.len();
+        self.shares.retain(|_, s| s.expires >= now);
+        if self.shares.len() != count {
+            self.save().await?;
+        }
+        self.prune_history().await
+    }
+
+    /// Keep only the accesses to the recorded shares in the history
+    async fn prune_history(&self) -> Result<(), Error> {
+        let _guard = self.write_lock.lock().await;
+        let data = match tokio::fs::read_to_string(&self.history_file).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let kept: String = data
+            .lines()
+            .filter(|l| {
+                serde_json::from_str::<ShareAccess>(l).is_ok_and(|a| self.contains(&a.share_id))
+            })
+            .map(|l| format!("{l}\n"))
+            .collect();
+        if kept.len() != data.len() {
+            tokio::fs::write(&self.history_file, kept).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn save(&self) -> Result<(), Error> {
+        let _guard = self.write_lock.lock().await;
+        let contents = serde_yaml_ng::to_string(&self.list())?;
+        tokio::fs::write(&self.file, contents).await?;
+        Ok(())
+    }
+
+    /// Append an access to the history of the shares
+    pub async fn record_access(&self, access: &ShareAccess) -> Result<(), Error> {
+        let mut line =
+            serde_json::to_string(access).map_err(|_| Error("could not encode share access"))?;
+        line.push('\n');
+        let _guard = self.write_lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.history_file)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// The accesses to a share, oldest first
+    pub async fn history(&self, id: &str) -> Result<Vec<ShareAccess>, Error> {
+        let data = match tokio::fs::read_to_string(&self.history_file).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(data
+            .lines()
+            .filter_map(|l| serde_json::from_str::<ShareAccess>(l).ok())
+            .filter(|a| a.share_id == id)
+            .collect())
+    }
+
+    /// Record an access to a share, and notify its creator by e-mail if asked
+    pub async fn report_access(&self, access: ShareAccess, smtp: Option<&SmtpConfig>) {
+        if let Err(e) = self.record_access(&access).await {
+            error!("could not record the share access: {}", e.0);
+        }
+        if let Some(smtp) = smtp
+            && let Some(record) = self.get(&access.share_id)
+            && let Some(to) = &record.notify_email
+            && let Some((subject, body)) = access.notification(&record)
+            && let Err(e) = send_mail(smtp, to, &subject, body).await
+        {
+            error!("could not notify {to} of the share access: {}", e.0);
+        }
+    }
+}
+
+/// Apply the expiry of the shares to the registry and to its history, to be run periodically
+pub async fn purge_expired(config_file: &str) {
+    let result = match ShareRegistry::for_config_file(config_file).await {
+        Ok(registry) => registry.purge().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        error!("could not purge the expired shares: {}", e.0);
+    }
+}
+
+/// List the shares given by the user
+pub async fn list_shares(
+    State(shares): State<Shares>,
+    user: UserToken,
+) -> Result<Json<Vec<ShareRecord>>, StatusCode> {
+    if user.share.is_some() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(Json(
+        shares
+            .list()
+            .into_iter()
+            .filter(|s| s.created_by == user.login)
+            .map(ShareRecord::redacted)
+            .collect(),
+    ))
+}
+
+/// Revoke a share given by the user
+pub async fn revoke_share(
+    State(shares): State<Shares>,
+    user: UserToken,
+    Path(share_id): Path<String>,
+) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
+    if user.share.is_some() {
+        return Err((StatusCode::FORBIDDEN, "share token cannot revoke shares"));
+    }
+    if shares
+        .get(&share_id)
+        .is_none_or(|s| s.created_by != user.login)
+    {
+        return Err((StatusCode::BAD_REQUEST, "share doesn't exist"));
+    }
+    remove_share(&shares, &share_id, &user.login).await
+}
+
+/// List the shares of all the users, restricted to the managed davs for scoped admins
+pub async fn admin_list_shares(
+    State(config): State<ConfigState>,
+    State(shares): State<Shares>,
+    admin: ScopedAdminToken,
+) -> Json<Vec<ShareRecord>> {
+    Json(
+        shares
+            .list()
+            .into_iter()
+            .filter(|s| admin_allowed(&admin, &config, s))
+            .map(ShareRecord::redacted)
+            .collect(),
+    )
+}
+
+/// Revoke the share of any user, restricted to the managed davs for scoped admins
+pub async fn admin_revoke_share(
+    State(config): State<ConfigState>,
+    State(shares): State<Shares>,
+    admin: ScopedAdminToken,
+    Path(share_id): Path<String>,
+) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
+    if shares
+        .get(&share_id)
+        .is_none_or(|s| !admin_allowed(&admin, &config, &s))
+    {
+        return Err((StatusCode::BAD_REQUEST, "share doesn't exist"));
+    }
+    remove_share(&shares, &share_id, &admin.0.login).await
+}
+
+/// Get the access history of a share given by the user
+pub async fn share_history(
+    State(shares): State<Shares>,
+    user: UserToken,
+    Path(share_id): Path<String>,
+) -> Result<Json<Vec<ShareAccess>>, (StatusCode, &'static str)> {
+    if user.share.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "share token cannot list share accesses",
+        ));
+    }
+    if shares
+        .get(&share_id)
+        .is_none_or(|s| s.created_by != user.login)
+    {
+        return Err((StatusCode::BAD_REQUEST, "share doesn't exist"));
+    }
+    Ok(Json(
+        shares.history(&share_id).await.map_err(ErrResponse::from)?,
+    ))
+}
+
+/// Get the access history of the share of any user, restricted to the managed davs for scoped admins
+pub async fn admin_share_history(
+    State(config): State<ConfigState>,
+    State(shares): State<Shares>,
+    admin: ScopedAdminToken,
+    Path(share_id): Path<String>,
+) -> Result<Json<Vec<ShareAccess>>, (StatusCode, &'static str)> {
+    if shares
+        .get(&share_id)
+        .is_none_or(|s| !admin_allowed(&admin, &config, &s))
+    {
+        return Err((StatusCode::BAD_REQUEST, "share doesn't exist"));
+    }
+    Ok(Json(
+        shares.history(&share_id).await.map_err(ErrResponse::from)?,
+    ))
+}
+
+fn admin_allowed(admin: &ScopedAdminToken, config: &ConfigState, share: &ShareRecord) -> bool {
+    admin.is_global()
+        || config
+            .davs
+            .iter()
+            .any(|d| d.id == share.dav_id && admin.dav_allowed(d))
+}
+
+async fn remove_share(
+    shares: &Shares,
+    share_id: &str,
+    revoked_by: &str,
+) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
+    let share = shares
+        .remove(share_id)
+        .await
+        .map_err(ErrResponse::from)?
+        .ok_or((StatusCode::BAD_REQUEST, "share doesn't exist"))?;
+    info!(
+        "SHARE REVOKED: {} of {} on {} by {}",
+        share.id,
+        share.path.display(),
+        share.hostname,
+        revoked_by
+    );
+    Ok((StatusCode::OK, "share revoked successfully"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_registry_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("atrium.yaml");
+        let config_file = config_file.to_str().unwrap();
+        let registry = ShareRegistry::for_config_file(config_file).await.unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let share = ShareRecord {
+            id: "share1".to_owned(),
+            created_by: "user".to_owned(),
+            created_at: now,
+            hostname: "files.atrium.io".to_owned(),
+            path: PathBuf::from("/folder"),
+            expires: now + 3600,
+            ..Default::default()
+        };
+        registry
+            .insert(ShareRecord {
+                id: "expired".to_owned(),
+                expires: now - 1,
+                ..share.clone()
+            })
+            .await
+            .unwrap();
+        registry.insert(share.clone()).await.unwrap();
+        // The expired share is dropped when a new one is recorded
+        assert_eq!(registry.list(), vec![share.clone()]);
+
+        // The registry is written beside the configuration file
+        let data = std::fs::read_to_string(dir.path().join("atrium.shares.yaml")).unwrap();
+        assert_eq!(
+            serde_yaml_ng::from_str::<Vec<ShareRecord>>(&data).unwrap(),
+            vec![share.clone()]
+        );
+        // The same registry is given for the same configuration file
+        assert!(Arc::ptr_eq(
+            &registry,
+            &ShareRegistry::for_config_file(config_file).await.unwrap()
+        ));
+
+        assert_eq!(registry.remove("share1").await.unwrap(), Some(share));
+        assert_eq!(registry.remove("share1").await.unwrap(), None);
+        assert!(!registry.contains("share1"));
+    }
+
+    #[tokio::test]
+    async fn test_download_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("atrium.yaml");
+        let registry = ShareRegistry::for_config_file(config_file.to_str().unwrap())
+            .await
+            .unwrap();
+        registry
+            .insert(ShareRecord {
+                id: "share1".to_owned(),
+                expires: OffsetDateTime::now_utc().unix_timestamp() + 3600,
+                max_downloads: Some(2),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(registry.reserve_download("share1"));
+        assert!(registry.reserve_download("share1"));
+        assert!(!registry.reserve_download("share1"));
+        assert!(registry.get("share1").unwrap().is_exhausted());
+        // A failed download is given back
+        registry.release_download("share1");
+        assert!(!registry.get("share1").unwrap().is_exhausted());
+        assert!(registry.reserve_download("share1"));
+        assert!(!registry.reserve_download("unknown"));
+    }
+
+    #[tokio::test]
+    async fn test_access_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("atrium.yaml");
+        let registry = ShareRegistry::for_config_file(config_file.to_str().unwrap())
+            .await
+            .unwrap();
+        let share = ShareRecord {
+            id: "share1".to_owned(),
+            created_by: "user".to_owned(),
+            path: PathBuf::from("/folder"),
+            ..Default::default()
+        };
+        let access = ShareAccess {
+            share_id: "share1".to_owned(),
+            by: "user (downloading)".to_owned(),
+            method: "GET".to_owned(),
+            path: "/folder/file".to_owned(),
+            status: 200,
+            notify: true,
+            ..Default::default()
+        };
+        registry.record_access(&access).await.unwrap();
+        registry
+            .record_access(&ShareAccess {
+                share_id: "share2".to_owned(),
+                ..access.clone()
+            })
+            .await
+            .unwrap();
+        // The history is kept beside the configuration file, without the notification flag
+        let history = registry.history("share1").await.unwrap();
+        assert_eq!(
+            history,
+            vec![ShareAccess {
+                notify: false,
+                ..access.clone()
+            }]
+        );
+        assert!(dir.path().join("atrium.share_history.jsonl").exists());
+        assert!(registry.history("unknown").await.unwrap().is_empty());
+
+        // Only the successful downloads and uploads are notified
+        let (subject, body) = access.notification(&share).unwrap();
+        assert_eq!(subject, "Share accessed : /folder/file");
+        assert!(body.starts_with("user (downloading) downloaded /folder/file"));
+        assert!(
+            ShareAccess {
+                status: 403,
+                ..access.clone()
+            }
+            .notification(&share)
+            .is_none()
+        );
+        assert!(
+            ShareAccess {
+                notify: false,
+                ..access
+            }
+            .notification(&share)
+            .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_history_pruning() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("atrium.yaml");
+        let registry = ShareRegistry::for_config_file(config_file.to_str().unwrap())
+            .await
+            .unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        for (id, expires) in [("share1", now + 3600), ("share2", now + 3600)] {
+            registry
+                .insert(ShareRecord {
+                    id: id.to_owned(),
+                    expires,
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        for share_id in ["share1", "share2", "forgotten"] {
+            registry
+                .record_access(&ShareAccess {
+                    share_id: share_id.to_owned(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        // The history of the shares that are not recorded anymore is dropped
+        registry.purge().await.unwrap();
+        let history =
+            std::fs::read_to_string(dir.path().join("atrium.share_history.jsonl")).unwrap();
+        assert_eq!(history.lines().count(), 2);
+        registry.remove("share1").await.unwrap();
+        assert!(registry.history("share1").await.unwrap().is_empty());
+        assert_eq!(registry.history("share2").await.unwrap().len(), 1);
+
+        // And so is the history of the expired shares
+        registry.shares.alter("share2", |_, s| ShareRecord {
+            expires: now - 1,
+            ..s
+        });
+        registry.purge().await.unwrap();
+        assert!(!registry.contains("share2"));
+        assert!(registry.history("share2").await.unwrap().is_empty());
+    }
+}
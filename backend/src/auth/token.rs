@@ -0,0 +1,64 @@
+use crate::{
+    auth::cookie_keys::{self, PreviousKey},
+    errors::ErrResponse,
+};
+use axum::response::IntoResponse;
+use axum_extra::extract::{PrivateCookieJar, cookie::Cookie};
+use http::header::SET_COOKIE;
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Serialize a value and encrypt it with the cookie key, giving a token that can be sent in a link.
+/// The name is authenticated along with the value, so a token cannot be used for another purpose.
+pub(crate) fn seal<T: Serialize>(
+    jar: PrivateCookieJar,
+    name: &'static str,
+    value: &T,
+) -> Result<String, ErrResponse> {
+    let encoded =
+        serde_json::to_string(value).map_err(|_| ErrResponse::S500("could not encode token"))?;
+    let response = jar.add(Cookie::new(name, encoded)).into_response();
+    let sealed = response
+        .headers()
+        .get(SET_COOKIE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(';').next())
+        .and_then(|h| h.split_once('='))
+        .ok_or(ErrResponse::S500("could not encrypt token"))?;
+    Ok(sealed.1.to_owned())
+}
+
+/// Decrypt and deserialize a token produced by `seal` with the same name, with the current or a previous key
+pub(crate) fn unseal<T: DeserializeOwned>(
+    jar: &PrivateCookieJar,
+    previous_keys: &[PreviousKey],
+    name: &'static str,
+    token: &str,
+) -> Option<T> {
+    let cookie = Cookie::parse_encoded(format!("{name}={token}")).ok()?;
+    let decrypted = cookie_keys::decrypt(jar, previous_keys, cookie)?;
+    serde_json::from_str(decrypted.value()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum_extra::extract::cookie::Key;
+    use http::HeaderMap;
+
+    #[test]
+    fn test_seal_unseal() {
+        let jar = PrivateCookieJar::from_headers(&HeaderMap::new(), Key::generate());
+        let token = seal(jar.clone(), "TEST", &("value".to_owned(), 42)).unwrap();
+        assert_eq!(
+            unseal::<(String, i32)>(&jar, &[], "TEST", &token),
+            Some(("value".to_owned(), 42))
+        );
+        // The token is bound to its name and to the key
+        assert_eq!(unseal::<(String, i32)>(&jar, &[], "OTHER", &token), None);
+        let other_jar = PrivateCookieJar::from_headers(&HeaderMap::new(), Key::generate());
+        assert_eq!(
+            unseal::<(String, i32)>(&other_jar, &[], "TEST", &token),
+            None
+        );
+    }
+}
@@ -1,9 +1,17 @@
 use crate::{
     appstate::ConfigFile,
-    appstate::{ConfigState, MAXMIND_READER, OptionalMaxMindReader},
-    auth::check_user_has_role,
-    configuration::Config,
+    appstate::{ConfigState, MAXMIND_READER, OptionalMaxMindReader, PreviousCookieKeys, Shares},
+    auth::{
+        ScopedAdminToken, challenge, check_user_has_role,
+        client_cert::ClientCertificate,
+        cookie_keys::{self, PreviousKey},
+        delegation::can_administrate,
+        password::{self, Verification},
+        roles::resolve_roles,
+        share_registry::ShareRegistry,
+    },
     configuration::config_or_error,
+    configuration::{Argon2Config, Config},
     errors::ErrResponse,
     extract::Host,
     logger::city_from_ip,
@@ -11,11 +19,11 @@ use crate::{
         is_default, query_pairs_or_error, random_string, string_trim, vec_trim_remove_empties,
     },
 };
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
 use axum::{
     Extension, Json, RequestPartsExt,
     extract::{
-        ConnectInfo, FromRef, FromRequestParts, OptionalFromRequestParts, Path, RawQuery, State,
+        ConnectInfo, FromRef, FromRequestParts, OptionalFromRequestParts, Path, Query, RawQuery,
+        State,
     },
     response::{IntoResponse, Response},
 };
@@ -23,13 +31,13 @@ use axum_extra::{
     TypedHeader,
     extract::cookie::{Cookie, Key, PrivateCookieJar},
 };
-use chacha20poly1305::aead::OsRng;
+use dashmap::DashMap;
 use headers::{Authorization, authorization::Basic};
 use http::{StatusCode, request::Parts};
 use serde::{Deserialize, Serialize};
-use std::{convert::Infallible, net::SocketAddr};
+use std::{net::SocketAddr, sync::LazyLock};
 use time::{Duration, OffsetDateTime};
-use tracing::info;
+use tracing::{error, info};
 
 pub use super::share::Share;
 
@@ -77,6 +85,26 @@ pub struct User {
     pub roles: Vec<String>,
     #[serde(default, skip_serializing_if = "is_default")]
     pub info: Option<UserInfo>,
+    /// Unix timestamp after which the account cannot be used anymore (guest accounts)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub expires_at: Option<i64>,
+    /// Unix timestamp before which the sessions of the user are revoked (set by password resets)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub sessions_valid_after: Option<i64>,
+    /// A disabled account cannot be used, but is kept to be enabled again (SCIM deactivation)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub disabled: bool,
+}
+
+impl User {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|e| now > e)
+    }
+
+    /// Whether the account can be used : neither disabled nor expired
+    pub fn is_active(&self, now: i64) -> bool {
+        !self.disabled && !self.is_expired(now)
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -87,8 +115,16 @@ pub struct UserToken {
     pub share: Option<Share>,
     pub expires: i64,
     pub info: Option<UserInfo>,
+    #[serde(default)]
+    pub issued_at: i64,
+    /// Session of the admin impersonating the user, restored when the impersonation stops
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub impersonator: Option<Box<UserToken>>,
 }
 
+/// Sessions revoked since the configuration was loaded : login -> timestamp before which the sessions are invalid
+pub(crate) static REVOKED_SESSIONS: LazyLock<DashMap<String, i64>> = LazyLock::new(DashMap::new);
+
 impl UserToken {
     pub(crate) fn from_json(
         serialized_user_token: &str,
@@ -110,15 +146,62 @@ impl UserToken {
             Ok(self)
         }
     }
+
+    pub(crate) fn check_not_revoked(
+        self,
+        config: &Config,
+    ) -> Result<Self, (StatusCode, &'static str)> {
+        let user = config.users.iter().find(|u| u.login == self.login);
+        // The account may have been disabled, or its expiry brought forward, since the login
+        if let Some(user) = user
+            && !user.is_active(OffsetDateTime::now_utc().unix_timestamp())
+        {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                if user.disabled {
+                    "user account is disabled"
+                } else {
+                    "user account is expired"
+                },
+            ));
+        }
+        let revoked_before = user
+            .and_then(|u| u.sessions_valid_after)
+            .max(REVOKED_SESSIONS.get(&self.login).map(|r| *r));
+        if revoked_before.is_some_and(|r| self.issued_at < r) {
+            Err((StatusCode::UNAUTHORIZED, "user session was revoked"))
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// Check that the share of a share token has not been revoked.
+    /// The tokens without share id, the download tokens and the links created before the registry, are valid until they expire.
+    pub(crate) fn check_share(
+        self,
+        shares: &ShareRegistry,
+    ) -> Result<Self, (StatusCode, &'static str)> {
+        match self.share.as_ref().and_then(|s| s.id.as_ref()) {
+            Some(id) if !shares.contains(id) => {
+                Err((StatusCode::UNAUTHORIZED, "share was revoked"))
+            }
+            _ => Ok(self),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AdminToken(pub(crate) UserToken);
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct LocalAuth {
     pub(crate) login: String,
     pub(crate) password: String,
+    /// Challenge given by `/auth/challenge` and its solution, required after repeated failures
+    #[serde(default)]
+    pub(crate) challenge: Option<String>,
+    #[serde(default)]
+    pub(crate) solution: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -132,12 +215,18 @@ where
     S: Send + Sync,
     Key: FromRef<S>,
     ConfigState: FromRef<S>,
+    ConfigFile: FromRef<S>,
+    PreviousCookieKeys: FromRef<S>,
+    Shares: FromRef<S>,
     crate::OptionalJail: FromRef<S>,
 {
     type Rejection = Response;
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         #[cfg(target_os = "linux")]
         let jail = crate::OptionalJail::from_ref(state);
+        let config = ConfigState::from_ref(state);
+        let previous_keys = PreviousCookieKeys::from_ref(state);
+        let shares = Shares::from_ref(state);
         let jar = PrivateCookieJar::from_request_parts(parts, state)
             .await
             .expect("Cookie jar retrieval is Infallible");
@@ -148,7 +237,9 @@ where
             .ok()
             .map(|hm| hm.get("token").map(|v| v.to_owned()))
         {
-            let user_token = decrypt_user_token(AUTH_COOKIE, &jar, password)
+            let user_token = decrypt_user_token(AUTH_COOKIE, &jar, &previous_keys, password)
+                .and_then(|t| t.check_not_revoked(&config))
+                .and_then(|t| t.check_share(&shares))
                 .map(|mut t| {
                     t.xsrf_token = None;
                     t
@@ -162,6 +253,8 @@ where
             // Deserialize the user_token and return him/her
             let serialized_user_token = cookie.value();
             let user_token = UserToken::from_json(serialized_user_token)
+                .and_then(|t| t.check_not_revoked(&config))
+                .and_then(|t| t.check_share(&shares))
                 .map_err(|e| (e.0, e.1).into_response())?;
             return Ok(user_token);
         }
@@ -174,33 +267,53 @@ where
             .await
         {
             let user_token = if let Ok(token) =
-                decrypt_user_token(AUTH_COOKIE, &jar, basic.password()).map(|mut t| {
-                    t.xsrf_token = None;
-                    t
-                }) {
+                decrypt_user_token(AUTH_COOKIE, &jar, &previous_keys, basic.password())
+                    .and_then(|t| t.check_not_revoked(&config))
+                    .and_then(|t| t.check_share(&shares))
+                    .map(|mut t| {
+                        t.xsrf_token = None;
+                        t
+                    }) {
                 token
             } else {
-                let config = ConfigState::from_ref(state);
-
                 let Extension(addr) = parts
                     .extract::<Extension<ConnectInfo<SocketAddr>>>()
                     .await
                     .expect("Could not find socket address");
+                // The basic auth cannot solve a challenge, the IPs that would have to are refused
+                let now = OffsetDateTime::now_utc().unix_timestamp();
+                let login = basic.username().to_string();
+                if challenge::ip_throttled(&config, addr.0.ip(), now) {
+                    return Err(
+                        (StatusCode::TOO_MANY_REQUESTS, "too many failed logins").into_response()
+                    );
+                }
                 match authenticate_local_user(
                     &config,
                     LocalAuth {
-                        login: basic.username().to_string(),
+                        login: login.clone(),
                         password: basic.password().to_string(),
+                        ..Default::default()
                     },
                     MAXMIND_READER.get(),
                     addr.0,
                 ) {
-                    Ok(user) => {
-                        let mut t = user.1;
+                    Ok((user, mut t, upgraded_hash)) => {
+                        challenge::report_success(&login);
+                        if let Some(upgraded_hash) = upgraded_hash {
+                            persist_upgraded_hash(
+                                &ConfigFile::from_ref(state),
+                                &user.login,
+                                &user.password,
+                                upgraded_hash,
+                            )
+                            .await;
+                        }
                         t.xsrf_token = None;
                         t
                     }
                     Err(e) => {
+                        challenge::report_ip_failure(&config, addr.0.ip(), now);
                         #[cfg(target_os = "linux")]
                         if let Some(jail) = jail {
                             jail.report_failure(addr.0.ip()).await;
@@ -212,6 +325,13 @@ where
             return Ok(user_token);
         }
 
+        // OR Try to get user_token from the verified client certificate of the TLS connection
+        if let Some(certificate) = parts.extensions.get::<ClientCertificate>()
+            && let Some(user_token) = certificate.to_user_token(&config)
+        {
+            return Ok(user_token);
+        }
+
         Err((
             StatusCode::UNAUTHORIZED,
             jar.remove(Cookie::build((AUTH_COOKIE, ""))),
@@ -226,25 +346,30 @@ where
     S: Send + Sync,
     Key: FromRef<S>,
     ConfigState: FromRef<S>,
+    ConfigFile: FromRef<S>,
+    PreviousCookieKeys: FromRef<S>,
+    Shares: FromRef<S>,
     crate::OptionalJail: FromRef<S>,
 {
-    type Rejection = Infallible;
+    type Rejection = Response;
 
     async fn from_request_parts(
         parts: &mut Parts,
         state: &S,
     ) -> Result<Option<Self>, Self::Rejection> {
-        Ok(
-            <UserToken as FromRequestParts<S>>::from_request_parts(parts, state)
-                .await
-                .ok(),
-        )
+        match <UserToken as FromRequestParts<S>>::from_request_parts(parts, state).await {
+            Ok(user_token) => Ok(Some(user_token)),
+            // A throttled basic auth is refused, rather than handled as an anonymous request
+            Err(res) if res.status() == StatusCode::TOO_MANY_REQUESTS => Err(res),
+            Err(_) => Ok(None),
+        }
     }
 }
 
 pub(crate) fn decrypt_user_token(
     cookie_name: &str,
     jar: &PrivateCookieJar,
+    previous_keys: &[PreviousKey],
     encrypted_token: &str,
 ) -> Result<UserToken, (StatusCode, &'static str)> {
     let cookie =
@@ -254,7 +379,7 @@ pub(crate) fn decrypt_user_token(
                 "could not parse encrypted user token",
             )
         })?;
-    let decrypted_cookie = jar.decrypt(cookie).ok_or({
+    let decrypted_cookie = cookie_keys::decrypt(jar, previous_keys, cookie).ok_or({
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             "could not decrypt user token",
@@ -269,6 +394,9 @@ where
     S: Send + Sync,
     Key: FromRef<S>,
     ConfigState: FromRef<S>,
+    ConfigFile: FromRef<S>,
+    PreviousCookieKeys: FromRef<S>,
+    Shares: FromRef<S>,
     crate::OptionalJail: FromRef<S>,
 {
     type Rejection = Response;
@@ -284,6 +412,13 @@ where
             )
                 .into_response());
         }
+        if user.impersonator.is_some() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "impersonated session cannot be used to access admin API",
+            )
+                .into_response());
+        }
         let admin = AdminToken(user);
         Ok(admin)
     }
@@ -293,21 +428,43 @@ pub async fn local_auth(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     jar: PrivateCookieJar,
     State(config): State<ConfigState>,
+    State(config_file): State<ConfigFile>,
     #[cfg(target_os = "linux")] State(jail): State<crate::OptionalJail>,
     host: Host,
     Json(payload): Json<LocalAuth>,
 ) -> Result<(PrivateCookieJar, Json<AuthResponse>), (StatusCode, &'static str)> {
+    // Require a proof of work after repeated failures
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let difficulty = challenge::required_difficulty(&config, addr.ip(), &payload.login, now);
+    if difficulty > 0
+        && !challenge::verify(
+            &jar,
+            payload.challenge.as_deref(),
+            payload.solution.as_deref(),
+            difficulty,
+            now,
+        )
+    {
+        return Err((StatusCode::PRECONDITION_REQUIRED, "proof of work required"));
+    }
+    let login = payload.login.clone();
     // Find the user in configuration
-    let (user, user_token) = match authenticate_local_user(&config, payload, MAXMIND_READER.get(), addr) {
-        Ok(v) => v,
-        Err(e) => {
-            #[cfg(target_os = "linux")]
-            if let Some(jail) = jail {
-                jail.report_failure(addr.ip()).await;
+    let (user, user_token, upgraded_hash) =
+        match authenticate_local_user(&config, payload, MAXMIND_READER.get(), addr) {
+            Ok(v) => v,
+            Err(e) => {
+                challenge::report_failure(&config, addr.ip(), &login, now);
+                #[cfg(target_os = "linux")]
+                if let Some(jail) = jail {
+                    jail.report_failure(addr.ip()).await;
+                }
+                return Err(e);
             }
-            return Err(e);
-        }
-    };
+        };
+    challenge::report_success(&login);
+    if let Some(upgraded_hash) = upgraded_hash {
+        persist_upgraded_hash(&config_file, &user.login, &user.password, upgraded_hash).await;
+    }
     let cookie = create_user_cookie(
         &user_token,
         &host,
@@ -320,7 +477,7 @@ pub async fn local_auth(
     Ok((
         jar.add(cookie),
         Json(AuthResponse {
-            is_admin: user.roles.contains(&ADMINS_ROLE.to_owned()),
+            is_admin: can_administrate(&user_token.roles, &config),
             xsrf_token: user_token.xsrf_token,
         }),
     ))
@@ -340,31 +497,42 @@ pub(crate) fn create_user_cookie(
     addr: SocketAddr,
     reader: OptionalMaxMindReader,
     user: &User,
+) -> Result<Cookie<'static>, ErrResponse> {
+    let cookie = session_cookie(user_token, host, config)?;
+    info!(
+        "AUTHENTICATION SUCCESS for {} from {}",
+        user.login,
+        city_from_ip(addr, reader)
+    );
+    Ok(cookie)
+}
+
+/// Build the session cookie holding the user token
+pub(crate) fn session_cookie(
+    user_token: &UserToken,
+    host: &Host,
+    config: &Config,
 ) -> Result<Cookie<'static>, ErrResponse> {
     let encoded = serde_json::to_string(user_token)
         .map_err(|_| ErrResponse::S500("could not encode user"))?;
-    let cookie = Cookie::build((AUTH_COOKIE, encoded))
+    Ok(Cookie::build((AUTH_COOKIE, encoded))
         .domain(host.hostname().to_owned())
         .path("/")
         .same_site(axum_extra::extract::cookie::SameSite::Lax)
         .secure(config.tls_mode.is_secure())
         .max_age(Duration::days(config.session_duration_days.unwrap_or(1)))
         .http_only(true)
-        .build();
-    info!(
-        "AUTHENTICATION SUCCESS for {} from {}",
-        user.login,
-        city_from_ip(addr, reader)
-    );
-    Ok(cookie)
+        .build())
 }
 
+/// Check the credentials of a local user, giving back the user, its token and,
+/// if the stored hash is outdated, the password hashed with the current Argon2 parameters
 pub fn authenticate_local_user(
     config: &Config,
     payload: LocalAuth,
     reader: OptionalMaxMindReader,
     addr: SocketAddr,
-) -> Result<(&User, UserToken), (StatusCode, &'static str)> {
+) -> Result<(&User, UserToken, Option<String>), (StatusCode, &'static str)> {
     let user = config
         .users
         .iter()
@@ -379,59 +547,125 @@ pub fn authenticate_local_user(
             (e, "user does not exist")
         })?;
 
-    // Check if the given password is correct
-    let parsed_hash = PasswordHash::new(&user.password).map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "could not compute password hash",
-        )
-    })?;
-    Argon2::default()
-        .verify_password(payload.password.as_bytes(), &parsed_hash)
-        .map_err(|_| {
+    if user.is_expired(OffsetDateTime::now_utc().unix_timestamp()) {
+        info!(
+            "AUTHENTICATION ERROR for {} from {} : account is expired",
+            user.login,
+            city_from_ip(addr, reader)
+        );
+        return Err((StatusCode::UNAUTHORIZED, "user account is expired"));
+    }
+
+    if user.disabled {
+        info!(
+            "AUTHENTICATION ERROR for {} from {} : account is disabled",
+            user.login,
+            city_from_ip(addr, reader)
+        );
+        return Err((StatusCode::UNAUTHORIZED, "user account is disabled"));
+    }
+
+    // Check if the given password is correct, users provisioned without a password cannot log in locally
+    let verification = if user.password.is_empty() {
+        Verification::Invalid
+    } else {
+        password::verify(&payload.password, &user.password, &config.argon2).map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "could not compute password hash",
+            )
+        })?
+    };
+    let upgraded_hash = match verification {
+        Verification::Valid => None,
+        // The password is correct but stored with a foreign scheme or outdated parameters : rehash it
+        Verification::ValidNeedsRehash => password::rehash(&payload.password, &config.argon2),
+        Verification::Invalid => {
             info!(
                 "AUTHENTICATION ERROR for {} from {} : password does not match",
                 user.login,
                 city_from_ip(addr, reader)
             );
-            (StatusCode::UNAUTHORIZED, "user is not authorized")
-        })?;
+            return Err((StatusCode::UNAUTHORIZED, "user is not authorized"));
+        }
+    };
 
     // Create a token payload from the user
     let user_token = user_to_token(user, config);
-    Ok((user, user_token))
+    Ok((user, user_token, upgraded_hash))
+}
+
+/// Store a password hash upgraded at login, unless the password was changed in the meantime
+async fn persist_upgraded_hash(
+    config_file: &str,
+    login: &str,
+    previous_hash: &str,
+    upgraded_hash: String,
+) {
+    let Ok(mut config) = Config::from_file(config_file).await else {
+        error!("could not read configuration to upgrade the password hash of {login}");
+        return;
+    };
+    let Some(user) = config
+        .users
+        .iter_mut()
+        .find(|u| u.login == login && u.password == previous_hash)
+    else {
+        return;
+    };
+    user.password = upgraded_hash;
+    match config.to_file(config_file).await {
+        Ok(_) => info!("PASSWORD HASH UPGRADED for {login}"),
+        Err(e) => error!("could not save the upgraded password hash of {login}: {e}"),
+    }
 }
 
 pub(crate) fn user_to_token(user: &User, config: &Config) -> UserToken {
+    let session_expires = (OffsetDateTime::now_utc()
+        + Duration::days(config.session_duration_days.unwrap_or(1)))
+    .unix_timestamp();
     UserToken {
         login: user.login.clone(),
-        roles: user.roles.clone(),
+        roles: resolve_roles(&user.login, &user.roles, config),
         xsrf_token: Some(random_string(16)),
         share: None,
-        expires: (OffsetDateTime::now_utc()
-            + Duration::days(config.session_duration_days.unwrap_or(1)))
-        .unix_timestamp(),
+        // A session cannot outlive the account
+        expires: user
+            .expires_at
+            .map_or(session_expires, |e| e.min(session_expires)),
         info: user.info.clone(),
+        issued_at: OffsetDateTime::now_utc().unix_timestamp(),
+        impersonator: None,
     }
 }
 
 pub async fn get_users(
     State(config_file): State<ConfigFile>,
-    _admin: AdminToken,
+    admin: ScopedAdminToken,
 ) -> Result<Json<Vec<User>>, (StatusCode, &'static str)> {
     let config = config_or_error(&config_file).await?;
-    // Return all the users as Json
-    Ok(Json(config.users))
+    // Return all the users visible by the admin as Json
+    Ok(Json(
+        config
+            .users
+            .into_iter()
+            .filter(|u| admin.user_visible(u))
+            .collect(),
+    ))
 }
 
 pub async fn delete_user(
     State(config_file): State<ConfigFile>,
-    _admin: AdminToken,
+    admin: ScopedAdminToken,
     Path(user_login): Path<String>,
 ) -> Result<impl IntoResponse, impl IntoResponse> {
     let mut config = config_or_error(&config_file).await?;
     // Find the user
-    if let Some(pos) = config.users.iter().position(|u| u.login == user_login) {
+    if let Some(pos) = config
+        .users
+        .iter()
+        .position(|u| u.login == user_login && admin.user_allowed(u, &config))
+    {
         // It is an existing user, delete it
         config.users.remove(pos);
     } else {
@@ -449,16 +683,27 @@ pub async fn delete_user(
 pub async fn add_user(
     State(config_file): State<ConfigFile>,
     State(config): State<ConfigState>,
-    _admin: AdminToken,
+    admin: ScopedAdminToken,
     Json(mut payload): Json<User>,
 ) -> Result<impl IntoResponse, impl IntoResponse> {
     // Clone the config
     let mut config = (*config).clone();
+    // A delegated admin can only give the roles of its scope, to users it already manages
+    if !admin.is_global()
+        && (!admin.user_visible(&payload)
+            || !admin.user_allowed(&payload, &config)
+            || config
+                .users
+                .iter()
+                .any(|u| u.login == payload.login && !admin.user_allowed(u, &config)))
+    {
+        return Err((StatusCode::FORBIDDEN, "user is outside of the admin scope"));
+    }
     // Find the user
     if let Some(user) = config.users.iter_mut().find(|u| u.login == payload.login) {
         // It is an existing user, we only hash the password if it is not empty
         if !payload.password.is_empty() {
-            hash_password(&mut payload)
+            hash_password(&mut payload, &config.argon2)
                 .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "password hash failed"))?;
         } else {
             payload.password = user.password.clone();
@@ -469,7 +714,7 @@ pub async fn add_user(
         if payload.password.is_empty() {
             return Err((StatusCode::NOT_ACCEPTABLE, "password is required"));
         }
-        hash_password(&mut payload)
+        hash_password(&mut payload, &config.argon2)
             .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "password hash failed"))?;
         config.users.push(payload);
     }
@@ -481,23 +726,104 @@ pub async fn add_user(
     Ok((StatusCode::CREATED, "user created or updated successfully"))
 }
 
-pub(crate) fn hash_password(payload: &mut User) -> Result<(), argon2::password_hash::Error> {
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    payload.password = argon2
-        .hash_password(payload.password.trim().as_bytes(), &salt)?
-        .to_string();
+pub(crate) fn hash_password(
+    payload: &mut User,
+    argon2: &Argon2Config,
+) -> Result<(), argon2::password_hash::Error> {
+    payload.password = password::hash(&payload.password, argon2)?;
     Ok(())
 }
 
-pub async fn whoami(token: UserToken) -> Json<User> {
+#[derive(Deserialize)]
+pub struct HtpasswdImport {
+    #[serde(default)]
+    roles: Option<String>,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct HtpasswdImportReport {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub skipped: Vec<SkippedLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SkippedLine {
+    pub line: usize,
+    pub reason: String,
+}
+
+/// Import the users of an htpasswd file : existing users get their password hash replaced,
+/// new users are created with the roles given as a comma separated query parameter.
+/// The imported hashes are upgraded to Argon2 on the first successful login.
+pub async fn import_htpasswd(
+    State(config_file): State<ConfigFile>,
+    _admin: AdminToken,
+    Query(params): Query<HtpasswdImport>,
+    body: String,
+) -> Result<Json<HtpasswdImportReport>, (StatusCode, &'static str)> {
+    let mut config = config_or_error(&config_file).await?;
+    let roles: Vec<String> = params
+        .roles
+        .unwrap_or_default()
+        .split(',')
+        .map(|r| r.trim().to_owned())
+        .filter(|r| !r.is_empty())
+        .collect();
+    let mut report = HtpasswdImportReport::default();
+    for (line, entry) in password::parse_htpasswd(&body) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(reason) => {
+                report.skipped.push(SkippedLine {
+                    line,
+                    reason: reason.to_owned(),
+                });
+                continue;
+            }
+        };
+        if let Some(user) = config.users.iter_mut().find(|u| u.login == entry.login) {
+            user.password = entry.hash;
+            report.updated.push(entry.login);
+        } else {
+            report.created.push(entry.login.clone());
+            config.users.push(User {
+                login: entry.login,
+                password: entry.hash,
+                roles: roles.clone(),
+                ..Default::default()
+            });
+        }
+    }
+
+    config
+        .to_file_or_internal_server_error(&config_file)
+        .await?;
+
+    Ok(Json(report))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WhoAmI {
+    #[serde(flatten)]
+    pub user: User,
+    /// Login of the admin impersonating the user, if any
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub impersonated_by: Option<String>,
+}
+
+pub async fn whoami(token: UserToken) -> Json<WhoAmI> {
     let user = User {
         login: token.login,
         password: REDACTED.to_owned(),
         roles: token.roles,
         info: token.info,
+        ..Default::default()
     };
-    Json(user)
+    Json(WhoAmI {
+        user,
+        impersonated_by: token.impersonator.map(|i| i.login),
+    })
 }
 
 pub async fn list_services(
@@ -535,6 +861,7 @@ pub async fn list_services(
 #[cfg(test)]
 mod user_tests {
     use super::UserToken;
+    use crate::auth::{share::Share, share_registry::ShareRegistry};
     use time::{Duration, OffsetDateTime};
 
     #[test]
@@ -554,4 +881,28 @@ mod user_tests {
         };
         assert!(user.check_expires().is_err());
     }
+
+    #[tokio::test]
+    async fn test_check_share() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("atrium.yaml");
+        let shares = ShareRegistry::for_config_file(config_file.to_str().unwrap())
+            .await
+            .unwrap();
+        // The links given before the registry have no id, they stay valid until they expire
+        let legacy = UserToken {
+            share: Some(Share::default()),
+            expires: OffsetDateTime::now_utc().unix_timestamp() + 3600,
+            ..Default::default()
+        };
+        assert!(legacy.clone().check_share(&shares).is_ok());
+        let revoked = UserToken {
+            share: Some(Share {
+                id: Some("revoked".to_owned()),
+                ..Default::default()
+            }),
+            ..legacy
+        };
+        assert!(revoked.check_share(&shares).is_err());
+    }
 }
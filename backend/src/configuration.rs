@@ -1,7 +1,13 @@
 use crate::{
     apps::{App, AppWithUri},
     appstate::{ConfigMap, ConfigState},
-    auth::User,
+    auth::{
+        User,
+        client_cert::ClientCertIdentity,
+        delegation::AdminScope,
+        invitation::ConsumedInvitation,
+        roles::{Group, RoleHierarchy},
+    },
     davs::model::Dav,
     errors::Error,
     extract,
@@ -52,6 +58,29 @@ pub struct OpenIdConfig {
     pub insecure_skip_verify: bool,
 }
 
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct ScimConfig {
+    /// Bearer token the identity provider must present to use the SCIM API
+    #[serde(deserialize_with = "string_trim")]
+    pub bearer_token: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct PreviousCookieKey {
+    pub key: String,
+    /// Unix timestamp after which the key cannot decrypt the cookies and tokens anymore
+    pub expires_at: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct ClientCertConfig {
+    /// PEM file of the certificate authorities issuing the client certificates
+    #[serde(deserialize_with = "string_trim")]
+    pub ca_file: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub identities: Vec<ClientCertIdentity>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
 pub enum TlsMode {
     #[default]
@@ -109,6 +138,103 @@ impl Default for JailConfig {
     }
 }
 
+/// Security of the connection to the SMTP relay
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
+pub enum SmtpTls {
+    /// Plain connection, for a trusted local relay
+    No,
+    #[default]
+    StartTls,
+    /// Implicit TLS (SMTPS)
+    Tls,
+}
+
+/// Relay used to send the e-mails, such as the share access notifications
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct SmtpConfig {
+    #[serde(deserialize_with = "string_trim")]
+    pub host: String,
+    /// Defaults to the usual port of the TLS mode
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "option_string_trim"
+    )]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub password: Option<String>,
+    /// Sender of the e-mails, as `address` or `Name <address>`
+    #[serde(deserialize_with = "string_trim")]
+    pub from: String,
+}
+
+/// Proof of work required by the local login once an IP or a login has failed recently
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct LoginChallengeConfig {
+    /// Number of failures, counted over the jail find time, before requiring a challenge
+    #[serde(default = "default_challenge_after_failures")]
+    pub after_failures: u32,
+    /// Leading zero bits of the hash required for the first challenge, one more bit for each new failure
+    #[serde(default = "default_challenge_difficulty")]
+    pub difficulty: u32,
+    #[serde(default = "default_challenge_max_difficulty")]
+    pub max_difficulty: u32,
+}
+
+fn default_challenge_after_failures() -> u32 {
+    3
+}
+fn default_challenge_difficulty() -> u32 {
+    16
+}
+fn default_challenge_max_difficulty() -> u32 {
+    24
+}
+
+impl Default for LoginChallengeConfig {
+    fn default() -> Self {
+        Self {
+            after_failures: default_challenge_after_failures(),
+            difficulty: default_challenge_difficulty(),
+            max_difficulty: default_challenge_max_difficulty(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct Argon2Config {
+    #[serde(default = "default_argon2_memory_cost")]
+    pub memory_cost: u32, // KiB
+    #[serde(default = "default_argon2_time_cost")]
+    pub time_cost: u32, // iterations
+    #[serde(default = "default_argon2_parallelism")]
+    pub parallelism: u32, // lanes
+}
+
+fn default_argon2_memory_cost() -> u32 {
+    argon2::Params::DEFAULT_M_COST
+}
+fn default_argon2_time_cost() -> u32 {
+    argon2::Params::DEFAULT_T_COST
+}
+fn default_argon2_parallelism() -> u32 {
+    argon2::Params::DEFAULT_P_COST
+}
+
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Self {
+            memory_cost: default_argon2_memory_cost(),
+            time_cost: default_argon2_time_cost(),
+            parallelism: default_argon2_parallelism(),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
 pub struct Config {
     #[serde(default = "hostname", deserialize_with = "string_trim")]
@@ -135,6 +261,9 @@ pub struct Config {
         deserialize_with = "option_string_trim"
     )]
     pub cookie_key: Option<String>,
+    /// Former cookie keys, kept after a rotation to decrypt the existing sessions and tokens
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub previous_cookie_keys: Vec<PreviousCookieKey>,
     #[serde(default, skip_serializing_if = "is_default")]
     pub log_to_file: bool,
     #[serde(default, skip_serializing_if = "is_default")]
@@ -144,13 +273,37 @@ pub struct Config {
     #[serde(default, skip_serializing_if = "is_default")]
     pub openid_config: Option<OpenIdConfig>,
     #[serde(default, skip_serializing_if = "is_default")]
+    pub scim_config: Option<ScimConfig>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub client_cert_config: Option<ClientCertConfig>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub smtp_config: Option<SmtpConfig>,
+    #[serde(default, skip_serializing_if = "is_default")]
     pub jail: JailConfig,
     #[serde(default, skip_serializing_if = "is_default")]
+    pub login_challenge: Option<LoginChallengeConfig>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub argon2: Argon2Config,
+    /// Maximum number of resources returned by a PROPFIND with Depth: infinity, such requests are refused if unset
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub propfind_depth_infinity_limit: Option<usize>,
+    /// Flush the files uploaded to the davs to the disk before acknowledging them
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub fsync_uploads: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
     pub apps: Vec<App>,
     #[serde(default, skip_serializing_if = "is_default")]
     pub davs: Vec<Dav>,
     #[serde(default, skip_serializing_if = "is_default")]
     pub users: Vec<User>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub groups: Vec<Group>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub role_hierarchy: RoleHierarchy,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub admin_scopes: Vec<AdminScope>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub consumed_invitations: Vec<ConsumedInvitation>,
 }
 
 impl Config {
@@ -386,6 +539,16 @@ impl HostType {
         }
     }
 
+    pub fn require_client_cert(&self) -> bool {
+        match self {
+            HostType::ReverseApp(app) | HostType::SkipVerifyReverseApp(app) => {
+                app.inner.require_client_cert
+            }
+            HostType::Dav(_dav) => false,
+            HostType::StaticApp(app) => app.require_client_cert,
+        }
+    }
+
     pub fn inject_security_headers(&self) -> bool {
         match self {
             HostType::ReverseApp(app) => app.inner.inject_security_headers,
@@ -501,6 +664,9 @@ mod tests {
                 allow_symlinks: false,
                 roles: vec!["ADMINS".to_owned(), "USERS".to_owned()],
                 passphrase: Some("ABCD123".to_owned()),
+                tags: vec![],
+                trash: None,
+                versioning: None,
                 key: None,
             },
             Dav {
@@ -515,6 +681,9 @@ mod tests {
                 allow_symlinks: true,
                 roles: vec!["USERS".to_owned()],
                 passphrase: None,
+                tags: vec![],
+                trash: None,
+                versioning: None,
                 key: None,
             },
         ];
@@ -525,12 +694,18 @@ mod tests {
                 password: "password".to_owned(),
                 roles: vec!["ADMINS".to_owned()],
                 info: None,
+                expires_at: None,
+                sessions_valid_after: None,
+                disabled: false,
             },
             User {
                 login: "user".to_owned(),
                 password: "password".to_owned(),
                 roles: vec!["USERS".to_owned()],
                 info: None,
+                expires_at: None,
+                sessions_valid_after: None,
+                disabled: false,
             },
         ];
 
@@ -542,14 +717,26 @@ mod tests {
             tls_mode: TlsMode::No,
             letsencrypt_email: "foo@bar.com".to_owned(),
             cookie_key: None,
+            previous_cookie_keys: vec![],
             log_to_file: false,
             jail: Default::default(),
+            argon2: Default::default(),
             apps,
             davs,
             users,
+            groups: Default::default(),
+            role_hierarchy: Default::default(),
+            admin_scopes: Default::default(),
+            consumed_invitations: Default::default(),
             session_duration_days: None,
             onlyoffice_config: None,
             openid_config: None,
+            scim_config: None,
+            client_cert_config: None,
+            login_challenge: None,
+            smtp_config: None,
+            propfind_depth_infinity_limit: None,
+            fsync_uploads: false,
             single_proxy: false,
         };
 
@@ -0,0 +1,181 @@
+use super::{dav_file::DavFile, tus::UPLOADS_DIR};
+use axum::body::Body;
+use futures_util::TryStreamExt;
+use http_body_util::BodyExt;
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::{fs, io::AsyncWriteExt};
+use tokio_util::io::StreamReader;
+
+/// Path of the chunked uploads of the Nextcloud clients within a dav : `remote.php/dav/uploads/{user}/{transfer}/{chunk}`
+pub const CHUNKING_PATH: &str = "remote.php/dav/uploads";
+/// Folder of the chunks of the transfers in progress, within the uploads folder
+const CHUNKS_DIR: &str = "chunks";
+/// Virtual file of a transfer, moved to the destination of the upload to assemble the chunks
+pub const ASSEMBLY_FILE: &str = ".file";
+/// A transfer whose chunks were not written for this time is removed
+const TRANSFER_EXPIRATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A path of the chunked uploads : a transfer, or one of its chunks
+#[derive(Debug, PartialEq, Eq)]
+pub struct ChunkedPath {
+    /// Folder on disk holding the chunks of the transfer
+    pub transfer: PathBuf,
+    pub chunk: Option<String>,
+}
+
+impl ChunkedPath {
+    pub fn parse(path: &Path, directory: &str) -> Option<Self> {
+        let mut components = path
+            .strip_prefix(Path::new(directory).join(CHUNKING_PATH))
+            .ok()?
+            .components()
+            .map(|c| match c {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            });
+        // The user segment mirrors the Nextcloud layout, the transfers belong to the dav
+        components.next()??;
+        let transfer = components.next()??;
+        let chunk = match components.next() {
+            Some(chunk) => Some(chunk?.to_owned()),
+            None => None,
+        };
+        if components.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            transfer: chunks_dir(Path::new(directory)).join(transfer),
+            chunk,
+        })
+    }
+}
+
+fn chunks_dir(directory: &Path) -> PathBuf {
+    directory.join(UPLOADS_DIR).join(CHUNKS_DIR)
+}
+
+/// The chunks are numbered, they are assembled in the order of their numbers
+pub fn chunk_number(name: &str) -> Option<u64> {
+    name.parse().ok()
+}
+
+/// Write a chunk of a transfer, it only appears once completely received
+pub async fn write_chunk(
+    transfer: &Path,
+    name: &str,
+    body: Body,
+    key: Option<[u8; 32]>,
+) -> io::Result<()> {
+    let part = transfer.join(format!("{name}.part"));
+    let mut file = DavFile::create(&part, key).await?;
+    let body_stream = body.into_data_stream().map_err(io::Error::other);
+    let mut body_reader = StreamReader::new(body_stream);
+    if let Err(e) = tokio::io::copy(&mut body_reader, &mut file).await {
+        drop(fs::remove_file(&part).await);
+        return Err(e);
+    }
+    file.shutdown().await?;
+    fs::rename(part, transfer.join(name)).await
+}
+
+/// Assemble the chunks of a transfer into a file of the transfer folder, returns it with its length
+pub async fn assemble(transfer: &Path, key: Option<[u8; 32]>) -> io::Result<(PathBuf, u64)> {
+    let mut chunks = vec![];
+    let mut rd = fs::read_dir(transfer).await?;
+    while let Some(entry) = rd.next_entry().await? {
+        if let Some(number) = entry.file_name().to_str().and_then(chunk_number) {
+            chunks.push((number, entry.path()));
+        }
+    }
+    chunks.sort_unstable();
+    let assembled = transfer.join(ASSEMBLY_FILE);
+    let mut file = DavFile::create(&assembled, key).await?;
+    for (_, chunk) in chunks {
+        let mut chunk = DavFile::open(chunk, key).await?;
+        tokio::io::copy(&mut chunk, &mut file).await?;
+    }
+    file.shutdown().await?;
+    let len = file.len().await;
+    Ok((assembled, len))
+}
+
+/// Remove the transfers of a dav that were abandoned
+pub async fn clean_expired(directory: &Path) {
+    let Ok(mut rd) = fs::read_dir(chunks_dir(directory)).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = rd.next_entry().await {
+        // Writing a chunk updates the modification time of its transfer folder
+        if let Ok(meta) = entry.metadata().await
+            && meta
+                .modified()
+                .ok()
+                .and_then(|m| SystemTime::now().duration_since(m).ok())
+                .is_some_and(|age| age > TRANSFER_EXPIRATION)
+        {
+            drop(fs::remove_dir_all(entry.path()).await);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_chunked_path() {
+        let directory = "./data/dir1";
+        assert_eq!(
+            ChunkedPath::parse(
+                Path::new("./data/dir1/remote.php/dav/uploads/admin/transfer-1/00002"),
+                directory
+            ),
+            Some(ChunkedPath {
+                transfer: Path::new("./data/dir1/.atrium_uploads/chunks/transfer-1").to_path_buf(),
+                chunk: Some("00002".to_owned())
+            })
+        );
+        assert_eq!(
+            ChunkedPath::parse(
+                Path::new("./data/dir1/remote.php/dav/uploads/admin"),
+                directory
+            ),
+            None
+        );
+        assert_eq!(
+            ChunkedPath::parse(Path::new("./data/dir1/dira/file1"), directory),
+            None
+        );
+        assert_eq!(chunk_number("00002"), Some(2));
+        assert_eq!(chunk_number(ASSEMBLY_FILE), None);
+    }
+
+    #[tokio::test]
+    async fn test_chunks_assembled() {
+        let dir = tempfile::tempdir().expect("temporary directory");
+        let key = Some([3; 32]);
+        let transfer = dir.path().join("transfer");
+        fs::create_dir(&transfer).await.expect("transfer created");
+        // The chunks are sent in parallel and may arrive in any order
+        for (name, content) in [("10", "ghi"), ("2", "def"), ("1", "abc")] {
+            write_chunk(&transfer, name, Body::from(content), key)
+                .await
+                .expect("chunk written");
+        }
+        let (assembled, len) = assemble(&transfer, key).await.expect("chunks assembled");
+        assert_eq!(len, 9);
+        let mut content = String::new();
+        DavFile::open(&assembled, key)
+            .await
+            .expect("encrypted file")
+            .read_to_string(&mut content)
+            .await
+            .expect("file read");
+        assert_eq!(content, "abcdefghi");
+    }
+}
@@ -0,0 +1,100 @@
+use dashmap::{DashMap, DashSet};
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::{Arc, LazyLock},
+};
+
+/// Files uploaded during each file request session -> (session expiration, uploaded paths)
+static SESSIONS: LazyLock<DashMap<String, (i64, Arc<DashSet<PathBuf>>)>> =
+    LazyLock::new(DashMap::new);
+
+/// The restrictions of an upload only share, given to the webdav server by the dav middleware.
+/// The recipient can upload files and create folders, but only sees what was uploaded during the session.
+#[derive(Debug, Clone, Default)]
+pub struct FileRequest {
+    /// Path of the share within the dav, as given in the share
+    pub root: PathBuf,
+    /// Prefix added to the name of the uploaded files
+    pub prefix: Option<String>,
+    /// Maximum size of an uploaded file, in bytes
+    pub max_upload_size: Option<u64>,
+    /// Paths on disk of what was uploaded during the session
+    pub uploaded: Arc<DashSet<PathBuf>>,
+}
+
+impl FileRequest {
+    /// Path on disk of the shared folder
+    pub fn root_path(&self, directory: &str) -> PathBuf {
+        Path::new(directory).join(
+            self.root
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .collect::<PathBuf>(),
+        )
+    }
+
+    /// The shared folder and what was uploaded during the session (with its parent folders) can be seen
+    pub fn is_visible(&self, path: &Path, directory: &str) -> bool {
+        path == self.root_path(directory) || self.uploaded.iter().any(|u| u.starts_with(path))
+    }
+
+    pub fn is_uploaded(&self, path: &Path) -> bool {
+        self.uploaded.contains(path)
+    }
+
+    /// Where an uploaded file is written, with the prefix added to its name
+    pub fn upload_path(&self, path: &Path, directory: &str) -> PathBuf {
+        match (&self.prefix, path.file_name()) {
+            (Some(prefix), Some(name)) if path != self.root_path(directory) => {
+                path.with_file_name(format!("{prefix}{}", name.to_string_lossy()))
+            }
+            _ => path.to_path_buf(),
+        }
+    }
+}
+
+/// Get the uploads of a session, the expired sessions are forgotten
+pub fn session_uploads(session: &str, expires: i64, now: i64) -> Arc<DashSet<PathBuf>> {
+    SESSIONS.retain(|_, (e, _)| *e >= now);
+    Arc::clone(
+        &SESSIONS
+            .entry(session.to_owned())
+            .or_insert_with(|| (expires, Arc::new(DashSet::new())))
+            .1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_request() {
+        let file_request = FileRequest {
+            root: PathBuf::from("/inbox"),
+            prefix: Some("client_".to_owned()),
+            uploaded: session_uploads("test_file_request", 2000, 1000),
+            ..Default::default()
+        };
+        let root = file_request.root_path("/data");
+        assert_eq!(root, PathBuf::from("/data/inbox"));
+        assert_eq!(
+            file_request.upload_path(&root.join("report.pdf"), "/data"),
+            PathBuf::from("/data/inbox/client_report.pdf")
+        );
+        // The shared folder itself is never renamed
+        assert_eq!(file_request.upload_path(&root, "/data"), root);
+        // Only the shared folder is visible before any upload
+        assert!(file_request.is_visible(&root, "/data"));
+        assert!(!file_request.is_visible(&root.join("secret.txt"), "/data"));
+        file_request
+            .uploaded
+            .insert(root.join("folder/client_report.pdf"));
+        assert!(file_request.is_visible(&root.join("folder"), "/data"));
+        assert!(file_request.is_visible(&root.join("folder/client_report.pdf"), "/data"));
+        assert!(!file_request.is_visible(&root.join("folder/other.pdf"), "/data"));
+        // The uploads are kept for the session, until it expires
+        assert_eq!(session_uploads("test_file_request", 2000, 1500).len(), 1);
+        assert!(session_uploads("test_file_request", 3000, 2001).is_empty());
+    }
+}
@@ -92,3 +92,183 @@ impl Header for Overwrite {
         values.extend(std::iter::once(HeaderValue::from_static(value)));
     }
 }
+
+static IF: HeaderName = HeaderName::from_static("if");
+
+/// If: header (RFC 4918 section 10.4), true if any of its lists of conditions is true.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct If(pub Vec<IfList>);
+
+/// Conditions that must all be true, on the tagged resource or on the request URI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfList {
+    pub resource: Option<String>,
+    pub conditions: Vec<IfCondition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IfCondition {
+    pub not: bool,
+    pub item: IfItem,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfItem {
+    StateToken(String),
+    ETag(String),
+}
+
+impl If {
+    /// Lock tokens submitted with the request
+    pub fn tokens(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .flat_map(|list| &list.conditions)
+            .filter_map(|condition| match &condition.item {
+                IfItem::StateToken(token) if !condition.not => Some(token.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let mut lists = vec![];
+        let mut resource = None;
+        let mut rest = value.trim_start();
+        while !rest.is_empty() {
+            if let Some(r) = rest.strip_prefix('<') {
+                let (uri, r) = r.split_once('>')?;
+                resource = Some(uri.to_owned());
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix('(') {
+                let (conditions, r) = parse_conditions(r)?;
+                lists.push(IfList {
+                    resource: resource.clone(),
+                    conditions,
+                });
+                rest = r;
+            } else {
+                return None;
+            }
+            rest = rest.trim_start();
+        }
+        (!lists.is_empty()).then_some(If(lists))
+    }
+}
+
+// helper : parse the conditions of a list, up to its closing parenthesis
+fn parse_conditions(mut rest: &str) -> Option<(Vec<IfCondition>, &str)> {
+    let mut conditions = vec![];
+    loop {
+        rest = rest.trim_start();
+        if let Some(r) = rest.strip_prefix(')') {
+            return (!conditions.is_empty()).then_some((conditions, r));
+        }
+        let not = if let Some(r) = rest.strip_prefix("Not") {
+            rest = r.trim_start();
+            true
+        } else {
+            false
+        };
+        let item = if let Some(r) = rest.strip_prefix('<') {
+            let (token, r) = r.split_once('>')?;
+            rest = r;
+            IfItem::StateToken(token.to_owned())
+        } else if let Some(r) = rest.strip_prefix('[') {
+            let (etag, r) = r.split_once(']')?;
+            rest = r;
+            IfItem::ETag(etag.to_owned())
+        } else {
+            return None;
+        };
+        conditions.push(IfCondition { not, item });
+    }
+}
+
+impl Header for If {
+    fn name() -> &'static HeaderName {
+        &IF
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = one(values)?.to_str().map_err(|_| invalid())?;
+        If::parse(value).ok_or_else(invalid)
+    }
+
+    fn encode<E>(&self, values: &mut E)
+    where
+        E: Extend<HeaderValue>,
+    {
+        let value = self
+            .0
+            .iter()
+            .map(|list| {
+                let conditions = list
+                    .conditions
+                    .iter()
+                    .map(|condition| {
+                        let not = if condition.not { "Not " } else { "" };
+                        match &condition.item {
+                            IfItem::StateToken(token) => format!("{not}<{token}>"),
+                            IfItem::ETag(etag) => format!("{not}[{etag}]"),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                match &list.resource {
+                    Some(resource) => format!("<{resource}> ({conditions})"),
+                    None => format!("({conditions})"),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            values.extend(std::iter::once(value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_if_header() {
+        let value = HeaderValue::from_static(
+            r#"<http://files.atrium.io/dir/file> (<opaquelocktoken:a> ["1-2"]) (Not <DAV:no-lock>)"#,
+        );
+        let header = If::decode(&mut std::iter::once(&value)).expect("valid if header");
+        assert_eq!(header.0.len(), 2);
+        assert!(
+            header
+                .0
+                .iter()
+                .all(|l| l.resource.as_deref() == Some("http://files.atrium.io/dir/file"))
+        );
+        assert_eq!(
+            header.0.first().map(|l| &l.conditions),
+            Some(&vec![
+                IfCondition {
+                    not: false,
+                    item: IfItem::StateToken("opaquelocktoken:a".to_owned())
+                },
+                IfCondition {
+                    not: false,
+                    item: IfItem::ETag(r#""1-2""#.to_owned())
+                }
+            ])
+        );
+        assert_eq!(header.tokens(), vec!["opaquelocktoken:a".to_owned()]);
+        let mut encoded = vec![];
+        header.encode(&mut encoded);
+        assert_eq!(If::decode(&mut encoded.iter()).ok(), Some(header));
+        // Malformed headers are rejected
+        for malformed in ["(<opaquelocktoken:a>", "()", "(Not)", "opaquelocktoken:a"] {
+            let value = HeaderValue::from_static(malformed);
+            assert!(If::decode(&mut std::iter::once(&value)).is_err());
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use super::properties;
+use chrono::{DateTime, Duration, Utc};
+use quick_xml::{NsReader, escape::escape, events::Event};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+use uuid::Uuid;
+
+pub const LOCK_TIMEOUT: i64 = 24 * 60 * 60; // 24 hours in seconds
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockScope {
+    Exclusive,
+    Shared,
+}
+
+/// A write lock on a resource, and on its members if its depth is infinity
+#[derive(Debug, Clone)]
+pub struct Lock {
+    pub token: String,
+    /// Path on disk of the locked resource
+    pub root: PathBuf,
+    /// URL path of the locked resource, as requested by the client
+    pub href: String,
+    pub scope: LockScope,
+    pub infinite: bool,
+    /// XML content of the owner element given by the client, as rewritten by `properties::read_content`
+    pub owner: Option<String>,
+    pub timeout: i64,
+    pub expires_at: DateTime<Utc>,
+    /// The resource was created empty by the lock and was not written yet
+    pub lock_null: bool,
+}
+
+impl Lock {
+    pub fn new(
+        root: PathBuf,
+        href: String,
+        info: LockRequest,
+        infinite: bool,
+        timeout: i64,
+    ) -> Self {
+        Self {
+            token: format!("opaquelocktoken:{}", Uuid::new_v4()),
+            root,
+            href,
+            scope: info.scope,
+            infinite,
+            owner: info.owner,
+            timeout,
+            expires_at: Utc::now() + Duration::seconds(timeout),
+            lock_null: false,
+        }
+    }
+
+    /// The lock applies to the resource itself
+    pub fn covers(&self, path: &Path) -> bool {
+        path == self.root || (self.infinite && path.starts_with(&self.root))
+    }
+
+    fn conflicts_with(&self, other: &Lock) -> bool {
+        (self.scope == LockScope::Exclusive || other.scope == LockScope::Exclusive)
+            && (self.covers(&other.root) || other.covers(&self.root))
+    }
+
+    /// The activelock element describing the lock
+    pub fn to_xml(&self) -> String {
+        let scope = match self.scope {
+            LockScope::Exclusive => "exclusive",
+            LockScope::Shared => "shared",
+        };
+        let depth = if self.infinite { "infinity" } else { "0" };
+        let owner = self
+            .owner
+            .as_ref()
+            .map(|owner| format!("<D:owner>{owner}</D:owner>"))
+            .unwrap_or_default();
+        format!(
+            r#"<D:activelock>
+<D:locktype><D:write/></D:locktype>
+<D:lockscope><D:{scope}/></D:lockscope>
+<D:depth>{depth}</D:depth>{owner}
+<D:timeout>Second-{}</D:timeout>
+<D:locktoken><D:href>{}</D:href></D:locktoken>
+<D:lockroot><D:href>{}</D:href></D:lockroot>
+</D:activelock>"#,
+            self.timeout,
+            self.token,
+            escape(&self.href)
+        )
+    }
+}
+
+/// What the client asked for in the lockinfo body of a LOCK request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockRequest {
+    pub scope: LockScope,
+    pub owner: Option<String>,
+}
+
+impl Default for LockRequest {
+    fn default() -> Self {
+        Self {
+            scope: LockScope::Exclusive,
+            owner: None,
+        }
+    }
+}
+
+impl LockRequest {
+    pub fn parse(xml_body: &str) -> Result<Self, quick_xml::Error> {
+        let mut info = LockRequest::default();
+        let mut reader = NsReader::from_str(xml_body);
+        loop {
+            match reader.read_event()? {
+                Event::Eof => break,
+                Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"shared" => {
+                    info.scope = LockScope::Shared;
+                }
+                Event::Start(e) if e.local_name().as_ref() == b"owner" => {
+                    let owner = properties::read_content(&mut reader)?;
+                    info.owner = Some(owner.trim().to_owned());
+                }
+                _ => (),
+            }
+        }
+        Ok(info)
+    }
+}
+
+/// The locks held on the files served by the webdav server
+#[derive(Debug, Default)]
+pub struct LockManager {
+    locks: Mutex<Vec<Lock>>,
+}
+
+impl LockManager {
+    fn locks(&self) -> MutexGuard<'_, Vec<Lock>> {
+        self.locks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Forget the expired locks, returning the lock-null resources that were never written
+    pub fn clean_expired(&self) -> Vec<PathBuf> {
+        let now = Utc::now();
+        let mut lock_nulls = vec![];
+        self.locks().retain(|lock| {
+            if lock.expires_at > now {
+                return true;
+            }
+            if lock.lock_null {
+                lock_nulls.push(lock.root.clone());
+            }
+            false
+        });
+        lock_nulls
+    }
+
+    /// Locks applying to a resource : its own and the depth infinity locks of its ancestors
+    pub fn covering(&self, path: &Path) -> Vec<Lock> {
+        let now = Utc::now();
+        self.locks()
+            .iter()
+            .filter(|lock| lock.expires_at > now && lock.covers(path))
+            .cloned()
+            .collect()
+    }
+
+    /// Find a lock preventing to write a resource, with its members if recursive,
+    /// and the membership of its parent collection if it is created or removed.
+    /// A lock does not prevent the writing if its token was submitted, or if it is shared and the token of another shared lock was.
+    pub fn check_write(
+        &self,
+        path: &Path,
+        recursive: bool,
+        membership: bool,
+        tokens: &[String],
+    ) -> Result<(), Lock> {
+        let now = Utc::now();
+        let locks = self.locks();
+        let affecting = locks
+            .iter()
+            .filter(|lock| {
+                lock.expires_at > now
+                    && (lock.covers(path)
+                        || (recursive && lock.root.starts_with(path))
+                        || (membership && path.parent() == Some(lock.root.as_path())))
+            })
+            .collect::<Vec<_>>();
+        let holds_shared = affecting
+            .iter()
+            .any(|lock| lock.scope == LockScope::Shared && tokens.contains(&lock.token));
+        match affecting.into_iter().find(|lock| {
+            !tokens.contains(&lock.token) && !(holds_shared && lock.scope == LockScope::Shared)
+        }) {
+            Some(lock) => Err(lock.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Add a lock, unless it conflicts with an existing one
+    pub fn lock(&self, lock: Lock) -> Result<Lock, Lock> {
+        let now = Utc::now();
+        let mut locks = self.locks();
+        if let Some(conflict) = locks
+            .iter()
+            .find(|l| l.expires_at > now && l.conflicts_with(&lock))
+        {
+            return Err(conflict.clone());
+        }
+        locks.push(lock.clone());
+        Ok(lock)
+    }
+
+    /// Extend the timeout of a lock applying to a resource, given one of its submitted tokens
+    pub fn refresh(&self, path: &Path, tokens: &[String], timeout: i64) -> Option<Lock> {
+        let now = Utc::now();
+        let mut locks = self.locks();
+        let lock = locks.iter_mut().find(|lock| {
+            lock.expires_at > now && lock.covers(path) && tokens.contains(&lock.token)
+        })?;
+        lock.timeout = timeout;
+        lock.expires_at = now + Duration::seconds(timeout);
+        Some(lock.clone())
+    }
+
+    /// Remove a lock applying to a resource, returns None if the token does not match such a lock
+    pub fn unlock(&self, path: &Path, token: &str) -> Option<Lock> {
+        let mut locks = self.locks();
+        let index = locks
+            .iter()
+            .position(|lock| lock.token == token && lock.covers(path))?;
+        Some(locks.remove(index))
+    }
+
+    /// The resource was written : a lock-null resource becomes a regular locked resource
+    pub fn written(&self, path: &Path) {
+        for lock in self.locks().iter_mut().filter(|lock| lock.root == path) {
+            lock.lock_null = false;
+        }
+    }
+
+    /// Forget the locks of a deleted or moved resource and of its members
+    pub fn remove_within(&self, path: &Path) {
+        self.locks().retain(|lock| !lock.root.starts_with(path));
+    }
+
+    /// The lockdiscovery property of a resource
+    pub fn lockdiscovery(&self, path: &Path) -> String {
+        let active = self
+            .covering(path)
+            .iter()
+            .map(Lock::to_xml)
+            .collect::<String>();
+        format!("<D:lockdiscovery>{active}</D:lockdiscovery>")
+    }
+}
+
+/// The supportedlock property of every resource
+pub const SUPPORTED_LOCK: &str = "<D:supportedlock>\
+<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>\
+<D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>\
+</D:supportedlock>";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_lock(root: &str, scope: LockScope, infinite: bool) -> Lock {
+        Lock::new(
+            PathBuf::from(root),
+            root.to_owned(),
+            LockRequest { scope, owner: None },
+            infinite,
+            60,
+        )
+    }
+
+    #[test]
+    fn test_lock_conflicts() {
+        let manager = LockManager::default();
+        let dir = manager
+            .lock(new_lock("/data/dir", LockScope::Exclusive, true))
+            .expect("first lock");
+        // The members of a depth infinity locked collection cannot be locked
+        assert!(
+            manager
+                .lock(new_lock("/data/dir/file", LockScope::Exclusive, false))
+                .is_err()
+        );
+        // Nor its parent with depth infinity, but it can with depth 0
+        assert!(
+            manager
+                .lock(new_lock("/data", LockScope::Shared, true))
+                .is_err()
+        );
+        let parent = manager
+            .lock(new_lock("/data", LockScope::Shared, false))
+            .expect("depth 0 lock on the parent");
+        assert!(
+            manager
+                .lock(new_lock("/data", LockScope::Shared, false))
+                .is_ok()
+        );
+        assert!(
+            manager
+                .lock(new_lock("/data", LockScope::Exclusive, false))
+                .is_err()
+        );
+
+        // Writing a member needs the token of the collection lock
+        let file = Path::new("/data/dir/file");
+        assert_eq!(
+            manager
+                .check_write(file, false, false, &[])
+                .map_err(|l| l.token),
+            Err(dir.token.clone())
+        );
+        assert!(
+            manager
+                .check_write(file, false, false, std::slice::from_ref(&dir.token))
+                .is_ok()
+        );
+        // Deleting the collection also changes the membership of its parent, any shared lock token will do
+        assert!(
+            manager
+                .check_write(
+                    Path::new("/data/dir"),
+                    true,
+                    true,
+                    std::slice::from_ref(&dir.token)
+                )
+                .is_err()
+        );
+        assert!(
+            manager
+                .check_write(
+                    Path::new("/data/dir"),
+                    true,
+                    true,
+                    &[dir.token.clone(), parent.token.clone()]
+                )
+                .is_ok()
+        );
+        // Locks within a collection prevent its deletion
+        assert!(
+            manager
+                .check_write(Path::new("/"), true, false, &[])
+                .is_err()
+        );
+        assert!(
+            manager
+                .check_write(Path::new("/other"), true, true, &[])
+                .is_ok()
+        );
+
+        assert_eq!(
+            manager
+                .lockdiscovery(file)
+                .matches("<D:activelock>")
+                .count(),
+            1
+        );
+        assert!(
+            manager
+                .refresh(file, &[parent.token.clone()], 120)
+                .is_none()
+        );
+        assert_eq!(
+            manager
+                .refresh(file, std::slice::from_ref(&dir.token), 120)
+                .map(|l| l.timeout),
+            Some(120)
+        );
+        assert!(manager.unlock(file, &parent.token).is_none());
+        assert!(manager.unlock(file, &dir.token).is_some());
+        manager.remove_within(Path::new("/data"));
+        assert!(manager.covering(Path::new("/data")).is_empty());
+    }
+
+    #[test]
+    fn test_lock_request() {
+        let info = LockRequest::parse(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:lockinfo xmlns:D="DAV:">
+  <D:lockscope><D:shared/></D:lockscope>
+  <D:locktype><D:write/></D:locktype>
+  <D:owner><D:href>mailto:admin@atrium.io</D:href></D:owner>
+</D:lockinfo>"#,
+        )
+        .expect("valid lockinfo");
+        assert_eq!(
+            info,
+            LockRequest {
+                scope: LockScope::Shared,
+                owner: Some(r#"<href xmlns="DAV:">mailto:admin@atrium.io</href>"#.to_owned())
+            }
+        );
+        // A text owner stays escaped when written in the lockdiscovery
+        let info = LockRequest::parse(
+            r#"<D:lockinfo xmlns:D="DAV:"><D:owner>Tom &lt;/D:owner&gt;</D:owner></D:lockinfo>"#,
+        )
+        .expect("valid lockinfo");
+        assert_eq!(info.owner.as_deref(), Some("Tom &lt;/D:owner&gt;"));
+    }
+}
@@ -1,14 +1,21 @@
+pub(crate) mod chunking;
 pub mod crypto;
 pub mod dav_file;
 pub mod error;
+pub mod file_request;
 pub(crate) mod headers;
+pub(crate) mod locks;
 pub mod model;
+pub(crate) mod properties;
+pub mod trash;
+pub(crate) mod tus;
+pub mod versions;
 pub(crate) mod webdav_server;
 
-use crate::configuration::HostType;
+use crate::{appstate::ConfigState, configuration::HostType};
 use axum::{
     body::Body,
-    extract::ConnectInfo,
+    extract::{ConnectInfo, State},
     http::{Request, Response},
 };
 use std::{net::SocketAddr, sync::LazyLock};
@@ -19,8 +26,10 @@ static WEBDAV_SERVER: LazyLock<webdav_server::WebdavServer> =
 pub async fn webdav_handler(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     host_type: HostType,
+    State(config): State<ConfigState>,
     mut req: Request<Body>,
 ) -> Response<Body> {
+    let file_request = req.extensions_mut().remove::<file_request::FileRequest>();
     // If the middleware modified the HostType, it's in the extensions
     let dav = if let Some(HostType::Dav(dav)) = req.extensions_mut().remove::<HostType>() {
         dav
@@ -31,5 +40,7 @@ pub async fn webdav_handler(
         }
     };
 
-    WEBDAV_SERVER.call(req, addr, &dav).await
+    WEBDAV_SERVER
+        .call(req, addr, &dav, file_request.as_ref(), &config)
+        .await
 }
@@ -1,7 +1,8 @@
+use super::{trash::TrashConfig, versions::VersioningConfig};
 use crate::{
     appstate::{ConfigFile, ConfigState},
-    configuration::config_or_error,
-    auth::AdminToken,
+    auth::{ScopedAdminToken, UserToken, check_user_has_role},
+    configuration::{Config, config_or_error},
     utils::{is_default, option_string_trim, string_trim, vec_trim_remove_empties},
 };
 use axum::{
@@ -43,6 +44,16 @@ pub struct Dav {
         skip_serializing_if = "is_default"
     )]
     pub passphrase: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "is_default",
+        deserialize_with = "vec_trim_remove_empties"
+    )]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub trash: Option<TrashConfig>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub versioning: Option<VersioningConfig>,
     #[serde(skip)]
     pub key: Option<[u8; 32]>,
 }
@@ -58,23 +69,54 @@ impl Dav {
     }
 }
 
+/// The dav a user reaches through the API, if it is accessible to the user, and writable if required
+pub(crate) fn user_dav<'a>(
+    config: &'a Config,
+    user: &UserToken,
+    dav_id: usize,
+    write: bool,
+) -> Result<&'a Dav, (StatusCode, &'static str)> {
+    if user.share.is_some() {
+        return Err((StatusCode::FORBIDDEN, "share token cannot use the api"));
+    }
+    let dav = config
+        .davs
+        .iter()
+        .find(|d| d.id == dav_id && (!d.secured || check_user_has_role(user, &d.roles)))
+        .ok_or((StatusCode::NOT_FOUND, "dav doesn't exist"))?;
+    if write && !dav.writable {
+        return Err((StatusCode::FORBIDDEN, "dav is not writable"));
+    }
+    Ok(dav)
+}
+
 pub async fn get_davs(
     State(config_file): State<ConfigFile>,
-    _admin: AdminToken,
+    admin: ScopedAdminToken,
 ) -> Result<Json<Vec<Dav>>, (StatusCode, &'static str)> {
     let config = config_or_error(&config_file).await?;
-    // Return all the davs as Json
-    Ok(Json(config.davs))
+    // Return all the davs managed by the admin as Json
+    Ok(Json(
+        config
+            .davs
+            .into_iter()
+            .filter(|d| admin.dav_allowed(d))
+            .collect(),
+    ))
 }
 
 pub async fn delete_dav(
     State(config_file): State<ConfigFile>,
-    _admin: AdminToken,
+    admin: ScopedAdminToken,
     Path(dav_id): Path<usize>,
 ) -> Result<impl IntoResponse, impl IntoResponse> {
     let mut config = config_or_error(&config_file).await?;
     // Find the dav
-    if let Some(pos) = config.davs.iter().position(|d| d.id == dav_id) {
+    if let Some(pos) = config
+        .davs
+        .iter()
+        .position(|d| d.id == dav_id && admin.dav_allowed(d))
+    {
         // It is an existing dav, delete it
         config.davs.remove(pos);
     } else {
@@ -92,11 +134,24 @@ pub async fn delete_dav(
 pub async fn add_dav(
     State(config_file): State<ConfigFile>,
     State(config): State<ConfigState>,
-    _admin: AdminToken,
+    admin: ScopedAdminToken,
     Json(payload): Json<Dav>,
 ) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
     // Clone the config
     let mut config = (*config).clone();
+    // A delegated admin can only update the davs of its scope, without changing where and how the data is stored
+    if !admin.is_global() {
+        let existing = config.davs.iter().find(|d| d.id == payload.id);
+        if !admin.dav_allowed(&payload)
+            || existing.is_none_or(|d| {
+                !admin.dav_allowed(d)
+                    || d.directory != payload.directory
+                    || d.passphrase != payload.passphrase
+            })
+        {
+            return Err((StatusCode::FORBIDDEN, "dav is outside of the admin scope"));
+        }
+    }
     // Find the dav
     if let Some(dav) = config.davs.iter_mut().find(|d| d.id == payload.id) {
         *dav = payload;
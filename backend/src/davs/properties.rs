@@ -0,0 +1,509 @@
+use quick_xml::{
+    NsReader,
+    escape::{escape, resolve_predefined_entity},
+    events::{BytesStart, Event},
+    name::{Namespace, ResolveResult},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+/// Extended attribute holding the dead properties of a resource
+const XATTR_NAME: &str = "user.atrium.properties";
+/// Folder holding the dead properties of the resources of a collection, when extended attributes are not available
+pub const SIDECAR_DIR: &str = ".atrium_properties";
+
+/// Properties set by the clients with PROPPATCH : name in Clark notation ({namespace}name) -> XML value, as given by `read_content`
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DeadProperties(pub BTreeMap<String, String>);
+
+impl DeadProperties {
+    pub fn key(namespace: &str, name: &str) -> String {
+        format!("{{{namespace}}}{name}")
+    }
+
+    /// The properties as elements of a PROPFIND response
+    pub fn properties(&self) -> Vec<Property> {
+        self.0
+            .iter()
+            .map(|(key, value)| {
+                let (namespace, name) = key
+                    .strip_prefix('{')
+                    .and_then(|k| k.split_once('}'))
+                    .unwrap_or(("", key));
+                Property {
+                    name: PropertyName {
+                        namespace: namespace.to_owned(),
+                        name: name.to_owned(),
+                    },
+                    element: format!(r#"<{name} xmlns="{}">{value}</{name}>"#, escape(namespace)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The qualified name of a property
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyName {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl PropertyName {
+    pub fn dav(name: &str) -> Self {
+        Self {
+            namespace: "DAV:".to_owned(),
+            name: name.to_owned(),
+        }
+    }
+
+    /// The element without value, as listed by propname requests and for unknown properties
+    pub fn empty_element(&self) -> String {
+        if self.namespace == "DAV:" {
+            format!("<D:{}/>", self.name)
+        } else {
+            format!(r#"<{} xmlns="{}"/>"#, self.name, escape(&self.namespace))
+        }
+    }
+}
+
+/// A property of a resource with its element in a PROPFIND response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    pub name: PropertyName,
+    pub element: String,
+}
+
+impl Property {
+    /// A live property of the DAV: namespace, the value must be escaped
+    pub fn dav(name: &str, value: &str) -> Self {
+        Self {
+            name: PropertyName::dav(name),
+            element: format!("<D:{name}>{value}</D:{name}>"),
+        }
+    }
+}
+
+/// The properties asked by a PROPFIND request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Propfind {
+    /// All the properties, with the listed ones that are only given on demand
+    AllProp(Vec<PropertyName>),
+    /// The names of all the properties
+    PropName,
+    /// The listed properties
+    Prop(Vec<PropertyName>),
+}
+
+impl Propfind {
+    /// An empty body asks for all the properties, a body without allprop, propname or prop is invalid
+    pub fn parse(xml_body: &str) -> Result<Option<Self>, quick_xml::Error> {
+        if xml_body.trim().is_empty() {
+            return Ok(Some(Self::AllProp(vec![])));
+        }
+        let mut names = vec![];
+        let mut reader = NsReader::from_str(xml_body);
+        let (mut propfind, mut in_list) = (None, false);
+        loop {
+            let (namespace, event) = reader.read_resolved_event()?;
+            let namespace = namespace_string(namespace);
+            let is_start = matches!(event, Event::Start(_));
+            match event {
+                Event::Eof => break,
+                // The properties are the children of the prop and include elements
+                Event::Start(e) | Event::Empty(e) if in_list => {
+                    names.push(PropertyName {
+                        namespace,
+                        name: local_name(&e),
+                    });
+                    if is_start {
+                        reader.read_to_end(e.name())?;
+                    }
+                }
+                Event::Start(e) | Event::Empty(e) if namespace == "DAV:" => {
+                    match e.local_name().as_ref() {
+                        b"allprop" => propfind = Some(Self::AllProp(vec![])),
+                        b"propname" => propfind = Some(Self::PropName),
+                        b"prop" => {
+                            propfind = Some(Self::Prop(vec![]));
+                            in_list = is_start;
+                        }
+                        b"include" => in_list = is_start,
+                        _ => (),
+                    }
+                }
+                Event::End(e)
+                    if namespace == "DAV:"
+                        && matches!(e.local_name().as_ref(), b"prop" | b"include") =>
+                {
+                    in_list = false;
+                }
+                _ => (),
+            }
+        }
+        Ok(propfind.map(|propfind| match propfind {
+            Self::AllProp(_) => Self::AllProp(names),
+            Self::PropName => Self::PropName,
+            Self::Prop(_) => Self::Prop(names),
+        }))
+    }
+
+    /// The live property of the DAV: namespace is asked for, explicitly or by its name
+    pub fn asks_for(&self, name: &str) -> bool {
+        match self {
+            Self::AllProp(names) | Self::Prop(names) => names
+                .iter()
+                .any(|n| n.namespace == "DAV:" && n.name == name),
+            Self::PropName => true,
+        }
+    }
+}
+
+fn namespace_string(namespace: ResolveResult) -> String {
+    match namespace {
+        ResolveResult::Bound(Namespace(ns)) => String::from_utf8_lossy(ns).into_owned(),
+        _ => String::new(),
+    }
+}
+
+fn local_name(e: &BytesStart) -> String {
+    String::from_utf8_lossy(e.local_name().as_ref()).into_owned()
+}
+
+/// The content of the element just started, up to its end, as XML that can be written in any document :
+/// the text is escaped, and the child elements declare their namespace instead of using the prefixes of the request
+pub fn read_content(reader: &mut NsReader<&[u8]>) -> Result<String, quick_xml::Error> {
+    let mut content = String::new();
+    let mut depth = 0_usize;
+    loop {
+        let (namespace, event) = reader.read_resolved_event()?;
+        let namespace = namespace_string(namespace);
+        let is_start = matches!(event, Event::Start(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                let name = local_name(&e);
+                content.push_str(&format!(r#"<{name} xmlns="{}""#, escape(&namespace)));
+                for attribute in e.attributes() {
+                    let attribute = attribute?;
+                    // The namespace declarations and the prefixed attributes would refer to the prefixes of the request
+                    if attribute.key.prefix().is_some()
+                        || attribute.key.as_namespace_binding().is_some()
+                    {
+                        continue;
+                    }
+                    content.push_str(&format!(
+                        r#" {}="{}""#,
+                        String::from_utf8_lossy(attribute.key.local_name().as_ref()),
+                        escape(attribute.unescape_value()?)
+                    ));
+                }
+                if is_start {
+                    depth += 1;
+                    content.push('>');
+                } else {
+                    content.push_str("/>");
+                }
+            }
+            Event::End(e) => {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+                content.push_str(&format!(
+                    "</{}>",
+                    String::from_utf8_lossy(e.local_name().as_ref())
+                ));
+            }
+            Event::Text(e) => content.push_str(&escape(e.decode()?)),
+            Event::CData(e) => content.push_str(&escape(e.decode()?)),
+            Event::GeneralRef(e) => {
+                if let Some(c) = e.resolve_char_ref()? {
+                    content.push_str(&escape(c.to_string()));
+                } else if let Some(value) = resolve_predefined_entity(&e.decode()?) {
+                    content.push_str(&escape(value));
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok(content)
+}
+
+/// A property to set with its value, or to remove, requested by a PROPPATCH propertyupdate body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyUpdate {
+    pub namespace: String,
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl PropertyUpdate {
+    pub fn parse(xml_body: &str) -> Result<Vec<Self>, quick_xml::Error> {
+        let mut updates = vec![];
+        let mut reader = NsReader::from_str(xml_body);
+        let (mut set, mut in_prop) = (true, false);
+        loop {
+            let (namespace, event) = reader.read_resolved_event()?;
+            let namespace = namespace_string(namespace);
+            match event {
+                Event::Eof => break,
+                // The properties are the children of the prop elements
+                Event::Start(e) if in_prop => {
+                    let value = read_content(&mut reader)?;
+                    updates.push(Self {
+                        namespace,
+                        name: local_name(&e),
+                        value: set.then_some(value),
+                    });
+                }
+                Event::Empty(e) if in_prop => updates.push(Self {
+                    namespace,
+                    name: local_name(&e),
+                    value: set.then(String::new),
+                }),
+                Event::Start(e) if namespace == "DAV:" => match e.local_name().as_ref() {
+                    b"set" => set = true,
+                    b"remove" => set = false,
+                    b"prop" => in_prop = true,
+                    _ => (),
+                },
+                Event::End(e) if namespace == "DAV:" && e.local_name().as_ref() == b"prop" => {
+                    in_prop = false;
+                }
+                _ => (),
+            }
+        }
+        Ok(updates)
+    }
+}
+
+/// Where the dead properties of a resource are kept when extended attributes are not available
+fn sidecar_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy();
+    Some(
+        path.parent()?
+            .join(SIDECAR_DIR)
+            .join(format!("{name}.json")),
+    )
+}
+
+/// The sidecar folders are not part of the served files
+pub fn is_hidden(path: &Path) -> bool {
+    path.components().any(|c| c.as_os_str() == SIDECAR_DIR)
+}
+
+pub async fn load(path: &Path) -> DeadProperties {
+    let value = match xattr::get(path, XATTR_NAME) {
+        Ok(Some(value)) => Some(value),
+        _ => match sidecar_path(path) {
+            Some(sidecar) => fs::read(sidecar).await.ok(),
+            None => None,
+        },
+    };
+    value
+        .and_then(|v| serde_json::from_slice(&v).ok())
+        .unwrap_or_default()
+}
+
+pub async fn save(path: &Path, properties: &DeadProperties) -> io::Result<()> {
+    let sidecar = sidecar_path(path).ok_or_else(|| io::Error::other("no sidecar path"))?;
+    if properties.0.is_empty() {
+        drop(xattr::remove(path, XATTR_NAME));
+        drop(fs::remove_file(sidecar).await);
+        return Ok(());
+    }
+    let value = serde_json::to_vec(properties)?;
+    if xattr::set(path, XATTR_NAME, &value).is_ok() {
+        drop(fs::remove_file(sidecar).await);
+    } else {
+        if let Some(parent) = sidecar.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(sidecar, value).await?;
+    }
+    Ok(())
+}
+
+/// Forget the dead properties of a deleted resource (the extended attributes are deleted with it)
+pub async fn remove(path: &Path) {
+    if let Some(sidecar) = sidecar_path(path) {
+        drop(fs::remove_file(sidecar).await);
+    }
+}
+
+/// Move the dead properties with a renamed resource (the extended attributes follow it)
+pub async fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    let (Some(from), Some(to)) = (sidecar_path(from), sidecar_path(to)) else {
+        return Ok(());
+    };
+    if fs::metadata(&from).await.is_ok() {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(from, to).await
+    } else {
+        drop(fs::remove_file(to).await);
+        Ok(())
+    }
+}
+
+/// Give the dead properties of a resource to its copy
+pub async fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    save(to, &load(from).await).await
+}
+
+/// Rename `replacement` over a file, the file keeping its dead properties although its content is a new inode
+pub async fn replace(path: &Path, replacement: &Path) -> io::Result<()> {
+    copy(path, replacement).await?;
+    fs::rename(replacement, path).await?;
+    rename(replacement, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_property_update() {
+        let updates = PropertyUpdate::parse(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:schemas-microsoft-com:">
+  <D:set>
+    <D:prop>
+      <Z:Win32FileAttributes>00000020</Z:Win32FileAttributes>
+      <favorite xmlns="http://owncloud.org/ns"/>
+      <Z:author><Z:name id="1">Tom &amp; <![CDATA[<Jerry>]]></Z:name></Z:author>
+    </D:prop>
+  </D:set>
+  <D:remove><D:prop><Z:Win32CreationTime/></D:prop></D:remove>
+</D:propertyupdate>"#,
+        )
+        .expect("valid propertyupdate");
+        assert_eq!(
+            updates,
+            vec![
+                PropertyUpdate {
+                    namespace: "urn:schemas-microsoft-com:".to_owned(),
+                    name: "Win32FileAttributes".to_owned(),
+                    value: Some("00000020".to_owned())
+                },
+                PropertyUpdate {
+                    namespace: "http://owncloud.org/ns".to_owned(),
+                    name: "favorite".to_owned(),
+                    value: Some(String::new())
+                },
+                PropertyUpdate {
+                    namespace: "urn:schemas-microsoft-com:".to_owned(),
+                    name: "author".to_owned(),
+                    value: Some(
+                        r#"<name xmlns="urn:schemas-microsoft-com:" id="1">Tom &amp; &lt;Jerry&gt;</name>"#
+                            .to_owned()
+                    )
+                },
+                PropertyUpdate {
+                    namespace: "urn:schemas-microsoft-com:".to_owned(),
+                    name: "Win32CreationTime".to_owned(),
+                    value: None
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_propfind() {
+        assert_eq!(
+            Propfind::parse("").ok(),
+            Some(Some(Propfind::AllProp(vec![])))
+        );
+        assert_eq!(
+            Propfind::parse(
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+<propfind xmlns="DAV:"><propname/></propfind>"#
+            )
+            .ok(),
+            Some(Some(Propfind::PropName))
+        );
+        let propfind = Propfind::parse(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propfind xmlns:D="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <D:prop>
+    <D:getetag/>
+    <oc:favorite></oc:favorite>
+  </D:prop>
+</D:propfind>"#,
+        );
+        assert_eq!(
+            propfind.ok(),
+            Some(Some(Propfind::Prop(vec![
+                PropertyName::dav("getetag"),
+                PropertyName {
+                    namespace: "http://owncloud.org/ns".to_owned(),
+                    name: "favorite".to_owned()
+                }
+            ])))
+        );
+        let propfind = Propfind::parse(
+            r#"<D:propfind xmlns:D="DAV:"><D:allprop/><D:include><D:quota-used-bytes/></D:include></D:propfind>"#,
+        )
+        .expect("valid propfind")
+        .expect("allprop propfind");
+        assert!(propfind.asks_for("quota-used-bytes"));
+        assert!(!propfind.asks_for("quota-available-bytes"));
+        assert_eq!(
+            Propfind::parse(r#"<D:propfind xmlns:D="DAV:"></D:propfind>"#).ok(),
+            Some(None)
+        );
+        assert!(Propfind::parse("<D:propfind").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_properties_storage() {
+        let dir = tempfile::tempdir().expect("temporary directory");
+        let file = dir.path().join("file");
+        fs::write(&file, b"abc").await.expect("file written");
+        assert_eq!(load(&file).await, DeadProperties::default());
+
+        let properties = DeadProperties(
+            [(
+                DeadProperties::key("http://owncloud.org/ns", "favorite"),
+                "1".to_owned(),
+            )]
+            .into(),
+        );
+        save(&file, &properties).await.expect("properties saved");
+        assert_eq!(load(&file).await, properties);
+        assert_eq!(
+            properties.properties(),
+            vec![Property {
+                name: PropertyName {
+                    namespace: "http://owncloud.org/ns".to_owned(),
+                    name: "favorite".to_owned()
+                },
+                element: r#"<favorite xmlns="http://owncloud.org/ns">1</favorite>"#.to_owned()
+            }]
+        );
+
+        // The properties follow the resource
+        let moved = dir.path().join("moved");
+        fs::rename(&file, &moved).await.expect("file moved");
+        rename(&file, &moved).await.expect("properties moved");
+        assert_eq!(load(&moved).await, properties);
+        fs::write(&file, b"abc").await.expect("file written");
+        copy(&moved, &file).await.expect("properties copied");
+        assert_eq!(load(&file).await, properties);
+
+        save(&moved, &DeadProperties::default())
+            .await
+            .expect("properties removed");
+        assert_eq!(load(&moved).await, DeadProperties::default());
+        assert!(is_hidden(&dir.path().join(SIDECAR_DIR).join("file.json")));
+        assert!(!is_hidden(&moved));
+    }
+}
@@ -0,0 +1,370 @@
+use super::{
+    model::{Dav, user_dav},
+    properties,
+    webdav_server::dir_size,
+};
+use crate::{appstate::ConfigState, auth::UserToken, configuration::Config, utils::is_default};
+use axum::{
+    Json,
+    extract::{Path as UrlPath, State},
+};
+use chrono::Utc;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+use tokio::fs;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Folder of the deleted resources of a dav, within its directory
+pub const TRASH_DIR: &str = ".atrium_trash";
+const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// Trash bin of a dav : the deleted resources are kept for a while instead of being removed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashConfig {
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u64,
+    /// Size in bytes above which the oldest deleted resources are purged
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_size: Option<u64>,
+}
+
+const fn default_retention_days() -> u64 {
+    DEFAULT_RETENTION_DAYS
+}
+
+/// The trash bin is not part of the served files
+pub fn is_hidden(path: &Path) -> bool {
+    path.components().any(|c| c.as_os_str() == TRASH_DIR)
+}
+
+/// A deleted resource, with where it was and when it was deleted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub id: String,
+    /// Path of the resource relative to the directory of the dav
+    pub path: PathBuf,
+    /// When the resource was deleted, in milliseconds since the epoch
+    pub deleted_at: i64,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+impl TrashItem {
+    fn resource_path(&self, directory: &Path) -> PathBuf {
+        directory.join(TRASH_DIR).join(&self.id)
+    }
+
+    fn json_path(&self, directory: &Path) -> PathBuf {
+        directory.join(TRASH_DIR).join(format!("{}.json", self.id))
+    }
+
+    /// Put the resource back at its original path, which must be free
+    pub async fn restore(&self, directory: &Path) -> io::Result<PathBuf> {
+        if !self
+            .path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(io::Error::other("invalid original path"));
+        }
+        let target = directory.join(&self.path);
+        if fs::symlink_metadata(&target).await.is_ok() {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(self.resource_path(directory), &target).await?;
+        properties::rename(&self.resource_path(directory), &target).await?;
+        drop(fs::remove_file(self.json_path(directory)).await);
+        Ok(target)
+    }
+
+    /// Remove the resource for good
+    pub async fn purge(&self, directory: &Path) {
+        let resource = self.resource_path(directory);
+        drop(if self.is_dir {
+            fs::remove_dir_all(&resource).await
+        } else {
+            fs::remove_file(&resource).await
+        });
+        properties::remove(&resource).await;
+        drop(fs::remove_file(self.json_path(directory)).await);
+    }
+}
+
+/// Move a resource of a dav to its trash bin, then apply the retention and the size cap of the trash
+pub async fn move_to_trash(directory: &Path, path: &Path, config: &TrashConfig) -> io::Result<()> {
+    let rel_path = path
+        .strip_prefix(directory)
+        .map_err(|_| io::Error::other("resource outside of the dav"))?;
+    if rel_path.as_os_str().is_empty() {
+        return Err(io::Error::other("the root of the dav cannot be deleted"));
+    }
+    let meta = fs::symlink_metadata(path).await?;
+    let is_dir = meta.is_dir();
+    let item = TrashItem {
+        id: Uuid::new_v4().to_string(),
+        path: rel_path.components().collect(),
+        deleted_at: Utc::now().timestamp_millis(),
+        size: if is_dir {
+            dir_size(path).await
+        } else {
+            meta.len()
+        },
+        is_dir,
+    };
+    fs::create_dir_all(directory.join(TRASH_DIR)).await?;
+    fs::write(item.json_path(directory), serde_json::to_vec(&item)?).await?;
+    if let Err(e) = fs::rename(path, item.resource_path(directory)).await {
+        drop(fs::remove_file(item.json_path(directory)).await);
+        return Err(e);
+    }
+    // The dead properties kept beside the resource go to the trash with it
+    properties::rename(path, &item.resource_path(directory)).await?;
+    enforce(directory, config).await;
+    Ok(())
+}
+
+/// The deleted resources of a dav, the most recent first, in a stable order
+pub async fn list(directory: &Path) -> Vec<TrashItem> {
+    let mut items = Vec::new();
+    let Ok(mut rd) = fs::read_dir(directory.join(TRASH_DIR)).await else {
+        return items;
+    };
+    while let Ok(Some(entry)) = rd.next_entry().await {
+        let entry_path = entry.path();
+        if entry_path.extension().is_some_and(|e| e == "json")
+            && let Ok(value) = fs::read(&entry_path).await
+            && let Ok(item) = serde_json::from_slice::<TrashItem>(&value)
+        {
+            items.push(item);
+        }
+    }
+    items.sort_unstable_by(|a, b| {
+        b.deleted_at
+            .cmp(&a.deleted_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    items
+}
+
+pub async fn load(directory: &Path, id: &str) -> Option<TrashItem> {
+    // The id is part of the path of the item, it must be one given by the trash
+    Uuid::try_parse(id).ok()?;
+    let json_path = directory.join(TRASH_DIR).join(format!("{id}.json"));
+    let value = fs::read(json_path).await.ok()?;
+    serde_json::from_slice(&value).ok()
+}
+
+/// Purge the resources deleted for longer than the retention, then the oldest ones while the trash exceeds its size cap
+pub async fn enforce(directory: &Path, config: &TrashConfig) {
+    let expired_before = Utc::now().timestamp_millis().saturating_sub(
+        i64::try_from(config.retention_days.saturating_mul(24 * 60 * 60 * 1000))
+            .unwrap_or(i64::MAX),
+    );
+    let mut size = 0;
+    for item in list(directory).await {
+        if item.deleted_at <= expired_before
+            || config
+                .max_size
+                .is_some_and(|max_size| size + item.size > max_size)
+        {
+            item.purge(directory).await;
+        } else {
+            size += item.size;
+        }
+    }
+}
+
+/// Apply the retention of the trash bins of all the davs, to be run periodically
+pub async fn purge_expired(config_file: &str) {
+    let Ok(config) = Config::from_file(config_file).await else {
+        error!("could not read configuration to purge the trash bins");
+        return;
+    };
+    for dav in &config.davs {
+        if let Some(trash) = &dav.trash {
+            enforce(Path::new(&dav.directory), trash).await;
+        }
+    }
+}
+
+/// The dav whose trash the user wants to access, if it has a trash
+fn trash_dav<'a>(
+    config: &'a Config,
+    user: &UserToken,
+    dav_id: usize,
+    write: bool,
+) -> Result<&'a Dav, (StatusCode, &'static str)> {
+    let dav = user_dav(config, user, dav_id, write)?;
+    if dav.trash.is_none() {
+        return Err((StatusCode::NOT_FOUND, "trash doesn't exist"));
+    }
+    Ok(dav)
+}
+
+/// List the deleted resources of a dav
+pub async fn list_trash(
+    State(config): State<ConfigState>,
+    user: UserToken,
+    UrlPath(dav_id): UrlPath<usize>,
+) -> Result<Json<Vec<TrashItem>>, (StatusCode, &'static str)> {
+    let dav = trash_dav(&config, &user, dav_id, false)?;
+    Ok(Json(list(Path::new(&dav.directory)).await))
+}
+
+/// Put a deleted resource back at its original path
+pub async fn restore_trash_item(
+    State(config): State<ConfigState>,
+    user: UserToken,
+    UrlPath((dav_id, item_id)): UrlPath<(usize, String)>,
+) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
+    let dav = trash_dav(&config, &user, dav_id, true)?;
+    let directory = Path::new(&dav.directory);
+    let item = load(directory, &item_id)
+        .await
+        .ok_or((StatusCode::NOT_FOUND, "trash item doesn't exist"))?;
+    match item.restore(directory).await {
+        Ok(_) => {
+            info!(
+                "TRASH ITEM RESTORED: {} by {}",
+                item.path.display(),
+                user.login
+            );
+            Ok((StatusCode::OK, "trash item restored successfully"))
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err((
+            StatusCode::CONFLICT,
+            "a resource already exists at the original path",
+        )),
+        Err(e) => {
+            error!("could not restore trash item {item_id}: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "trash item could not be restored",
+            ))
+        }
+    }
+}
+
+/// Remove a deleted resource for good
+pub async fn purge_trash_item(
+    State(config): State<ConfigState>,
+    user: UserToken,
+    UrlPath((dav_id, item_id)): UrlPath<(usize, String)>,
+) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
+    let dav = trash_dav(&config, &user, dav_id, true)?;
+    let directory = Path::new(&dav.directory);
+    let item = load(directory, &item_id)
+        .await
+        .ok_or((StatusCode::NOT_FOUND, "trash item doesn't exist"))?;
+    item.purge(directory).await;
+    info!(
+        "TRASH ITEM PURGED: {} by {}",
+        item.path.display(),
+        user.login
+    );
+    Ok((StatusCode::OK, "trash item purged successfully"))
+}
+
+/// Remove all the deleted resources of a dav for good
+pub async fn empty_trash(
+    State(config): State<ConfigState>,
+    user: UserToken,
+    UrlPath(dav_id): UrlPath<usize>,
+) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
+    let dav = trash_dav(&config, &user, dav_id, true)?;
+    let directory = Path::new(&dav.directory);
+    for item in list(directory).await {
+        item.purge(directory).await;
+    }
+    info!("TRASH EMPTIED: dav {} by {}", dav.id, user.login);
+    Ok((StatusCode::OK, "trash emptied successfully"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trash() {
+        let dir = tempfile::tempdir().expect("temporary directory");
+        let directory = dir.path();
+        let config = TrashConfig {
+            retention_days: 30,
+            max_size: Some(10),
+        };
+        fs::create_dir_all(directory.join("dira"))
+            .await
+            .expect("folder created");
+        fs::write(directory.join("dira/file1"), "abcdef")
+            .await
+            .expect("file written");
+        move_to_trash(directory, &directory.join("dira/file1"), &config)
+            .await
+            .expect("file deleted");
+        assert!(!directory.join("dira/file1").exists());
+        let items = list(directory).await;
+        assert_eq!(items.len(), 1);
+        let item = items.first().expect("one deleted file expected");
+        assert_eq!(item.path, Path::new("dira/file1"));
+        assert_eq!(item.size, 6);
+        // The file is deleted a second earlier, whatever the time taken by the test
+        let item = TrashItem {
+            deleted_at: item.deleted_at - 1000,
+            ..item.clone()
+        };
+        fs::write(
+            item.json_path(directory),
+            serde_json::to_vec(&item).expect("item serialized"),
+        )
+        .await
+        .expect("item written");
+
+        // The oldest deleted files are purged above the size cap
+        fs::write(directory.join("dira/file2"), "ghijkl")
+            .await
+            .expect("file written");
+        let dead_properties = properties::DeadProperties(
+            [(
+                properties::DeadProperties::key("http://owncloud.org/ns", "favorite"),
+                "1".to_owned(),
+            )]
+            .into(),
+        );
+        properties::save(&directory.join("dira"), &dead_properties)
+            .await
+            .expect("properties saved");
+        move_to_trash(directory, &directory.join("dira"), &config)
+            .await
+            .expect("folder deleted");
+        let items = list(directory).await;
+        assert_eq!(items.len(), 1);
+        let folder = items.first().expect("one deleted folder expected");
+        assert_eq!(folder.path, Path::new("dira"));
+        assert!(folder.is_dir);
+
+        let restored = load(directory, &folder.id)
+            .await
+            .expect("item loaded")
+            .restore(directory)
+            .await
+            .expect("folder restored");
+        assert_eq!(
+            fs::read_to_string(restored.join("file2"))
+                .await
+                .expect("file read"),
+            "ghijkl"
+        );
+        // The folder comes back with its dead properties
+        assert_eq!(properties::load(&restored).await, dead_properties);
+        assert!(list(directory).await.is_empty());
+        assert_eq!(load(directory, "../dira").await, None);
+    }
+}
@@ -0,0 +1,396 @@
+use super::{dav_file::DavFile, properties};
+use axum::body::Body;
+use base64ct::{Base64, Encoding};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use http_body_util::BodyExt;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use tokio_util::io::StreamReader;
+use uuid::Uuid;
+
+/// Folder of a dav holding the uploads in progress
+pub const UPLOADS_DIR: &str = ".atrium_uploads";
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,expiration,checksum,termination";
+pub const TUS_CHECKSUM_ALGORITHMS: &str = "sha1,sha256";
+/// An upload that was not written for this time is removed
+pub const UPLOAD_EXPIRATION: i64 = 24 * 60 * 60; // 24 hours in seconds
+
+/// The uploads folder is not part of the served files
+pub fn is_hidden(path: &Path) -> bool {
+    path.components().any(|c| c.as_os_str() == UPLOADS_DIR)
+}
+
+/// The id of the upload targeted by a tus request, if the path is the one of an upload
+pub fn upload_id<'a>(path: &'a Path, directory: &str) -> Option<&'a str> {
+    let id = path
+        .strip_prefix(Path::new(directory).join(UPLOADS_DIR))
+        .ok()?
+        .to_str()?;
+    (!id.is_empty() && Uuid::try_parse(id).is_ok()).then_some(id)
+}
+
+/// The Upload-Metadata header : comma separated pairs of a key and a base64 encoded value
+pub fn metadata_value(header: &str, key: &str) -> Option<String> {
+    header.split(',').find_map(|pair| {
+        let mut pair = pair.split_whitespace();
+        if pair.next()? != key {
+            return None;
+        }
+        let value = Base64::decode_vec(pair.next().unwrap_or_default()).ok()?;
+        String::from_utf8(value).ok()
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+/// The Upload-Checksum header : the algorithm and the base64 encoded digest of the body of a PATCH
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub digest: String,
+}
+
+impl Checksum {
+    /// None if the header is malformed or the algorithm not supported
+    pub fn parse(header: &str) -> Option<Self> {
+        let (algorithm, digest) = header.trim().split_once(' ')?;
+        let algorithm = match algorithm {
+            "sha1" => ChecksumAlgorithm::Sha1,
+            "sha256" => ChecksumAlgorithm::Sha256,
+            _ => return None,
+        };
+        Some(Self {
+            algorithm,
+            digest: digest.trim().to_owned(),
+        })
+    }
+}
+
+enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha1 => Self::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Self::Sha1(hasher) => Base64::encode_string(&hasher.finalize()),
+            Self::Sha256(hasher) => Base64::encode_string(&hasher.finalize()),
+        }
+    }
+}
+
+/// What became of the body of a PATCH
+#[derive(Debug, PartialEq, Eq)]
+pub enum Appended {
+    /// The body was written, completely or until the connection was lost
+    Written,
+    /// The body did not match its checksum and was discarded
+    ChecksumMismatch,
+}
+
+/// An upload in progress, described by {id}.json in the uploads folder of its dav.
+/// Its data is written in segments, {id}.{n} : a plain dav appends to a single segment, an encrypted one starts a new
+/// segment for each PATCH as an encrypted stream cannot be resumed, they are assembled once the upload is complete.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upload {
+    pub id: String,
+    /// Path on disk where the file is moved once complete
+    pub target: PathBuf,
+    pub length: u64,
+    /// Length of the data of each segment
+    pub segments: Vec<u64>,
+    /// Unix timestamp in seconds
+    pub expires_at: i64,
+    /// Tokens of the locks submitted at the creation, the tus clients not submitting them again with each PATCH
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lock_tokens: Vec<String>,
+}
+
+impl Upload {
+    pub async fn create(
+        directory: &Path,
+        target: PathBuf,
+        length: u64,
+        lock_tokens: Vec<String>,
+    ) -> io::Result<Self> {
+        let upload = Self {
+            id: Uuid::new_v4().to_string(),
+            target,
+            length,
+            segments: vec![],
+            expires_at: Utc::now().timestamp() + UPLOAD_EXPIRATION,
+            lock_tokens,
+        };
+        fs::create_dir_all(directory.join(UPLOADS_DIR)).await?;
+        upload.save(directory).await?;
+        Ok(upload)
+    }
+
+    /// None if the upload does not exist or expired
+    pub async fn load(directory: &Path, id: &str) -> Option<Self> {
+        let value = fs::read(directory.join(UPLOADS_DIR).join(format!("{id}.json")))
+            .await
+            .ok()?;
+        serde_json::from_slice::<Self>(&value)
+            .ok()
+            .filter(|upload| upload.expires_at > Utc::now().timestamp())
+    }
+
+    async fn save(&self, directory: &Path) -> io::Result<()> {
+        let value = serde_json::to_vec(self)?;
+        fs::write(self.path(directory, "json"), value).await
+    }
+
+    fn path(&self, directory: &Path, extension: &str) -> PathBuf {
+        directory
+            .join(UPLOADS_DIR)
+            .join(format!("{}.{extension}", self.id))
+    }
+
+    fn segment_path(&self, directory: &Path, segment: usize) -> PathBuf {
+        self.path(directory, &segment.to_string())
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.segments.iter().sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.offset() >= self.length
+    }
+
+    /// The Upload-Expires header value
+    pub fn expires(&self) -> String {
+        DateTime::<Utc>::from_timestamp(self.expires_at, 0)
+            .map(|t| t.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+            .unwrap_or_default()
+    }
+
+    /// Write the body of a PATCH at the end of the upload.
+    /// What was received before a connection loss is kept, unless it cannot be checked against a checksum.
+    pub async fn append(
+        &mut self,
+        directory: &Path,
+        body: Body,
+        checksum: Option<&Checksum>,
+        key: Option<[u8; 32]>,
+    ) -> io::Result<Appended> {
+        let new_segment = key.is_some() || self.segments.is_empty();
+        let (segment, previous_len) = if new_segment {
+            (self.segments.len(), 0)
+        } else {
+            (self.segments.len() - 1, self.offset())
+        };
+        let segment_path = self.segment_path(directory, segment);
+        let mut file = if new_segment {
+            DavFile::create(&segment_path, key).await?
+        } else {
+            DavFile::Plain(
+                fs::OpenOptions::new()
+                    .append(true)
+                    .open(&segment_path)
+                    .await?,
+            )
+        };
+
+        let mut hasher = checksum.map(|c| Hasher::new(c.algorithm));
+        let body_stream = body
+            .into_data_stream()
+            .map_err(io::Error::other)
+            .inspect_ok(|bytes| {
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.update(bytes);
+                }
+            });
+        let mut body_reader =
+            StreamReader::new(body_stream).take(self.length.saturating_sub(self.offset()));
+        let copied = tokio::io::copy(&mut body_reader, &mut file).await;
+        drop(body_reader);
+        file.shutdown().await?;
+        let len = file.len().await;
+
+        let valid = match (&copied, checksum, hasher) {
+            (Ok(_), Some(checksum), Some(hasher)) => hasher.finalize() == checksum.digest,
+            (Err(_), Some(_), _) => false,
+            _ => true,
+        };
+        if !valid {
+            if new_segment {
+                drop(fs::remove_file(&segment_path).await);
+            } else if let DavFile::Plain(file) = file {
+                file.set_len(previous_len).await?;
+            }
+        } else if new_segment {
+            self.segments.push(len);
+        } else if let Some(last) = self.segments.last_mut() {
+            *last = len;
+        }
+        self.expires_at = Utc::now().timestamp() + UPLOAD_EXPIRATION;
+        self.save(directory).await?;
+        copied?;
+        Ok(if valid {
+            Appended::Written
+        } else {
+            Appended::ChecksumMismatch
+        })
+    }
+
+    /// Move the complete file to its target, assembling its segments if needed
+    pub async fn finish(&self, directory: &Path, key: Option<[u8; 32]>) -> io::Result<()> {
+        let assembled = if self.segments.len() == 1 {
+            self.segment_path(directory, 0)
+        } else {
+            let assembled = self.path(directory, "assembled");
+            let mut file = DavFile::create(&assembled, key).await?;
+            for segment in 0..self.segments.len() {
+                let mut segment = DavFile::open(self.segment_path(directory, segment), key).await?;
+                tokio::io::copy(&mut segment, &mut file).await?;
+            }
+            file.shutdown().await?;
+            assembled
+        };
+        properties::replace(&self.target, &assembled).await?;
+        self.remove(directory).await;
+        Ok(())
+    }
+
+    /// Forget the upload and its data
+    pub async fn remove(&self, directory: &Path) {
+        for segment in 0..self.segments.len() {
+            drop(fs::remove_file(self.segment_path(directory, segment)).await);
+        }
+        drop(fs::remove_file(self.path(directory, "assembled")).await);
+        drop(fs::remove_file(self.path(directory, "json")).await);
+    }
+}
+
+/// Remove the expired uploads of a dav
+pub async fn clean_expired(directory: &Path) {
+    let Ok(mut rd) = fs::read_dir(directory.join(UPLOADS_DIR)).await else {
+        return;
+    };
+    let now = Utc::now().timestamp();
+    while let Ok(Some(entry)) = rd.next_entry().await {
+        let entry_path = entry.path();
+        if entry_path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        if let Ok(value) = fs::read(&entry_path).await
+            && let Ok(upload) = serde_json::from_slice::<Upload>(&value)
+            && upload.expires_at <= now
+        {
+            upload.remove(directory).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tus_headers() {
+        assert_eq!(
+            metadata_value(
+                "relativePath null,filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==",
+                "filename"
+            ),
+            Some("world_domination_plan.pdf".to_owned())
+        );
+        assert_eq!(metadata_value("is_confidential", "filename"), None);
+        assert_eq!(
+            Checksum::parse("sha1 Kq5sNclPz7QV2+lfQIuc6R7oRu0="),
+            Some(Checksum {
+                algorithm: ChecksumAlgorithm::Sha1,
+                digest: "Kq5sNclPz7QV2+lfQIuc6R7oRu0=".to_owned()
+            })
+        );
+        assert_eq!(Checksum::parse("md5 1B2M2Y8AsgTpgAmY7PhCfg=="), None);
+        let id = Uuid::new_v4().to_string();
+        let path = Path::new("./data/dir1").join(UPLOADS_DIR).join(&id);
+        assert_eq!(upload_id(&path, "./data/dir1"), Some(id.as_str()));
+        assert_eq!(
+            upload_id(Path::new("./data/dir1/file"), "./data/dir1"),
+            None
+        );
+        assert!(is_hidden(&path));
+    }
+
+    #[tokio::test]
+    async fn test_upload_resumed() {
+        let dir = tempfile::tempdir().expect("temporary directory");
+        let key = Some([7; 32]);
+        let target = dir.path().join("file");
+        let mut upload = Upload::create(dir.path(), target.clone(), 6, vec![])
+            .await
+            .expect("upload created");
+        assert_eq!(
+            Upload::load(dir.path(), &upload.id).await.as_ref(),
+            Some(&upload)
+        );
+
+        let checksum = Checksum {
+            algorithm: ChecksumAlgorithm::Sha256,
+            digest: "wrong".to_owned(),
+        };
+        assert_eq!(
+            upload
+                .append(dir.path(), Body::from("abc"), Some(&checksum), key)
+                .await
+                .expect("body read"),
+            Appended::ChecksumMismatch
+        );
+        assert_eq!(upload.offset(), 0);
+        upload
+            .append(dir.path(), Body::from("abc"), None, key)
+            .await
+            .expect("first part written");
+        upload
+            .append(dir.path(), Body::from("def"), None, key)
+            .await
+            .expect("second part written");
+        assert_eq!(upload.segments, vec![3, 3]);
+        assert!(upload.is_complete());
+        upload.finish(dir.path(), key).await.expect("upload moved");
+
+        let mut content = String::new();
+        DavFile::open(&target, key)
+            .await
+            .expect("encrypted file")
+            .read_to_string(&mut content)
+            .await
+            .expect("file read");
+        assert_eq!(content, "abcdef");
+        assert_eq!(Upload::load(dir.path(), &upload.id).await, None);
+    }
+}
@@ -0,0 +1,383 @@
+use super::{
+    dav_file::{DavFile, decrypted_size_from_file},
+    model::{Dav, user_dav},
+    webdav_server::{self, encode_uri, upload_temp_path},
+};
+use crate::{appstate::ConfigState, auth::UserToken, configuration::Config, utils::is_default};
+use async_walkdir::WalkDir;
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path as UrlPath, Query, State},
+    response::Response,
+};
+use chrono::Utc;
+use futures_util::StreamExt;
+use hyper::{
+    StatusCode,
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use tokio::fs;
+use tokio_util::io::ReaderStream;
+use tracing::{error, info};
+
+/// Folder of the previous contents of the files of a dav, within its directory
+pub const VERSIONS_DIR: &str = ".atrium_versions";
+const DEFAULT_MAX_VERSIONS: usize = 10;
+
+/// Versioning of a dav : the content of a file is kept when it is overwritten
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersioningConfig {
+    #[serde(default = "default_max_versions")]
+    pub max_versions: usize,
+    /// Age in days after which the versions are purged
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_age_days: Option<u64>,
+}
+
+const fn default_max_versions() -> usize {
+    DEFAULT_MAX_VERSIONS
+}
+
+/// The versions are not part of the served files, they are only browsable through their own collection
+pub fn is_hidden(path: &Path) -> bool {
+    path.components().any(|c| c.as_os_str() == VERSIONS_DIR)
+}
+
+/// Whether the path is within the collection of the versions, which is read only
+pub fn is_versions_path(path: &Path, directory: &str) -> bool {
+    path.strip_prefix(Path::new(directory).join(VERSIONS_DIR))
+        .is_ok_and(|rel_path| !webdav_server::is_hidden(rel_path))
+}
+
+/// A previous content of a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Version {
+    /// Time in milliseconds at which the content was replaced
+    pub id: u64,
+    /// Modification time in milliseconds of the content
+    pub modified: u64,
+    pub size: u64,
+}
+
+/// Folder of the versions of a file, given by its path relative to the directory of the dav
+fn file_versions_dir(directory: &Path, rel_path: &Path) -> PathBuf {
+    directory.join(VERSIONS_DIR).join(rel_path)
+}
+
+fn now_millis() -> u64 {
+    u64::try_from(Utc::now().timestamp_millis()).unwrap_or_default()
+}
+
+/// Keep the content of a file about to be replaced as its newest version, then apply the policy of the versioning
+pub async fn keep(directory: &Path, path: &Path, config: &VersioningConfig) -> io::Result<()> {
+    // The files reached through a symbolic link out of the dav are not versioned
+    let Ok(rel_path) = path.strip_prefix(directory) else {
+        return Ok(());
+    };
+    if rel_path.as_os_str().is_empty() {
+        return Ok(());
+    }
+    let dir = file_versions_dir(directory, rel_path);
+    fs::create_dir_all(&dir).await?;
+    // Two replacements within the same millisecond get consecutive ids
+    let mut id = now_millis();
+    while fs::symlink_metadata(dir.join(id.to_string())).await.is_ok() {
+        id += 1;
+    }
+    let version_path = dir.join(id.to_string());
+    // The files are replaced by a rename, so the version can share the content of the file
+    if fs::hard_link(path, &version_path).await.is_err() {
+        fs::copy(path, &version_path).await?;
+    }
+    enforce(&dir, config).await;
+    Ok(())
+}
+
+async fn list_dir(dir: &Path, key: Option<[u8; 32]>) -> Vec<Version> {
+    let mut versions = Vec::new();
+    let Ok(mut rd) = fs::read_dir(dir).await else {
+        return versions;
+    };
+    while let Ok(Some(entry)) = rd.next_entry().await {
+        if let Some(id) = entry.file_name().to_str().and_then(|n| n.parse().ok())
+            && let Ok(meta) = entry.metadata().await
+            && meta.is_file()
+        {
+            let size = if key.is_some() {
+                decrypted_size_from_file(&entry.path(), meta.len()).await
+            } else {
+                meta.len()
+            };
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or_default());
+            versions.push(Version { id, modified, size });
+        }
+    }
+    versions.sort_unstable_by(|a, b| b.id.cmp(&a.id));
+    versions
+}
+
+/// The versions of a file, the most recent first
+pub async fn list(directory: &Path, rel_path: &Path, key: Option<[u8; 32]>) -> Vec<Version> {
+    list_dir(&file_versions_dir(directory, rel_path), key).await
+}
+
+/// Purge the versions of a file above the maximum count, and the ones older than the maximum age
+async fn enforce(dir: &Path, config: &VersioningConfig) {
+    let expired_before = config
+        .max_age_days
+        .map(|days| now_millis().saturating_sub(days.saturating_mul(24 * 60 * 60 * 1000)));
+    for (index, version) in list_dir(dir, None).await.into_iter().enumerate() {
+        if index >= config.max_versions || expired_before.is_some_and(|before| version.id <= before)
+        {
+            drop(fs::remove_file(dir.join(version.id.to_string())).await);
+        }
+    }
+}
+
+/// Apply the maximum age of the versions of all the davs, to be run periodically
+pub async fn purge_expired(config_file: &str) {
+    let Ok(config) = Config::from_file(config_file).await else {
+        error!("could not read configuration to purge the versions");
+        return;
+    };
+    for dav in &config.davs {
+        if let Some(versioning) = &dav.versioning
+            && versioning.max_age_days.is_some()
+        {
+            let mut walkdir = WalkDir::new(Path::new(&dav.directory).join(VERSIONS_DIR));
+            while let Some(entry) = walkdir.next().await {
+                if let Ok(entry) = entry
+                    && fs::metadata(entry.path()).await.is_ok_and(|m| m.is_dir())
+                {
+                    enforce(&entry.path(), versioning).await;
+                }
+            }
+        }
+    }
+}
+
+/// Put a version back as the content of its file, the replaced content being kept as a version too
+pub async fn restore(
+    directory: &Path,
+    rel_path: &Path,
+    id: u64,
+    config: &VersioningConfig,
+) -> io::Result<PathBuf> {
+    let version_path = file_versions_dir(directory, rel_path).join(id.to_string());
+    if !fs::metadata(&version_path).await?.is_file() {
+        return Err(io::ErrorKind::NotFound.into());
+    }
+    let path = directory.join(rel_path);
+    let exists = match fs::symlink_metadata(&path).await {
+        Ok(meta) if meta.is_file() => true,
+        Ok(_) => return Err(io::ErrorKind::AlreadyExists.into()),
+        Err(_) => false,
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    // The version is copied first, keeping the replaced content may purge it
+    let temp_path = upload_temp_path(&path)?;
+    let restored = async {
+        fs::copy(&version_path, &temp_path).await?;
+        if exists {
+            keep(directory, &path, config).await?;
+        }
+        fs::rename(&temp_path, &path).await
+    }
+    .await;
+    if let Err(e) = restored {
+        drop(fs::remove_file(&temp_path).await);
+        return Err(e);
+    }
+    Ok(path)
+}
+
+#[derive(Deserialize)]
+pub struct VersionsQuery {
+    /// Path of the file relative to the directory of the dav
+    pub path: String,
+}
+
+/// The dav whose versions the user wants to access, if it is versioned, with the checked path of the file
+fn versioned_file<'a>(
+    config: &'a Config,
+    user: &UserToken,
+    dav_id: usize,
+    path: &str,
+    write: bool,
+) -> Result<(&'a Dav, PathBuf), (StatusCode, &'static str)> {
+    let dav = user_dav(config, user, dav_id, write)?;
+    if dav.versioning.is_none() {
+        return Err((StatusCode::NOT_FOUND, "versioning is not enabled"));
+    }
+    let rel_path = Path::new(path.trim_start_matches('/'));
+    if rel_path.as_os_str().is_empty()
+        || !rel_path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        || webdav_server::is_hidden(rel_path)
+    {
+        return Err((StatusCode::BAD_REQUEST, "invalid path"));
+    }
+    Ok((dav, rel_path.to_path_buf()))
+}
+
+/// List the versions of a file of a dav
+pub async fn list_versions(
+    State(config): State<ConfigState>,
+    user: UserToken,
+    UrlPath(dav_id): UrlPath<usize>,
+    Query(query): Query<VersionsQuery>,
+) -> Result<Json<Vec<Version>>, (StatusCode, &'static str)> {
+    let (dav, rel_path) = versioned_file(&config, &user, dav_id, &query.path, false)?;
+    let mut dav = dav.clone();
+    dav.compute_key();
+    Ok(Json(
+        list(Path::new(&dav.directory), &rel_path, dav.key).await,
+    ))
+}
+
+/// Download a version of a file, decrypted if the dav is encrypted
+pub async fn download_version(
+    State(config): State<ConfigState>,
+    user: UserToken,
+    UrlPath((dav_id, version_id)): UrlPath<(usize, u64)>,
+    Query(query): Query<VersionsQuery>,
+) -> Result<Response, (StatusCode, &'static str)> {
+    let (dav, rel_path) = versioned_file(&config, &user, dav_id, &query.path, false)?;
+    let mut dav = dav.clone();
+    dav.compute_key();
+    let version_path =
+        file_versions_dir(Path::new(&dav.directory), &rel_path).join(version_id.to_string());
+    if !fs::metadata(&version_path).await.is_ok_and(|m| m.is_file()) {
+        return Err((StatusCode::NOT_FOUND, "version doesn't exist"));
+    }
+    let file = DavFile::open(&version_path, dav.key)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "version doesn't exist"))?;
+    let name = rel_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    Response::builder()
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", encode_uri(name)),
+        )
+        .body(Body::from_stream(ReaderStream::new(file)))
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "version could not be downloaded",
+            )
+        })
+}
+
+/// Put a version back as the content of its file
+pub async fn restore_version(
+    State(config): State<ConfigState>,
+    user: UserToken,
+    UrlPath((dav_id, version_id)): UrlPath<(usize, u64)>,
+    Query(query): Query<VersionsQuery>,
+) -> Result<(StatusCode, &'static str), (StatusCode, &'static str)> {
+    let (dav, rel_path) = versioned_file(&config, &user, dav_id, &query.path, true)?;
+    let Some(versioning) = &dav.versioning else {
+        return Err((StatusCode::NOT_FOUND, "versioning is not enabled"));
+    };
+    match restore(Path::new(&dav.directory), &rel_path, version_id, versioning).await {
+        Ok(_) => {
+            info!(
+                "VERSION RESTORED: {} ({version_id}) by {}",
+                rel_path.display(),
+                user.login
+            );
+            Ok((StatusCode::OK, "version restored successfully"))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err((StatusCode::NOT_FOUND, "version doesn't exist"))
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err((
+            StatusCode::CONFLICT,
+            "another resource exists at the path of the file",
+        )),
+        Err(e) => {
+            error!("could not restore version {version_id}: {e}");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "version could not be restored",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_versions() {
+        let dir = tempfile::tempdir().expect("temporary directory");
+        let directory = dir.path();
+        let config = VersioningConfig {
+            max_versions: 2,
+            max_age_days: None,
+        };
+        let path = directory.join("dira/file1");
+        let rel_path = Path::new("dira/file1");
+        fs::create_dir_all(directory.join("dira"))
+            .await
+            .expect("folder created");
+        // The files are replaced by a rename, as the uploads do
+        let replace = async |content: &str| {
+            let upload = directory.join("upload");
+            fs::write(&upload, content).await.expect("file written");
+            fs::rename(&upload, &path).await.expect("file replaced");
+        };
+        for content in ["v1", "v2", "v3"] {
+            replace(content).await;
+            keep(directory, &path, &config).await.expect("version kept");
+        }
+
+        // Only the most recent versions are kept
+        let versions = list(directory, rel_path, None).await;
+        assert_eq!(versions.len(), 2);
+        let newest = versions.first().expect("newest version");
+        let oldest = versions.last().expect("oldest version");
+        assert!(newest.id > oldest.id);
+        assert_eq!(newest.size, 2);
+
+        replace("v4").await;
+        restore(directory, rel_path, oldest.id, &config)
+            .await
+            .expect("version restored");
+        assert_eq!(fs::read_to_string(&path).await.expect("file read"), "v2");
+        // The replaced content is the newest version
+        let versions = list(directory, rel_path, None).await;
+        assert_eq!(versions.len(), 2);
+        let newest = versions.first().expect("newest version");
+        assert_eq!(
+            fs::read_to_string(file_versions_dir(directory, rel_path).join(newest.id.to_string()))
+                .await
+                .expect("version read"),
+            "v4"
+        );
+        assert!(
+            restore(directory, rel_path, 1, &config)
+                .await
+                .is_err_and(|e| e.kind() == io::ErrorKind::NotFound)
+        );
+    }
+}
@@ -23,29 +23,43 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
 use super::{
-    dav_file::{decrypted_size_from_file}, headers::Depth, model::Dav,
+    chunking::{self, ChunkedPath},
+    dav_file::decrypted_size_from_file,
+    file_request::FileRequest,
+    headers::{Depth, If, IfItem},
+    locks::{LOCK_TIMEOUT, Lock, LockManager, LockRequest, SUPPORTED_LOCK},
+    model::Dav,
+    properties::{self, DeadProperties, Property, PropertyName, PropertyUpdate, Propfind},
+    trash,
+    tus::{self, Appended, Checksum, Upload},
+    versions,
 };
 use crate::{
+    configuration::Config,
     davs::{dav_file::DavFile, headers::Overwrite},
     utils::extract_query_pairs,
 };
 use async_walkdir::WalkDir;
 use async_zip::{Compression, ZipEntryBuilder, tokio::write::ZipFileWriter};
 use axum::body::Body;
-use chrono::{DateTime, Duration, TimeZone, Utc};
-use futures_util::{FutureExt, StreamExt, future::BoxFuture};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use futures_util::{
+    FutureExt, Stream, StreamExt,
+    future::{self, BoxFuture},
+    stream,
+};
 use headers::{
-    AcceptRanges, ContentType, HeaderMap, HeaderMapExt, IfModifiedSince, IfNoneMatch, IfRange,
-    Range,
+    AcceptRanges, ContentType, ETag, HeaderMap, HeaderMapExt, IfMatch, IfModifiedSince,
+    IfNoneMatch, IfRange, IfUnmodifiedSince, LastModified, Range,
 };
-use http_body_util::BodyExt;
+use http_body_util::{BodyExt, Limited};
 use hyper::{
     Method, StatusCode, Uri,
     header::{
-        CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, HeaderValue, RANGE,
+        CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+        HeaderValue, LOCATION, RANGE,
     },
 };
-use dashmap::DashMap;
 use quick_xml::{Reader, escape::escape, events::Event};
 use serde::Serialize;
 use std::{
@@ -75,33 +89,39 @@ pub type BoxResult<T> = Result<T, Box<dyn std::error::Error>>;
 static APPLICATION_JSON: HeaderValue = HeaderValue::from_static("application/json");
 static ACCEPTED: HeaderValue = HeaderValue::from_static("accepted");
 
-const LOCK_TIMEOUT: i64 = 24 * 60 * 60; // 24 hours in seconds
 const BUF_SIZE: usize = 65536;
-
-#[derive(Debug)]
-pub struct LockInfo {
-    token: String,
-    expires_at: DateTime<Utc>,
-}
-
-pub type LockMap = Arc<DashMap<PathBuf, LockInfo>>;
+/// Number of resources whose metadata are read concurrently while streaming a listing
+const METADATA_CONCURRENCY: usize = 32;
+/// Prefix of the temporary files the uploads are written to before replacing their target
+const UPLOAD_TEMP_PREFIX: &str = ".atrium_upload-";
+const MULTISTATUS_START: &str = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:multistatus xmlns:D="DAV:">
+"#;
+const MULTISTATUS_END: &str = "\n</D:multistatus>";
 
 pub struct WebdavServer {
-    locks: LockMap,
+    locks: Arc<LockManager>,
 }
 
 impl WebdavServer {
     pub fn new() -> Self {
         Self {
-            locks: Arc::new(DashMap::new()),
+            locks: Arc::default(),
         }
     }
 
-    pub async fn call(&self, req: Request, addr: SocketAddr, dav: &Dav) -> Response {
+    pub async fn call(
+        &self,
+        req: Request,
+        addr: SocketAddr,
+        dav: &Dav,
+        file_request: Option<&FileRequest>,
+        config: &Config,
+    ) -> Response {
         let method = req.method().clone();
         let uri = req.uri().clone();
 
-        match self.handle(req, dav).await {
+        match self.handle(req, dav, file_request, config).await {
             Ok(res) => {
                 debug!(r#"{} "{} {}" - {}"#, addr.ip(), method, uri, res.status());
                 res
@@ -123,7 +143,13 @@ impl WebdavServer {
         }
     }
 
-    pub async fn handle(&self, req: Request, dav: &Dav) -> BoxResult<Response> {
+    pub async fn handle(
+        &self,
+        mut req: Request,
+        dav: &Dav,
+        file_request: Option<&FileRequest>,
+        config: &Config,
+    ) -> BoxResult<Response> {
         let mut res = Response::default();
         let head_only = req.method() == Method::HEAD;
 
@@ -133,7 +159,41 @@ impl WebdavServer {
             status_forbid(&mut res);
             return Ok(res);
         };
+        // The files uploaded to a file request get the prefix of the request
+        let path = match file_request {
+            Some(file_request) if req.method() == Method::PUT => {
+                file_request.upload_path(&path, &dav.directory)
+            }
+            _ => path,
+        };
         let path = path.as_path();
+        // The uploads in progress are only reachable with the tus protocol
+        if let Some(id) = tus::upload_id(path, &dav.directory) {
+            self.handle_tus_upload(id, req, &mut res, dav, file_request)
+                .await?;
+            return Ok(res);
+        }
+        // The chunked uploads of the Nextcloud clients have their own namespace
+        if let Some(chunked) = ChunkedPath::parse(path, &dav.directory) {
+            self.handle_chunked_upload(chunked, req, &mut res, dav, file_request)
+                .await?;
+            return Ok(res);
+        }
+        // The versions of the files are a read only collection, the other sidecar folders are not served
+        let in_versions = versions::is_versions_path(path, &dav.directory);
+        if in_versions
+            && !matches!(
+                req.method().as_str(),
+                "GET" | "HEAD" | "OPTIONS" | "PROPFIND"
+            )
+        {
+            status_forbid(&mut res);
+            return Ok(res);
+        }
+        if is_hidden(path) && !in_versions {
+            status_not_found(&mut res);
+            return Ok(res);
+        }
 
         let query = extract_query_pairs(req.uri().query().unwrap_or_default());
 
@@ -142,36 +202,112 @@ impl WebdavServer {
             None => (true, false, false, 0),
         };
 
-        let allow_upload = dav.writable;
-        let allow_delete = dav.writable;
+        let allow_upload = dav.writable || file_request.is_some();
+        let allow_delete = dav.writable && file_request.is_none();
         let allow_search = true;
         let key = dav.key;
 
         if !dav.allow_symlinks
             && !is_miss
-            && !self
-                .is_root_contained(path, Path::new(&dav.directory))
-                .await
+            && !Self::is_root_contained(path, Path::new(&dav.directory)).await
         {
             status_not_found(&mut res);
             return Ok(res);
         }
 
+        // A file request only shows what was uploaded during the session
+        if let Some(file_request) = file_request {
+            let method = req.method().as_str();
+            if !is_miss
+                && method != "PUT"
+                && method != "MKCOL"
+                && !file_request.is_visible(path, &dav.directory)
+            {
+                status_not_found(&mut res);
+                return Ok(res);
+            }
+            if is_dir && (method == "GET" || method == "HEAD") && !query.contains_key("q") {
+                status_forbid(&mut res);
+                return Ok(res);
+            }
+            if method == "COPY" {
+                status_forbid(&mut res);
+                return Ok(res);
+            }
+            // The shared folder itself is visible, but only what was uploaded can be modified
+            if method == "PROPPATCH" && !file_request.is_uploaded(path) {
+                status_forbid(&mut res);
+                return Ok(res);
+            }
+            if method == "PUT" {
+                if is_dir || (is_file && !file_request.is_uploaded(path)) {
+                    status_forbid(&mut res);
+                    return Ok(res);
+                }
+                if let Some(max_upload_size) = file_request.max_upload_size {
+                    if req
+                        .headers()
+                        .get(CONTENT_LENGTH)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .is_some_and(|length| length > max_upload_size)
+                    {
+                        *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+                        *res.body_mut() = Body::from("file is too large");
+                        return Ok(res);
+                    }
+                    let limit = usize::try_from(max_upload_size).unwrap_or(usize::MAX);
+                    req = req.map(|body| Body::new(Limited::new(body, limit)));
+                }
+            }
+        }
+
+        // The If header gives conditions on the state of the resources, and submits the lock tokens
+        let if_header = if let Ok(if_header) = req.headers().typed_try_get::<If>() {
+            if_header
+        } else {
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            return Ok(res);
+        };
+        if let Some(if_header) = &if_header
+            && !self.eval_if_header(if_header, path, dav).await
+        {
+            *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+            return Ok(res);
+        }
+        let tokens = if_header.as_ref().map_or_else(Vec::new, If::tokens);
+
+        // The preconditions on the entity tag prevent the lost updates of concurrent editors
+        if matches!(req.method().as_str(), "PUT" | "DELETE" | "COPY" | "MOVE")
+            && !write_preconditions_pass(req.headers(), path, !is_miss, key).await
+        {
+            *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+            return Ok(res);
+        }
+
+        // Writing a locked resource requires to submit the token of the lock
+        let write = match req.method().as_str() {
+            "PUT" => Some((false, is_miss)),
+            "PROPPATCH" => Some((false, false)),
+            "MKCOL" => Some((false, true)),
+            "DELETE" | "MOVE" => Some((true, true)),
+            _ => None,
+        };
+        if let Some((recursive, membership)) = write
+            && let Err(lock) = self.locks.check_write(path, recursive, membership, &tokens)
+        {
+            status_locked(&mut res, &lock);
+            return Ok(res);
+        }
+
         match req.method() {
             &Method::GET | &Method::HEAD => {
                 if is_dir {
                     if let Some(search_str) = query.get("q") {
                         if allow_search {
                             let q = decode_uri(search_str).unwrap_or_default();
-                            self.handle_query_dir(
-                                path,
-                                &q,
-                                &mut res,
-                                &dav.directory,
-                                dav.allow_symlinks,
-                                key,
-                            )
-                            .await?;
+                            self.handle_query_dir(path, &q, &mut res, dav, file_request)
+                                .await?;
                         }
                     } else if query.contains_key("diskusage") {
                         self.handle_disk_usage(path, &mut res).await?;
@@ -188,42 +324,57 @@ impl WebdavServer {
             }
             &Method::OPTIONS => {
                 set_webdav_headers(&mut res);
+                set_tus_headers(&mut res);
+            }
+            &Method::POST => {
+                if !is_dir {
+                    status_method_not_allowed(&mut res);
+                } else if !dav.writable || file_request.is_some() {
+                    status_forbid(&mut res);
+                } else {
+                    self.handle_tus_create(path, req, &mut res, dav, &tokens)
+                        .await?;
+                }
             }
             &Method::PUT => {
-                if !allow_upload || (!allow_delete && is_file && size > 0) {
+                // The recipient of a file request can upload again the files of the session
+                if !allow_upload
+                    || (!allow_delete
+                        && is_file
+                        && size > 0
+                        && file_request.is_none_or(|f| !f.is_uploaded(path)))
+                {
                     status_forbid(&mut res);
                 } else {
-                    self.handle_upload(path, req, &mut res, key).await?;
+                    self.handle_upload(path, req, &mut res, dav, config.fsync_uploads)
+                        .await?;
+                    if res.status() == StatusCode::CREATED {
+                        self.locks.written(path);
+                        if let Some(file_request) = file_request {
+                            file_request.uploaded.insert(path.to_path_buf());
+                        }
+                    }
                 }
             }
             &Method::DELETE => {
                 if !allow_delete {
                     status_forbid(&mut res);
                 } else if !is_miss {
-                    self.handle_delete(path, is_dir, &mut res).await?;
+                    self.handle_delete(path, is_dir, &mut res, dav).await?;
                 } else {
                     status_not_found(&mut res);
                 }
             }
             method => match method.as_str() {
                 "PROPFIND" => {
-                    if is_dir {
-                        self.handle_propfind_dir(
-                            path,
-                            req.headers(),
-                            &mut res,
-                            &dav.directory,
-                            dav.allow_symlinks,
-                            key,
-                        )
-                        .await?;
-                    } else if is_file {
-                        self.handle_propfind_file(
+                    if is_dir || is_file {
+                        self.handle_propfind(
                             path,
+                            req,
                             &mut res,
-                            &dav.directory,
-                            dav.allow_symlinks,
-                            key,
+                            dav,
+                            file_request,
+                            config.propfind_depth_infinity_limit,
                         )
                         .await?;
                     } else {
@@ -231,10 +382,13 @@ impl WebdavServer {
                     }
                 }
                 "PROPPATCH" => {
-                    if is_file {
-                        self.handle_proppatch(req, path, &mut res).await?;
-                    } else {
+                    if is_miss {
                         status_not_found(&mut res);
+                    } else if !allow_upload {
+                        status_forbid(&mut res);
+                    } else {
+                        self.handle_proppatch(req, path, Path::new(&dav.directory), &mut res)
+                            .await?;
                     }
                 }
                 "MKCOL" => {
@@ -247,6 +401,11 @@ impl WebdavServer {
                         *res.body_mut() = Body::from("Unsupported Media Type");
                     } else {
                         self.handle_mkcol(path, &mut res).await?;
+                        if let Some(file_request) = file_request
+                            && res.status() == StatusCode::CREATED
+                        {
+                            file_request.uploaded.insert(path.to_path_buf());
+                        }
                     }
                 }
                 "COPY" => {
@@ -255,7 +414,7 @@ impl WebdavServer {
                     } else if is_miss {
                         status_not_found(&mut res);
                     } else {
-                        self.handle_copymove(path, req, &mut res, &dav.directory)
+                        self.handle_copymove(path, req, &mut res, &dav.directory, &tokens)
                             .await?;
                     }
                 }
@@ -265,22 +424,23 @@ impl WebdavServer {
                     } else if is_miss {
                         status_not_found(&mut res);
                     } else {
-                        self.handle_copymove(path, req, &mut res, &dav.directory)
+                        self.handle_copymove(path, req, &mut res, &dav.directory, &tokens)
                             .await?;
                     }
                 }
                 "LOCK" => {
-                    if is_dir {
-                        status_not_found(&mut res);
+                    if !dav.writable || file_request.is_some() {
+                        status_forbid(&mut res);
                     } else {
-                        self.handle_lock(path, req, is_miss, &mut res)?;
+                        self.handle_lock(path, req, is_miss, &tokens, key, &mut res)
+                            .await?;
                     }
                 }
                 "UNLOCK" => {
                     if is_miss {
                         status_not_found(&mut res);
                     } else {
-                        self.handle_unlock(path, req, &mut res)?;
+                        self.handle_unlock(path, req, &mut res).await?;
                     }
                 }
                 _ => {
@@ -296,11 +456,24 @@ impl WebdavServer {
         path: &Path,
         req: Request,
         res: &mut Response,
-        key: Option<[u8; 32]>,
+        dav: &Dav,
+        fsync: bool,
     ) -> BoxResult<()> {
+        let key = dav.key;
         ensure_path_parent(path).await?;
+        // The upload replaces the target of a symbolic link, not the link itself
+        let path = match fs::symlink_metadata(path).await {
+            Ok(meta) if meta.is_symlink() => fs::canonicalize(path).await?,
+            _ => path.to_path_buf(),
+        };
+        if fs::metadata(&path).await.is_ok_and(|m| m.is_dir()) {
+            status_forbid(res);
+            return Ok(());
+        }
 
-        let mut file = if let Ok(v) = DavFile::create(path, key).await {
+        // The file is written beside the target, which is only replaced once the upload succeeded
+        let temp_path = upload_temp_path(&path)?;
+        let mut file = if let Ok(v) = DavFile::create(&temp_path, key).await {
             v
         } else {
             status_forbid(res);
@@ -316,7 +489,7 @@ impl WebdavServer {
             *res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
             *res.body_mut() = Body::from("error writing file");
             error!(
-                "WARNING: The file creation on {}{} encountered an error: {}. The file was not created or was deleted !",
+                "WARNING: The file creation on {}{} encountered an error: {}. The file was not created or kept its previous content !",
                 parts
                     .headers
                     .get(http::header::HOST)
@@ -326,32 +499,379 @@ impl WebdavServer {
                 parts.uri,
                 e
             );
-            drop(fs::remove_file(path).await);
+            drop(fs::remove_file(&temp_path).await);
             return Ok(());
         };
 
+        // The upload fails rather than losing the replaced content
+        if let Err(e) = keep_version(dav, &path).await {
+            drop(fs::remove_file(&temp_path).await);
+            return Err(e.into());
+        }
+        if let Err(e) = Self::replace_with_upload(file, &temp_path, &path, fsync).await {
+            drop(fs::remove_file(&temp_path).await);
+            return Err(e.into());
+        }
+
+        set_oc_mtime(&parts.headers, &path, res);
+        // The entity tag of the new content allows the next write to be conditional
+        if let Some(etag) = resource_etag(&path, key).await {
+            res.headers_mut().typed_insert(etag);
+        }
+
+        *res.status_mut() = StatusCode::CREATED;
+        Ok(())
+    }
+
+    /// Complete an upload written to a temporary file and rename it over its target
+    async fn replace_with_upload(
+        mut file: DavFile,
+        temp_path: &Path,
+        path: &Path,
+        fsync: bool,
+    ) -> Result<(), Error> {
         file.shutdown().await?;
+        drop(file);
+        // The replaced file keeps its permissions
+        if let Ok(meta) = fs::metadata(path).await {
+            fs::set_permissions(temp_path, meta.permissions()).await?;
+        }
+        if fsync {
+            fs::File::open(temp_path).await?.sync_all().await?;
+        }
+        properties::replace(path, temp_path).await?;
+        if fsync && let Some(parent) = path.parent() {
+            // The rename itself is durable once the folder is flushed
+            fs::File::open(parent).await?.sync_all().await?;
+        }
+        Ok(())
+    }
+
+    /// Create a tus upload of a file of the collection, named by the filename metadata
+    async fn handle_tus_create(
+        &self,
+        path: &Path,
+        req: Request,
+        res: &mut Response,
+        dav: &Dav,
+        tokens: &[String],
+    ) -> BoxResult<()> {
+        set_tus_headers(res);
+        if !check_tus_resumable(&req, res) {
+            return Ok(());
+        }
+        let headers = req.headers();
+        let length = headers
+            .get("upload-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        // The file is created in the collection, its name cannot be a path
+        let name = headers
+            .get("upload-metadata")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| tus::metadata_value(v, "filename"))
+            .filter(|name| {
+                Path::new(name)
+                    .file_name()
+                    .is_some_and(|n| n == name.as_str())
+            });
+        let (Some(length), Some(name)) = (length, name) else {
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            return Ok(());
+        };
+        let target = path.join(name);
+        if is_hidden(&target) || fs::metadata(&target).await.is_ok_and(|m| m.is_dir()) {
+            *res.status_mut() = StatusCode::CONFLICT;
+            return Ok(());
+        }
+        // The preconditions are those of the file the upload will write
+        let exists = fs::metadata(&target).await.is_ok();
+        if !write_preconditions_pass(headers, &target, exists, dav.key).await {
+            *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+            return Ok(());
+        }
+        if let Err(lock) = self.locks.check_write(&target, false, true, tokens) {
+            status_locked(res, &lock);
+            return Ok(());
+        }
+        let directory = Path::new(&dav.directory);
+        tus::clean_expired(directory).await;
+        let upload = Upload::create(directory, target, length, tokens.to_vec()).await?;
+        // An empty file is complete right away
+        if upload.is_complete() {
+            keep_version(dav, &upload.target).await?;
+            upload.finish(directory, dav.key).await?;
+            self.locks.written(&upload.target);
+        }
+        set_upload_headers(res, &upload)?;
+        res.headers_mut().insert(
+            LOCATION,
+            HeaderValue::from_str(&format!("/{}/{}", tus::UPLOADS_DIR, upload.id))?,
+        );
+        *res.status_mut() = StatusCode::CREATED;
+        Ok(())
+    }
+
+    /// Give the offset of a tus upload, append to it or terminate it
+    async fn handle_tus_upload(
+        &self,
+        id: &str,
+        req: Request,
+        res: &mut Response,
+        dav: &Dav,
+        file_request: Option<&FileRequest>,
+    ) -> BoxResult<()> {
+        set_tus_headers(res);
+        if req.method() == Method::OPTIONS {
+            status_no_content(res);
+            return Ok(());
+        }
+        if !dav.writable || file_request.is_some() {
+            status_forbid(res);
+            return Ok(());
+        }
+        if !check_tus_resumable(&req, res) {
+            return Ok(());
+        }
+        let directory = Path::new(&dav.directory);
+        tus::clean_expired(directory).await;
+        let Some(mut upload) = Upload::load(directory, id).await else {
+            status_not_found(res);
+            return Ok(());
+        };
+        match req.method().as_str() {
+            "HEAD" => {
+                set_upload_headers(res, &upload)?;
+                res.headers_mut()
+                    .insert("upload-length", HeaderValue::from(upload.length));
+            }
+            "PATCH" => self.handle_tus_patch(&mut upload, req, res, dav).await?,
+            "DELETE" => {
+                upload.remove(directory).await;
+                status_no_content(res);
+            }
+            _ => status_method_not_allowed(res),
+        }
+        Ok(())
+    }
 
-        // If the X-OC-Mtime header is present, alter the file modified time according to that header's value.
-        if let Some(h) = parts.headers.get("X-OC-Mtime")
-            && let Ok(h) = h.to_str()
-            && let Ok(t) = h.parse::<i64>()
-            && filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(t, 0)).is_ok()
+    async fn handle_tus_patch(
+        &self,
+        upload: &mut Upload,
+        req: Request,
+        res: &mut Response,
+        dav: &Dav,
+    ) -> BoxResult<()> {
+        let headers = req.headers();
+        if headers
+            .get(CONTENT_TYPE)
+            .is_none_or(|v| v != "application/offset+octet-stream")
         {
-            // Respond with the appropriate header on success
-            res.headers_mut().insert("X-OC-Mtime", ACCEPTED.clone());
+            *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            return Ok(());
+        }
+        let Some(offset) = headers
+            .get("upload-offset")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+        else {
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            return Ok(());
         };
+        if offset != upload.offset() {
+            *res.status_mut() = StatusCode::CONFLICT;
+            return Ok(());
+        }
+        let checksum = match headers.get("upload-checksum") {
+            Some(v) => {
+                let Some(checksum) = v.to_str().ok().and_then(Checksum::parse) else {
+                    *res.status_mut() = StatusCode::BAD_REQUEST;
+                    return Ok(());
+                };
+                Some(checksum)
+            }
+            None => None,
+        };
+        // The locks are those submitted at the creation or with the PATCH,
+        // a target locked in the meantime is reported before the data is sent rather than once complete
+        let mut tokens = headers
+            .typed_get::<If>()
+            .as_ref()
+            .map_or_else(Vec::new, If::tokens);
+        tokens.extend(upload.lock_tokens.iter().cloned());
+        if let Err(lock) = self.locks.check_write(&upload.target, false, true, &tokens) {
+            status_locked(res, &lock);
+            return Ok(());
+        }
 
-        *res.status_mut() = StatusCode::CREATED;
+        let directory = Path::new(&dav.directory);
+        let appended = upload
+            .append(directory, req.into_body(), checksum.as_ref(), dav.key)
+            .await?;
+        set_upload_headers(res, upload)?;
+        if appended == Appended::ChecksumMismatch {
+            // Checksum Mismatch status of the tus checksum extension
+            *res.status_mut() = StatusCode::from_u16(460)?;
+            return Ok(());
+        }
+        if upload.is_complete() {
+            if let Err(lock) = self.locks.check_write(&upload.target, false, true, &tokens) {
+                status_locked(res, &lock);
+                return Ok(());
+            }
+            keep_version(dav, &upload.target).await?;
+            if upload.finish(directory, dav.key).await.is_err() {
+                *res.status_mut() = StatusCode::CONFLICT;
+                return Ok(());
+            }
+            self.locks.written(&upload.target);
+        }
+        status_no_content(res);
+        Ok(())
+    }
+
+    /// Create or delete a transfer of the Nextcloud chunking, write its chunks and assemble them
+    async fn handle_chunked_upload(
+        &self,
+        chunked: ChunkedPath,
+        req: Request,
+        res: &mut Response,
+        dav: &Dav,
+        file_request: Option<&FileRequest>,
+    ) -> BoxResult<()> {
+        if !dav.writable || file_request.is_some() {
+            status_forbid(res);
+            return Ok(());
+        }
+        chunking::clean_expired(Path::new(&dav.directory)).await;
+        let transfer_exists = fs::metadata(&chunked.transfer)
+            .await
+            .is_ok_and(|m| m.is_dir());
+        match (req.method().as_str(), chunked.chunk.as_deref()) {
+            ("MKCOL", None) => {
+                if transfer_exists {
+                    status_method_not_allowed(res);
+                } else {
+                    fs::create_dir_all(&chunked.transfer).await?;
+                    *res.status_mut() = StatusCode::CREATED;
+                }
+            }
+            ("DELETE", None) => {
+                if transfer_exists {
+                    fs::remove_dir_all(&chunked.transfer).await?;
+                    status_no_content(res);
+                } else {
+                    status_not_found(res);
+                }
+            }
+            (_, _) if !transfer_exists => status_not_found(res),
+            ("PUT", Some(name)) if chunking::chunk_number(name).is_some() => {
+                chunking::write_chunk(&chunked.transfer, name, req.into_body(), dav.key).await?;
+                *res.status_mut() = StatusCode::CREATED;
+            }
+            ("MOVE", Some(chunking::ASSEMBLY_FILE)) => {
+                self.handle_chunked_move(&chunked.transfer, req, res, dav)
+                    .await?;
+            }
+            _ => status_method_not_allowed(res),
+        }
+        Ok(())
+    }
+
+    /// Assemble the chunks of a transfer into the destination file
+    async fn handle_chunked_move(
+        &self,
+        transfer: &Path,
+        req: Request,
+        res: &mut Response,
+        dav: &Dav,
+    ) -> BoxResult<()> {
+        let headers = req.headers();
+        let (dest, exists) = match self.extract_dest(headers, &dav.directory).await {
+            Some(Destination::ExistingFile(dest)) => (dest, true),
+            Some(Destination::FileToBe(dest)) => (dest, false),
+            Some(_) => {
+                *res.status_mut() = StatusCode::CONFLICT;
+                return Ok(());
+            }
+            None => {
+                status_forbid(res);
+                return Ok(());
+            }
+        };
+        if is_hidden(&dest)
+            || dest.parent().is_none_or(|parent| !parent.is_dir())
+            || ChunkedPath::parse(&dest, &dav.directory).is_some()
+        {
+            *res.status_mut() = StatusCode::CONFLICT;
+            return Ok(());
+        }
+        // The preconditions of the final MOVE are those of the file it writes
+        if !write_preconditions_pass(headers, &dest, exists, dav.key).await {
+            *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+            return Ok(());
+        }
+        let tokens = headers
+            .typed_get::<If>()
+            .as_ref()
+            .map_or_else(Vec::new, If::tokens);
+        if let Err(lock) = self.locks.check_write(&dest, false, true, &tokens) {
+            status_locked(res, &lock);
+            return Ok(());
+        }
+
+        let (assembled, len) = chunking::assemble(transfer, dav.key).await?;
+        // The clients give the expected size of the file, to detect a missing chunk
+        if headers
+            .get("OC-Total-Length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .is_some_and(|total| total != len)
+        {
+            drop(fs::remove_file(&assembled).await);
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            *res.body_mut() = Body::from("chunks do not match the total length");
+            return Ok(());
+        }
+        keep_version(dav, &dest).await?;
+        properties::replace(&dest, &assembled).await?;
+        drop(fs::remove_dir_all(transfer).await);
+        self.locks.written(&dest);
+
+        set_oc_mtime(headers, &dest, res);
+        if let Some(etag) = resource_etag(&dest, dav.key).await {
+            res.headers_mut().typed_insert(etag);
+        }
+        if exists {
+            status_no_content(res);
+        } else {
+            *res.status_mut() = StatusCode::CREATED;
+        }
         Ok(())
     }
 
-    async fn handle_delete(&self, path: &Path, is_dir: bool, res: &mut Response) -> BoxResult<()> {
-        match is_dir {
-            true => fs::remove_dir_all(path).await?,
-            false => fs::remove_file(path).await?,
+    async fn handle_delete(
+        &self,
+        path: &Path,
+        is_dir: bool,
+        res: &mut Response,
+        dav: &Dav,
+    ) -> BoxResult<()> {
+        // With a trash bin, the resource is kept with its original path and its dead properties to be restored
+        match (&dav.trash, is_dir) {
+            (Some(trash), _) => {
+                trash::move_to_trash(Path::new(&dav.directory), path, trash).await?;
+            }
+            (None, true) => {
+                fs::remove_dir_all(path).await?;
+                properties::remove(path).await;
+            }
+            (None, false) => {
+                fs::remove_file(path).await?;
+                properties::remove(path).await;
+            }
         }
-        self.locks.remove(path);
+        self.locks.remove_within(path);
 
         status_no_content(res);
         Ok(())
@@ -362,43 +882,49 @@ impl WebdavServer {
         path: &Path,
         query: &str,
         res: &mut Response,
-        directory: &str,
-        allow_symlinks: bool,
-        key: Option<[u8; 32]>,
+        dav: &Dav,
+        file_request: Option<&FileRequest>,
     ) -> BoxResult<()> {
-        let mut paths: Vec<PathItem> = vec![];
-        let mut walkdir = WalkDir::new(path);
-        while let Some(entry) = walkdir.next().await {
-            if let Ok(entry) = entry {
-                if !entry
-                    .file_name()
-                    .to_string_lossy()
-                    .to_lowercase()
-                    .contains(&query.to_lowercase())
-                {
-                    continue;
-                }
-                if fs::symlink_metadata(entry.path()).await.is_err() {
-                    continue;
-                }
-                if let Ok(Some(item)) = self
-                    .to_pathitem(
-                        entry.path(),
-                        path.to_path_buf(),
-                        directory,
-                        allow_symlinks,
-                        &key,
-                    )
-                    .await
-                {
-                    paths.push(item);
+        let query = query.to_lowercase();
+        let directory = dav.directory.clone();
+        let (allow_symlinks, key) = (dav.allow_symlinks, dav.key);
+        let file_request = file_request.cloned();
+        let base_path = path.to_path_buf();
+        let matches = WalkDir::new(path).filter_map(move |entry| {
+            future::ready(entry.ok().map(|entry| entry.path()).filter(|entry_path| {
+                !is_hidden(entry_path)
+                    && file_request
+                        .as_ref()
+                        .is_none_or(|f| f.is_visible(entry_path, &directory))
+                    && entry_path
+                        .file_name()
+                        .is_some_and(|name| name.to_string_lossy().to_lowercase().contains(&query))
+            }))
+        });
+        let directory = dav.directory.clone();
+        // The results are written as they are found, as a JSON array
+        let items = matches
+            .map(move |entry_path| {
+                let (base_path, directory) = (base_path.clone(), directory.clone());
+                async move {
+                    Self::to_pathitem(entry_path, base_path, &directory, allow_symlinks, &key)
+                        .await
+                        .ok()
+                        .flatten()
                 }
-            }
-        }
-        let j = serde_json::to_string(&paths)?;
+            })
+            .buffered(METADATA_CONCURRENCY)
+            .filter_map(future::ready)
+            .enumerate()
+            .map(|(i, item)| {
+                serde_json::to_string(&item).map(|j| if i == 0 { j } else { format!(",{j}") })
+            });
+        let body = stream::once(future::ready(Ok("[".to_owned())))
+            .chain(items)
+            .chain(stream::once(future::ready(Ok("]".to_owned()))));
         res.headers_mut()
             .insert(CONTENT_TYPE, APPLICATION_JSON.clone());
-        *res.body_mut() = Body::from(j);
+        *res.body_mut() = Body::from_stream(body);
         Ok(())
     }
 
@@ -554,72 +1080,92 @@ impl WebdavServer {
         Ok(())
     }
 
-    async fn handle_propfind_dir(
+    async fn handle_propfind(
         &self,
         path: &Path,
-        headers: &HeaderMap<HeaderValue>,
+        req: Request,
         res: &mut Response,
-        directory: &str,
-        allow_symlinks: bool,
-        key: Option<[u8; 32]>,
+        dav: &Dav,
+        file_request: Option<&FileRequest>,
+        depth_infinity_limit: Option<usize>,
     ) -> BoxResult<()> {
-        let base_path = Path::new(directory);
-        let depth: u32 = match headers.get("depth") {
-            Some(v) => {
-                if let Some(v) = v.to_str().ok().and_then(|v| v.parse().ok()) {
-                    v
-                } else {
-                    *res.status_mut() = StatusCode::BAD_REQUEST;
-                    return Ok(());
-                }
-            }
-            None => 1,
-        };
-        let mut paths = vec![
-            self.to_pathitem(path, base_path, directory, allow_symlinks, &key)
-                .await?
-                .ok_or(Error::other("no paths"))?,
-        ];
-        if depth != 0 {
-            if let Ok(child) = self
-                .list_dir(path, base_path, directory, allow_symlinks, &key)
-                .await
-            {
-                paths.extend(child);
-            } else {
-                status_forbid(res);
+        // Without Depth header, only the members of a collection are listed to keep the response bounded
+        let depth = match req.headers().typed_try_get::<Depth>() {
+            Ok(depth) => depth.unwrap_or(Depth::One),
+            Err(_) => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
                 return Ok(());
             }
-        }
-        let output = paths
-            .iter()
-            .map(|v| v.to_dav_xml("/"))
-            .fold(String::new(), |mut acc, v| {
-                acc.push_str(&v);
-                acc
-            });
-        res_multistatus(res, &output);
-        Ok(())
-    }
-
-    async fn handle_propfind_file(
-        &self,
-        path: &Path,
-        res: &mut Response,
-        directory: &str,
-        allow_symlinks: bool,
-        key: Option<[u8; 32]>,
-    ) -> BoxResult<()> {
-        let base_path = Path::new(directory);
-        let self_uri_prefix = "/";
-        if let Some(pathitem) = self
-            .to_pathitem(path, base_path, directory, allow_symlinks, &key)
-            .await?
-        {
-            res_multistatus(res, &pathitem.to_dav_xml(self_uri_prefix));
-        } else {
+        };
+        let body = req.into_body().collect().await?.to_bytes();
+        let Ok(Some(propfind)) = Propfind::parse(&String::from_utf8_lossy(&body)) else {
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            return Ok(());
+        };
+        let quota =
+            propfind.asks_for("quota-available-bytes") || propfind.asks_for("quota-used-bytes");
+        let context = PropfindContext {
+            directory: dav.directory.clone(),
+            allow_symlinks: dav.allow_symlinks,
+            key: dav.key,
+            locks: self.locks.clone(),
+            propfind: Arc::new(propfind),
+            quota,
+        };
+        let Some(root) = context.response(path).await else {
             status_not_found(res);
+            return Ok(());
+        };
+        if depth == Depth::Zero || !fs::metadata(path).await.is_ok_and(|m| m.is_dir()) {
+            res_multistatus(res, &root);
+            return Ok(());
+        }
+        if fs::read_dir(path).await.is_err() {
+            status_forbid(res);
+            return Ok(());
+        }
+        let recursive = depth == Depth::Infinity;
+        let file_request = file_request.cloned();
+        if recursive {
+            // The members are counted first as the status cannot be changed once streaming
+            let within_limit = match depth_infinity_limit {
+                Some(limit) => {
+                    walk_members(
+                        path.to_path_buf(),
+                        true,
+                        dav.directory.clone(),
+                        file_request.clone(),
+                    )
+                    .take(limit)
+                    .count()
+                    .await
+                        < limit
+                }
+                None => false,
+            };
+            if !within_limit {
+                status_lock_error(res, StatusCode::FORBIDDEN, "propfind-finite-depth", "");
+                return Ok(());
+            }
         }
+        // The responses are formatted while the collection is read, a few at a time
+        let members = walk_members(
+            path.to_path_buf(),
+            recursive,
+            dav.directory.clone(),
+            file_request,
+        )
+        .map(move |member| {
+            let context = context.clone();
+            async move { context.response(&member).await }
+        })
+        .buffered(METADATA_CONCURRENCY)
+        .filter_map(future::ready);
+        let body = stream::once(future::ready(format!("{MULTISTATUS_START}{root}")))
+            .chain(members)
+            .chain(stream::once(future::ready(MULTISTATUS_END.to_owned())))
+            .map(Ok::<_, Error>);
+        res_multistatus_stream(res, Body::from_stream(body));
         Ok(())
     }
 
@@ -633,58 +1179,104 @@ impl WebdavServer {
         }
     }
 
-    fn handle_lock(
+    async fn handle_lock(
         &self,
         path: &Path,
         req: Request,
         is_miss: bool,
+        tokens: &[String],
+        key: Option<[u8; 32]>,
         res: &mut Response,
     ) -> BoxResult<()> {
-        debug!("LockMap on lock: {:?}", self.locks);
-        clean_expired_locks(&self.locks);
-
-        if self.locks.contains_key(path) {
-            *res.status_mut() = StatusCode::LOCKED;
-            *res.body_mut() = Body::from("Resource is already locked");
-            return Ok(());
-        }
+        self.clean_expired_locks().await;
 
-        let timeout_secs = parse_timeout_header(req.headers());
-
-        let token = format!("opaquelocktoken:{}", Uuid::new_v4());
-        self.locks.insert(
-            path.to_path_buf(),
-            LockInfo {
-                token: token.clone(),
-                expires_at: Utc::now() + Duration::seconds(timeout_secs),
-            },
-        );
+        let infinite = match req.headers().typed_get::<Depth>() {
+            Some(Depth::Infinity) | None => true,
+            Some(Depth::Zero) => false,
+            Some(Depth::One) => {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return Ok(());
+            }
+        };
+        let timeout = parse_timeout_header(req.headers());
+        let href = req.uri().path().to_owned();
+        let body = req.into_body().collect().await?.to_bytes();
+        let xml_body = String::from_utf8(body.to_vec())?;
 
-        // Timeout header in response
-        let timeout_resp = if timeout_secs == i64::MAX {
-            "Infinite".to_string()
+        // A LOCK request without body and with a submitted token refreshes the lock
+        let refresh = xml_body.trim().is_empty() && !tokens.is_empty();
+        let lock = if refresh {
+            if let Some(lock) = self.locks.refresh(path, tokens, timeout) {
+                lock
+            } else {
+                *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+                return Ok(());
+            }
         } else {
-            format!("Second-{timeout_secs}")
+            let info = if xml_body.trim().is_empty() {
+                LockRequest::default()
+            } else if let Ok(info) = LockRequest::parse(&xml_body) {
+                info
+            } else {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return Ok(());
+            };
+            // Locking an unmapped URL creates an empty resource in its collection
+            if is_miss {
+                let parent_is_dir = match path.parent() {
+                    Some(parent) => fs::metadata(parent).await.is_ok_and(|m| m.is_dir()),
+                    None => false,
+                };
+                if !parent_is_dir {
+                    *res.status_mut() = StatusCode::CONFLICT;
+                    return Ok(());
+                }
+                if let Err(lock) = self.locks.check_write(path, false, true, tokens) {
+                    status_locked(res, &lock);
+                    return Ok(());
+                }
+            }
+            let mut lock = Lock::new(path.to_path_buf(), href, info, infinite, timeout);
+            lock.lock_null = is_miss;
+            let lock = match self.locks.lock(lock) {
+                Ok(lock) => lock,
+                Err(conflict) => {
+                    status_lock_error(
+                        res,
+                        StatusCode::LOCKED,
+                        "no-conflicting-lock",
+                        &conflict.href,
+                    );
+                    return Ok(());
+                }
+            };
+            if is_miss {
+                let created = match DavFile::create(path, key).await {
+                    Ok(mut file) => file.shutdown().await.is_ok(),
+                    Err(_) => false,
+                };
+                if !created {
+                    self.locks.unlock(path, &lock.token);
+                    drop(fs::remove_file(path).await);
+                    status_forbid(res);
+                    return Ok(());
+                }
+            }
+            res.headers_mut()
+                .insert("lock-token", format!("<{}>", lock.token).parse()?);
+            lock
         };
+
         res.headers_mut().insert(
             CONTENT_TYPE,
             HeaderValue::from_static("application/xml; charset=utf-8"),
         );
         res.headers_mut()
-            .insert("lock-token", format!("<{token}>").parse()?);
-        res.headers_mut()
-            .insert("Timeout", HeaderValue::from_str(&timeout_resp)?);
-
+            .insert("Timeout", format!("Second-{}", lock.timeout).parse()?);
         *res.body_mut() = Body::from(format!(
             r#"<?xml version="1.0" encoding="utf-8"?>
-<D:prop xmlns:D="DAV:"><D:lockdiscovery><D:activelock>
-<D:locktoken><D:href>{}</D:href></D:locktoken>
-<D:lockroot><D:href>{}</D:href></D:lockroot>
-<D:timeout>{}</D:timeout>
-</D:activelock></D:lockdiscovery></D:prop>"#,
-            token,
-            req.uri().path(),
-            timeout_resp
+<D:prop xmlns:D="DAV:"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>"#,
+            lock.to_xml()
         ));
         if is_miss {
             *res.status_mut() = StatusCode::CREATED;
@@ -692,16 +1284,15 @@ impl WebdavServer {
         Ok(())
     }
 
-    fn handle_unlock(&self, path: &Path, req: Request, res: &mut Response) -> BoxResult<()> {
-        clean_expired_locks(&self.locks);
-        debug!("LockMap on lock: {:?}", self.locks);
+    async fn handle_unlock(&self, path: &Path, req: Request, res: &mut Response) -> BoxResult<()> {
+        self.clean_expired_locks().await;
 
         // Extract Lock-Token header
         let token_header = req
             .headers()
             .get("Lock-Token")
             .and_then(|v| v.to_str().ok())
-            .map(|s| s.trim_matches(['<', '>'].as_ref()));
+            .map(|s| s.trim().trim_matches(['<', '>'].as_ref()));
         let token_header = if let Some(t) = token_header {
             t
         } else {
@@ -710,51 +1301,81 @@ impl WebdavServer {
             return Ok(());
         };
 
-        // Check if lock exists and token matches
-        match self.locks.get(path) {
-            Some(lock_info) if lock_info.token == token_header => {
-                // avoid deadlock
-                drop(lock_info);
-                self.locks.remove(path);
-                *res.status_mut() = StatusCode::NO_CONTENT;
-            }
-            Some(_) => {
-                *res.status_mut() = StatusCode::FORBIDDEN;
-                *res.body_mut() = Body::from("Lock-Token does not match");
-            }
-            None => {
-                *res.status_mut() = StatusCode::NOT_FOUND;
-                *res.body_mut() = Body::from("No lock found on resource");
+        // The token must be the one of a lock applying to the resource
+        if let Some(lock) = self.locks.unlock(path, token_header) {
+            // A resource created by a lock and never written disappears with it
+            if lock.lock_null {
+                drop(fs::remove_file(&lock.root).await);
             }
+            *res.status_mut() = StatusCode::NO_CONTENT;
+        } else {
+            status_lock_error(
+                res,
+                StatusCode::CONFLICT,
+                "lock-token-matches-request-uri",
+                "",
+            );
         }
         Ok(())
     }
 
+    /// Forget the expired locks, and remove the resources created by a lock and never written
+    async fn clean_expired_locks(&self) {
+        for path in self.locks.clean_expired() {
+            drop(fs::remove_file(path).await);
+        }
+    }
+
+    /// Evaluate the If header : true if all the conditions of one of its lists are true
+    async fn eval_if_header(&self, if_header: &If, path: &Path, dav: &Dav) -> bool {
+        for list in &if_header.0 {
+            let resource = match &list.resource {
+                Some(uri) => uri
+                    .parse::<Uri>()
+                    .ok()
+                    .and_then(|uri| Self::extract_path(uri.path(), &dav.directory)),
+                None => Some(path.to_path_buf()),
+            };
+            let Some(resource) = resource else {
+                continue;
+            };
+            let locks = self.locks.covering(&resource);
+            let etag = if list
+                .conditions
+                .iter()
+                .any(|c| matches!(c.item, IfItem::ETag(_)))
+            {
+                resource_etag(&resource, dav.key).await
+            } else {
+                None
+            };
+            if list.conditions.iter().all(|condition| {
+                let matches = match &condition.item {
+                    IfItem::StateToken(token) => locks.iter().any(|lock| &lock.token == token),
+                    IfItem::ETag(tag) => tag
+                        .parse::<ETag>()
+                        .is_ok_and(|tag| etag.as_ref() == Some(&tag)),
+                };
+                matches != condition.not
+            }) {
+                return true;
+            }
+        }
+        false
+    }
+
     async fn handle_proppatch(
         &self,
         req: Request,
         path: &Path,
+        root: &Path,
         res: &mut Response,
     ) -> BoxResult<()> {
-        let output: String;
         let req_path = req.uri().path().to_string();
-        if let Ok(modtime) = Self::extract_lastmodified_value(req).await {
-            filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(modtime, 0))?;
-            output = format!(
-                r#"
-                    <D:response>
-                      <D:href>{req_path}</D:href>
-                      <D:propstat>
-                        <D:prop>
-                          <D:lastmodified xmlns="DAV:">{modtime}</D:lastmodified>
-                        </D:prop>
-                        <D:status>HTTP/1.1 200 OK</D:status>
-                      </D:propstat>
-                    </D:response>
-                "#
-            );
-        } else {
-            output = format!(
+        let body = req.into_body().collect().await?.to_bytes();
+        let updates = PropertyUpdate::parse(&String::from_utf8(body.to_vec())?).unwrap_or_default();
+        if updates.is_empty() {
+            let output = format!(
                 r#"
                 <D:response>
                     <D:href>{req_path}</D:href>
@@ -766,42 +1387,89 @@ impl WebdavServer {
                 </D:response>
                 "#
             );
+            res_multistatus(res, &output);
+            return Ok(());
         }
-        res_multistatus(res, &output);
-        Ok(())
-    }
 
-    async fn extract_lastmodified_value(req: Request) -> BoxResult<i64> {
-        let body = req.into_body().collect().await?.to_bytes();
-        let xml_body = String::from_utf8(body.to_vec())?;
-
-        let mut in_lastmodified_tag = false;
-        let mut lastmodified_value = String::new();
-
-        let mut reader = Reader::from_str(&xml_body);
-        loop {
-            match reader.read_event() {
-                Err(e) => return Err(Box::new(e)),
-                Ok(Event::Eof) => break,
-                Ok(Event::Start(e)) => {
-                    in_lastmodified_tag = e.name().as_ref() == b"lastmodified";
-                }
-                Ok(Event::End(_)) => {
-                    in_lastmodified_tag = false;
-                }
-                Ok(Event::Text(e)) => {
-                    if in_lastmodified_tag {
-                        lastmodified_value = e.decode()?.into_owned();
-                        break;
-                    }
+        // The live properties are protected, except the modification time that can be set with lastmodified,
+        // and the root has no dead properties since they would be kept next to it, outside of the dav
+        let is_root = path == root;
+        let forbidden = |update: &PropertyUpdate| {
+            (is_root || update.namespace == "DAV:") && proppatch_modtime(update).is_none()
+        };
+        // The update is atomic : nothing is changed if a property cannot be
+        let failed = updates.iter().any(forbidden);
+        if !failed {
+            let mut dead = properties::load(path).await;
+            for update in &updates {
+                if let Some(modtime) = proppatch_modtime(update) {
+                    filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(modtime, 0))?;
+                    continue;
                 }
-                _ => (),
+                let key = DeadProperties::key(&update.namespace, &update.name);
+                match &update.value {
+                    Some(value) => dead.0.insert(key, value.clone()),
+                    None => dead.0.remove(&key),
+                };
+            }
+            properties::save(path, &dead).await?;
+        }
+
+        let mut propstats = vec![
+            ("200 OK", String::new()),
+            ("403 Forbidden", String::new()),
+            ("424 Failed Dependency", String::new()),
+        ];
+        for update in &updates {
+            let (status, prop) = match proppatch_modtime(update) {
+                Some(modtime) if !failed => (
+                    0,
+                    format!(r#"<D:lastmodified xmlns="DAV:">{modtime}</D:lastmodified>"#),
+                ),
+                _ => (
+                    if !failed {
+                        0
+                    } else if forbidden(update) {
+                        1
+                    } else {
+                        2
+                    },
+                    format!(
+                        r#"<{} xmlns="{}"/>"#,
+                        update.name,
+                        escape(&update.namespace)
+                    ),
+                ),
+            };
+            if let Some((_, props)) = propstats.get_mut(status) {
+                props.push_str(&prop);
             }
         }
-        Ok(lastmodified_value.parse::<i64>()?)
+        let propstats = propstats
+            .iter()
+            .filter(|(_, props)| !props.is_empty())
+            .map(|(status, props)| {
+                format!(
+                    r#"<D:propstat>
+<D:prop>{props}</D:prop>
+<D:status>HTTP/1.1 {status}</D:status>
+</D:propstat>"#
+                )
+            })
+            .collect::<String>();
+        res_multistatus(
+            res,
+            &format!(
+                r#"<D:response>
+<D:href>{req_path}</D:href>
+{propstats}
+</D:response>"#
+            ),
+        );
+        Ok(())
     }
 
-    async fn is_root_contained(&self, path: &Path, directory: &Path) -> bool {
+    async fn is_root_contained(path: &Path, directory: &Path) -> bool {
         let (path, dir) = tokio::join!(fs::canonicalize(path), fs::canonicalize(directory));
         let dir = match dir {
             Ok(dir) => dir,
@@ -830,36 +1498,7 @@ impl WebdavServer {
         Some(self_path.join(stripped_path))
     }
 
-    async fn list_dir(
-        &self,
-        entry_path: &Path,
-        base_path: &Path,
-        directory: &str,
-        allow_symlinks: bool,
-        key: &Option<[u8; 32]>,
-    ) -> BoxResult<Vec<PathItem>> {
-        let mut paths: Vec<PathItem> = vec![];
-        let mut rd = fs::read_dir(entry_path).await?;
-        while let Ok(Some(entry)) = rd.next_entry().await {
-            let entry_path = entry.path();
-            if let Ok(Some(item)) = self
-                .to_pathitem(
-                    entry_path.as_path(),
-                    base_path,
-                    directory,
-                    allow_symlinks,
-                    key,
-                )
-                .await
-            {
-                paths.push(item);
-            }
-        }
-        Ok(paths)
-    }
-
     async fn to_pathitem<P: AsRef<Path>>(
-        &self,
         path: P,
         base_path: P,
         directory: &str,
@@ -873,7 +1512,7 @@ impl WebdavServer {
         let is_symlink = meta2.is_symlink();
         if !allow_symlinks
             && is_symlink
-            && !self.is_root_contained(path, Path::new(directory)).await
+            && !Self::is_root_contained(path, Path::new(directory)).await
         {
             return Ok(None);
         }
@@ -885,6 +1524,8 @@ impl WebdavServer {
             (false, false) => PathType::File,
         };
         let mtime = to_timestamp(&meta.modified()?);
+        // The creation time is not available on every file system
+        let created = meta.created().map_or(mtime, |t| to_timestamp(&t));
         let size = match path_type {
             PathType::Dir | PathType::SymlinkDir => None,
             PathType::File | PathType::SymlinkFile => Some(if key.is_some() {
@@ -898,6 +1539,7 @@ impl WebdavServer {
             path_type,
             name,
             mtime,
+            created,
             size,
         }))
     }
@@ -908,6 +1550,7 @@ impl WebdavServer {
         req: Request,
         res: &mut Response,
         dav_path: &str,
+        tokens: &[String],
     ) -> BoxResult<()> {
         // get and check headers.
         let overwrite = req.headers().typed_get::<Overwrite>().is_none_or(|o| o.0);
@@ -934,6 +1577,12 @@ impl WebdavServer {
             return Ok(());
         }
 
+        // The versions and the other sidecar folders cannot be written through a copy or a move
+        if is_hidden(dest.path()) {
+            *res.status_mut() = StatusCode::FORBIDDEN;
+            return Ok(());
+        }
+
         // Fails if collection parent does not exist
         if dest.path().parent().is_none()
             || !dest
@@ -972,7 +1621,12 @@ impl WebdavServer {
 
         // COPY or MOVE
         if req.method().as_str() == "COPY" {
+            if let Err(lock) = self.locks.check_write(dest.path(), true, true, tokens) {
+                status_locked(res, &lock);
+                return Ok(());
+            }
             Self::do_copy(path, dest.path(), dest.path(), dest.is_dir(), depth).await?;
+            self.locks.written(dest.path());
             if overwrite && dest.exists() {
                 *res.status_mut() = StatusCode::NO_CONTENT;
             } else {
@@ -983,10 +1637,17 @@ impl WebdavServer {
             if path.is_file() && dest.is_dir() {
                 dest.push(path.file_name().ok_or(Error::other("no path"))?.into());
             }
+            if let Err(lock) = self.locks.check_write(dest.path(), true, true, tokens) {
+                status_locked(res, &lock);
+                return Ok(());
+            }
             if path.is_dir() && dest.is_file() && dest.exists() {
                 *res.status_mut() = StatusCode::NO_CONTENT;
             } else {
                 fs::rename(path, dest.path()).await?;
+                properties::rename(path, dest.path()).await?;
+                // The locks of the source are not moved with it
+                self.locks.remove_within(path);
                 if dest.exists() {
                     *res.status_mut() = StatusCode::NO_CONTENT;
                 } else {
@@ -1020,6 +1681,9 @@ impl WebdavServer {
             // create dest if directory
             if dest_is_dir_or_to_be {
                 fs::create_dir(dest).await.ok();
+                if meta.is_dir() {
+                    properties::copy(source, dest).await?;
+                }
             }
 
             // if it's a file we can overwrite it.
@@ -1030,8 +1694,8 @@ impl WebdavServer {
                             .file_name()
                             .ok_or_else(|| Error::other("could not extract file name"))?,
                     );
-                    return match fs::copy(source, destfile).await {
-                        Ok(_) => Ok(()),
+                    return match fs::copy(source, &destfile).await {
+                        Ok(_) => properties::copy(source, &destfile).await,
                         Err(e) => {
                             debug!("do_copy: fs::copy error: {:?}", e);
                             Err(e)
@@ -1039,7 +1703,7 @@ impl WebdavServer {
                     };
                 } else {
                     return match fs::copy(source, dest).await {
-                        Ok(_) => Ok(()),
+                        Ok(_) => properties::copy(source, dest).await,
                         Err(e) => {
                             debug!("do_copy: fs::copy error: {:?}", e);
                             Err(e)
@@ -1069,6 +1733,10 @@ impl WebdavServer {
                     Err(e) => return Err(e),
                 };
                 let name = dirent.file_name();
+                // The dead properties are copied with each resource, the uploads in progress are not copied
+                if is_hidden(Path::new(&name)) {
+                    continue;
+                }
                 let nsrc = source.join(&name);
                 let ndest = dest.join(&name);
 
@@ -1084,11 +1752,106 @@ impl WebdavServer {
     }
 }
 
+/// What is needed to give the properties of the resources listed by a PROPFIND
+#[derive(Clone)]
+struct PropfindContext {
+    directory: String,
+    allow_symlinks: bool,
+    key: Option<[u8; 32]>,
+    locks: Arc<LockManager>,
+    propfind: Arc<Propfind>,
+    quota: bool,
+}
+
+impl PropfindContext {
+    /// The response element of a resource, None if it cannot be served
+    async fn response(&self, path: &Path) -> Option<String> {
+        let base_path = Path::new(&self.directory);
+        let item = WebdavServer::to_pathitem(
+            path,
+            base_path,
+            &self.directory,
+            self.allow_symlinks,
+            &self.key,
+        )
+        .await
+        .ok()??;
+        let props = self.properties(&item, &base_path.join(&item.name)).await;
+        Some(item.to_dav_xml("/", &props, &self.propfind))
+    }
+
+    /// The live and dead properties of a resource, the quota ones are costly and only computed on demand
+    async fn properties(&self, item: &PathItem, path: &Path) -> Vec<Property> {
+        let mut props = item.live_properties();
+        props.push(Property {
+            name: PropertyName::dav("supportedlock"),
+            element: SUPPORTED_LOCK.to_owned(),
+        });
+        props.push(Property {
+            name: PropertyName::dav("lockdiscovery"),
+            element: self.locks.lockdiscovery(path),
+        });
+        if self.quota && item.is_dir() {
+            if let Ok(real_path) = fs::canonicalize(path).await
+                && let Ok(disk) = crate::sysinfo::disk_info(real_path).await
+            {
+                props.push(Property::dav(
+                    "quota-available-bytes",
+                    &disk.available_space.to_string(),
+                ));
+            }
+            props.push(Property::dav(
+                "quota-used-bytes",
+                &dir_size(path).await.to_string(),
+            ));
+        }
+        if path != Path::new(&self.directory) {
+            props.extend(properties::load(path).await.properties());
+        }
+        props
+    }
+}
+
+/// The members of a collection, and of its sub collections if recursive, as they are read
+fn walk_members(
+    dir: PathBuf,
+    recursive: bool,
+    directory: String,
+    file_request: Option<FileRequest>,
+) -> impl Stream<Item = PathBuf> + Send {
+    async_stream::stream! {
+        let mut dirs = vec![dir];
+        while let Some(dir) = dirs.pop() {
+            let Ok(mut rd) = fs::read_dir(&dir).await else {
+                continue;
+            };
+            while let Ok(Some(entry)) = rd.next_entry().await {
+                let entry_path = entry.path();
+                // The members of the versions collection are listed, its sidecar folders are not
+                if is_hidden(Path::new(&entry.file_name()))
+                    || file_request
+                        .as_ref()
+                        .is_some_and(|f| !f.is_visible(&entry_path, &directory))
+                {
+                    continue;
+                }
+                // Symbolic links to collections are not followed
+                if recursive && entry.file_type().await.is_ok_and(|t| t.is_dir()) {
+                    dirs.push(entry_path.clone());
+                }
+                yield entry_path;
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Eq, PartialEq, Ord, PartialOrd)]
 struct PathItem {
     path_type: PathType,
     name: String,
     mtime: u64,
+    #[serde(skip)]
+    created: u64,
     size: Option<u64>,
 }
 
@@ -1097,52 +1860,79 @@ impl PathItem {
         self.path_type == PathType::Dir || self.path_type == PathType::SymlinkDir
     }
 
-    pub fn to_dav_xml(&self, prefix: &str) -> String {
-        let mut mtime = Utc
+    /// The properties computed from the file system
+    fn live_properties(&self) -> Vec<Property> {
+        let mtime = Utc
             .timestamp_millis_opt(self.mtime as i64)
-            .unwrap()
-            .to_rfc2822();
-        mtime.truncate(mtime.len() - 6);
-        let mtime = format!("{mtime} GMT");
+            .single()
+            .map(|t| {
+                let mut mtime = t.to_rfc2822();
+                mtime.truncate(mtime.len() - 6);
+                format!("{mtime} GMT")
+            })
+            .unwrap_or_default();
+        let created = Utc
+            .timestamp_millis_opt(self.created as i64)
+            .single()
+            .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true))
+            .unwrap_or_default();
+        let mut props = vec![
+            Property::dav("displayname", &escape(self.base_name())),
+            Property::dav("creationdate", &created),
+            Property::dav("getlastmodified", &mtime),
+        ];
+        if self.is_dir() {
+            props.push(Property::dav("resourcetype", "<D:collection/>"));
+        } else {
+            let size = self.size.unwrap_or_default();
+            let content_type = mime_guess::from_path(&self.name).first_or_octet_stream();
+            props.extend([
+                Property::dav("getcontentlength", &size.to_string()),
+                Property::dav("resourcetype", ""),
+                Property::dav("getetag", &format!("\"{}-{size}\"", self.mtime)),
+                Property::dav("getcontenttype", &escape(content_type.as_ref())),
+            ]);
+        }
+        props
+    }
+
+    /// The response element of a PROPFIND : the found properties, and the missing ones with a 404 status
+    pub fn to_dav_xml(&self, prefix: &str, props: &[Property], propfind: &Propfind) -> String {
         let mut href = encode_uri(&format!("{}{}", prefix, &self.name));
         if self.is_dir() && !href.ends_with('/') {
             href.push('/');
         }
-        let displayname = escape(self.base_name());
-        match self.path_type {
-            PathType::Dir | PathType::SymlinkDir => format!(
-                r#"<D:response>
-<D:href>{href}</D:href>
-<D:propstat>
-<D:prop>
-<D:displayname>{displayname}</D:displayname>
-<D:getlastmodified>{mtime}</D:getlastmodified>
-<D:resourcetype><D:collection/></D:resourcetype>
-</D:prop>
-<D:status>HTTP/1.1 200 OK</D:status>
-</D:propstat>
-</D:response>"#
-            ),
-            PathType::File | PathType::SymlinkFile => format!(
-                r#"<D:response>
-<D:href>{}</D:href>
-<D:propstat>
-<D:prop>
-<D:displayname>{}</D:displayname>
-<D:getcontentlength>{}</D:getcontentlength>
-<D:getlastmodified>{}</D:getlastmodified>
-<D:resourcetype></D:resourcetype>
-</D:prop>
-<D:status>HTTP/1.1 200 OK</D:status>
-</D:propstat>
-</D:response>"#,
-                href,
-                displayname,
-                self.size.unwrap_or_default(),
-                mtime
+        let (found, missing) = match propfind {
+            Propfind::AllProp(_) => (props.iter().map(|p| p.element.clone()).collect(), vec![]),
+            Propfind::PropName => (
+                props.iter().map(|p| p.name.empty_element()).collect(),
+                vec![],
             ),
+            Propfind::Prop(names) => {
+                let (mut found, mut missing) = (vec![], vec![]);
+                for name in names {
+                    match props.iter().find(|p| &p.name == name) {
+                        Some(prop) => found.push(prop.element.clone()),
+                        None => missing.push(name.empty_element()),
+                    }
+                }
+                (found, missing)
+            }
+        };
+        let mut propstats = String::new();
+        if !found.is_empty() || missing.is_empty() {
+            propstats.push_str(&propstat(&found, "200 OK"));
+        }
+        if !missing.is_empty() {
+            propstats.push_str(&propstat(&missing, "404 Not Found"));
         }
+        format!(
+            r#"<D:response>
+<D:href>{href}</D:href>
+{propstats}</D:response>"#
+        )
     }
+
     fn base_name(&self) -> &str {
         Path::new(&self.name)
             .file_name()
@@ -1159,6 +1949,34 @@ enum PathType {
     SymlinkFile,
 }
 
+fn propstat(props: &[String], status: &str) -> String {
+    format!(
+        r#"<D:propstat>
+<D:prop>
+{}
+</D:prop>
+<D:status>HTTP/1.1 {status}</D:status>
+</D:propstat>
+"#,
+        props.join("\n")
+    )
+}
+
+/// Size of the files within a collection
+pub(crate) async fn dir_size(path: &Path) -> u64 {
+    let mut size = 0;
+    let mut walkdir = WalkDir::new(path);
+    while let Some(entry) = walkdir.next().await {
+        if let Ok(entry) = entry
+            && let Ok(meta) = fs::symlink_metadata(entry.path()).await
+            && meta.is_file()
+        {
+            size += meta.len();
+        }
+    }
+    size
+}
+
 fn to_timestamp(time: &SystemTime) -> u64 {
     time.duration_since(SystemTime::UNIX_EPOCH)
         .map_or(0, |t| t.as_millis()) as u64
@@ -1184,17 +2002,19 @@ async fn ensure_path_parent(path: &Path) -> BoxResult<()> {
 }
 
 fn res_multistatus(res: &mut Response, content: &str) {
+    res_multistatus_stream(
+        res,
+        Body::from(format!("{MULTISTATUS_START}{content}{MULTISTATUS_END}")),
+    );
+}
+
+fn res_multistatus_stream(res: &mut Response, body: Body) {
     *res.status_mut() = StatusCode::MULTI_STATUS;
     res.headers_mut().insert(
         CONTENT_TYPE,
         HeaderValue::from_static("application/xml; charset=utf-8"),
     );
-    *res.body_mut() = Body::from(format!(
-        r#"<?xml version="1.0" encoding="utf-8" ?>
-<D:multistatus xmlns:D="DAV:">
-{content}
-</D:multistatus>"#,
-    ));
+    *res.body_mut() = body;
 }
 
 async fn zip_dir<W: tokio::io::AsyncWrite + Unpin>(
@@ -1218,6 +2038,10 @@ async fn zip_dir<W: tokio::io::AsyncWrite + Unpin>(
                 Some(v) => v,
                 None => continue,
             };
+            // Relative to the zipped folder, so that the versions collection can be zipped too
+            if is_hidden(Path::new(filename)) {
+                continue;
+            }
 
             let mut file = DavFile::open(&entry_path, key).await?;
 
@@ -1264,6 +2088,80 @@ fn parse_range(headers: &HeaderMap<HeaderValue>) -> Option<RangeValue> {
     }
 }
 
+/// The sidecar folders of the dead properties, the trash bin, the versions and the uploads in progress are not part of the served files
+pub(crate) fn is_hidden(path: &Path) -> bool {
+    properties::is_hidden(path)
+        || trash::is_hidden(path)
+        || tus::is_hidden(path)
+        || versions::is_hidden(path)
+        || path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(UPLOAD_TEMP_PREFIX))
+}
+
+/// Keep the content of a file about to be replaced as a version, if the dav is versioned
+async fn keep_version(dav: &Dav, path: &Path) -> Result<(), Error> {
+    match &dav.versioning {
+        Some(versioning) if fs::symlink_metadata(path).await.is_ok_and(|m| m.is_file()) => {
+            versions::keep(Path::new(&dav.directory), path, versioning).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A temporary file beside the target of an upload, unique to the upload
+pub(crate) fn upload_temp_path(path: &Path) -> Result<PathBuf, Error> {
+    let parent = path.parent().ok_or(Error::other("no parent"))?;
+    Ok(parent.join(format!("{UPLOAD_TEMP_PREFIX}{}", Uuid::new_v4())))
+}
+
+fn set_tus_headers(res: &mut Response) {
+    let headers = res.headers_mut();
+    headers.insert("tus-resumable", HeaderValue::from_static(tus::TUS_VERSION));
+    headers.insert("tus-version", HeaderValue::from_static(tus::TUS_VERSION));
+    headers.insert(
+        "tus-extension",
+        HeaderValue::from_static(tus::TUS_EXTENSIONS),
+    );
+    headers.insert(
+        "tus-checksum-algorithm",
+        HeaderValue::from_static(tus::TUS_CHECKSUM_ALGORITHMS),
+    );
+}
+
+/// The tus requests must give the version of the protocol they use
+fn check_tus_resumable(req: &Request, res: &mut Response) -> bool {
+    let supported = req
+        .headers()
+        .get("tus-resumable")
+        .is_some_and(|v| v == tus::TUS_VERSION);
+    if !supported {
+        *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+    }
+    supported
+}
+
+fn set_upload_headers(res: &mut Response, upload: &Upload) -> BoxResult<()> {
+    let headers = res.headers_mut();
+    headers.insert("upload-offset", HeaderValue::from(upload.offset()));
+    headers.insert("upload-expires", HeaderValue::from_str(&upload.expires())?);
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(())
+}
+
+/// If the X-OC-Mtime header is present, alter the file modified time according to that header's value
+fn set_oc_mtime(headers: &HeaderMap<HeaderValue>, path: &Path, res: &mut Response) {
+    if let Some(h) = headers.get("X-OC-Mtime")
+        && let Ok(h) = h.to_str()
+        && let Ok(t) = h.parse::<i64>()
+        && filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(t, 0)).is_ok()
+    {
+        // Respond with the appropriate header on success
+        res.headers_mut().insert("X-OC-Mtime", ACCEPTED.clone());
+    }
+}
+
 fn status_forbid(res: &mut Response) {
     *res.status_mut() = StatusCode::FORBIDDEN;
     *res.body_mut() = Body::from("Forbidden");
@@ -1274,6 +2172,36 @@ fn status_not_found(res: &mut Response) {
     *res.body_mut() = Body::from("Not Found");
 }
 
+/// The modification time set with the lastmodified property of a PROPPATCH
+fn proppatch_modtime(update: &PropertyUpdate) -> Option<i64> {
+    if update.namespace == "DAV:" && update.name == "lastmodified" {
+        update.value.as_ref()?.trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+fn status_locked(res: &mut Response, lock: &Lock) {
+    status_lock_error(res, StatusCode::LOCKED, "lock-token-submitted", &lock.href);
+}
+
+fn status_lock_error(res: &mut Response, status: StatusCode, condition: &str, href: &str) {
+    *res.status_mut() = status;
+    res.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/xml; charset=utf-8"),
+    );
+    let href = if href.is_empty() {
+        String::new()
+    } else {
+        format!("<D:href>{}</D:href>", escape(href))
+    };
+    *res.body_mut() = Body::from(format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<D:error xmlns:D="DAV:"><D:{condition}>{href}</D:{condition}></D:error>"#
+    ));
+}
+
 fn status_no_content(res: &mut Response) {
     *res.status_mut() = StatusCode::NO_CONTENT;
 }
@@ -1380,9 +2308,57 @@ impl Destination {
     }
 }
 
-fn clean_expired_locks(locks: &DashMap<PathBuf, LockInfo>) {
-    let now = Utc::now();
-    locks.retain(|_, info| now < info.expires_at);
+async fn resource_etag(path: &Path, key: Option<[u8; 32]>) -> Option<ETag> {
+    resource_cache_headers(path, key)
+        .await
+        .map(|(etag, _)| etag)
+}
+
+async fn resource_cache_headers(
+    path: &Path,
+    key: Option<[u8; 32]>,
+) -> Option<(ETag, LastModified)> {
+    if !fs::metadata(path).await.ok()?.is_file() {
+        return None;
+    }
+    let file = DavFile::open(path, key).await.ok()?;
+    file.cache_headers().await
+}
+
+/// Evaluate the If-Match, If-Unmodified-Since and If-None-Match preconditions of a write against the resource
+async fn write_preconditions_pass(
+    headers: &HeaderMap<HeaderValue>,
+    path: &Path,
+    exists: bool,
+    key: Option<[u8; 32]>,
+) -> bool {
+    let cache_headers = if exists {
+        resource_cache_headers(path, key).await
+    } else {
+        None
+    };
+    let etag = cache_headers.as_ref().map(|(etag, _)| etag);
+    if let Some(if_match) = headers.typed_get::<IfMatch>() {
+        // A collection has no entity tag, it only matches *
+        let passes = if if_match.is_any() {
+            exists
+        } else {
+            etag.is_some_and(|etag| if_match.precondition_passes(etag))
+        };
+        if !passes {
+            return false;
+        }
+    } else if let Some(if_unmodified_since) = headers.typed_get::<IfUnmodifiedSince>()
+        && let Some((_, last_modified)) = cache_headers
+        && !if_unmodified_since.precondition_passes(last_modified.into())
+    {
+        return false;
+    }
+    match headers.typed_get::<IfNoneMatch>() {
+        Some(if_none_match) if if_none_match == IfNoneMatch::any() => !exists,
+        Some(if_none_match) => etag.is_none_or(|etag| if_none_match.precondition_passes(etag)),
+        None => true,
+    }
 }
 
 fn parse_timeout_header(headers: &HeaderMap<HeaderValue>) -> i64 {
@@ -1400,4 +2376,4 @@ fn parse_timeout_header(headers: &HeaderMap<HeaderValue>) -> i64 {
         }
     }
     LOCK_TIMEOUT
-}
\ No newline at end of file
+}
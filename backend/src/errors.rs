@@ -70,6 +70,27 @@ impl From<rcgen::Error> for Error {
     }
 }
 
+impl From<lettre::transport::smtp::Error> for Error {
+    fn from(value: lettre::transport::smtp::Error) -> Self {
+        error!("smtp error: {value}");
+        Error("smtp error")
+    }
+}
+
+impl From<lettre::error::Error> for Error {
+    fn from(value: lettre::error::Error) -> Self {
+        error!("e-mail building error: {value}");
+        Error("e-mail building error")
+    }
+}
+
+impl From<lettre::address::AddressError> for Error {
+    fn from(value: lettre::address::AddressError) -> Self {
+        error!("e-mail address error: {value}");
+        Error("e-mail address error")
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{self:?}")
@@ -13,13 +13,15 @@ pub type OptionalJail = Option<std::sync::Arc<jail::Jail>>;
 // TODO : remove the OptionalJail when cfg conditionals are supported in where clauses
 #[cfg(not(target_os = "linux"))]
 pub type OptionalJail = ();
+pub mod auth;
 pub mod logger;
+pub mod mail;
 pub mod middlewares;
 pub mod mocks;
 pub mod oauth2;
 pub mod onlyoffice;
+pub mod scim;
 pub mod server;
 pub mod sysinfo;
-pub mod auth;
 pub mod utils;
 pub mod web;
@@ -0,0 +1,55 @@
+use crate::{
+    configuration::{SmtpConfig, SmtpTls},
+    errors::Error,
+};
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Mailbox, header::ContentType},
+    transport::smtp::{
+        SMTP_PORT, SUBMISSION_PORT, SUBMISSIONS_PORT,
+        authentication::Credentials,
+        client::{Tls, TlsParameters},
+    },
+};
+use std::time::Duration;
+
+/// Maximum duration of the exchange with the SMTP relay
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Send a plain text e-mail through the configured SMTP relay
+pub async fn send_mail(
+    smtp: &SmtpConfig,
+    to: &str,
+    subject: &str,
+    body: String,
+) -> Result<(), Error> {
+    let message = Message::builder()
+        .from(smtp.from.parse::<Mailbox>()?)
+        .to(to.parse::<Mailbox>()?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)?;
+    let (tls, default_port) = match smtp.tls {
+        SmtpTls::No => (Tls::None, SMTP_PORT),
+        SmtpTls::StartTls => (
+            Tls::Required(TlsParameters::new(smtp.host.clone())?),
+            SUBMISSION_PORT,
+        ),
+        SmtpTls::Tls => (
+            Tls::Wrapper(TlsParameters::new(smtp.host.clone())?),
+            SUBMISSIONS_PORT,
+        ),
+    };
+    let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host)
+        .port(smtp.port.unwrap_or(default_port))
+        .tls(tls)
+        .timeout(Some(SMTP_TIMEOUT));
+    if let Some(username) = &smtp.username {
+        transport = transport.credentials(Credentials::new(
+            username.clone(),
+            smtp.password.clone().unwrap_or_default(),
+        ));
+    }
+    transport.build().send(message).await?;
+    Ok(())
+}
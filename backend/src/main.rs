@@ -1,5 +1,9 @@
 use atrium::extract::Host;
 use atrium::{
+    auth::{
+        bulk::{BulkFormat, export_users, import_users},
+        client_cert::{ClientCertAcceptor, server_config_builder},
+    },
     configuration::{Config, TlsMode},
     errors::Error,
     mocks::{mock_oauth2_server, mock_proxied_server},
@@ -8,7 +12,6 @@ use atrium::{
 use axum::{BoxError, handler::HandlerWithoutStateExt, response::Redirect};
 use axum_server::Handle;
 use http::{StatusCode, Uri};
-use rustls::ServerConfig;
 use rustls_acme::{AcmeConfig, caches::DirCache};
 use std::{
     fs::File,
@@ -31,6 +34,12 @@ pub mod self_signed;
 pub const CONFIG_FILE: &str = "atrium.yaml";
 
 fn main() -> Result<(), Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some((command, args)) = args.split_first()
+        && command == "users"
+    {
+        return users_command(args);
+    }
     // println!("MiMalloc version: {}", mimalloc::MiMalloc.version()); // mimalloc = { version = "0.1", features = ["extended"] } in Cargo.toml to use this
     // We need to work out the local time offset before entering multi-threaded context
     let cfg: Config = if let Ok(file) = File::open(CONFIG_FILE) {
@@ -44,6 +53,56 @@ fn main() -> Result<(), Error> {
     run()
 }
 
+/// Bulk import or export the users of the configuration file :
+/// `atrium users import <file.csv|file.json> [--dry-run]` or `atrium users export [--csv]`
+#[tokio::main]
+async fn users_command(args: &[String]) -> Result<(), Error> {
+    let mut config = Config::from_file(CONFIG_FILE).await?;
+    match args {
+        [command, file, options @ ..] if command == "import" => {
+            let dry_run = options.iter().any(|o| o == "--dry-run");
+            let content = tokio::fs::read_to_string(file).await?;
+            let report = import_users(
+                &mut config,
+                &content,
+                BulkFormat::from_path(file),
+                dry_run,
+                |_, _| true,
+            )
+            .map_err(|e| Error(e.1))?;
+            if !dry_run {
+                config.to_file(CONFIG_FILE).await?;
+            }
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report)
+                    .map_err(|_| Error("could not serialize import report"))?
+            );
+            if !dry_run {
+                eprintln!("Reload the configuration of a running server to apply the changes.");
+            }
+        }
+        [command, options @ ..] if command == "export" => {
+            let format = if options.iter().any(|o| o == "--csv") {
+                BulkFormat::Csv
+            } else {
+                BulkFormat::Json
+            };
+            print!(
+                "{}",
+                export_users(config.users.iter(), format).map_err(|e| Error(e.1))?
+            );
+        }
+        _ => {
+            eprintln!(
+                "Usage: atrium users import <file.csv|file.json> [--dry-run]\n       atrium users export [--csv]"
+            );
+            return Err(Error("invalid users command"));
+        }
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn run() -> Result<(), Error> {
     let debug_mode = Config::from_file(CONFIG_FILE).await?.debug_mode;
@@ -117,11 +176,11 @@ async fn run() -> Result<(), Error> {
                     .cache(DirCache::new("./letsencrypt_cache"))
                     .state();
 
-                let mut rustls_config = ServerConfig::builder()
-                    .with_no_client_auth()
-                    .with_cert_resolver(state.resolver());
+                let mut rustls_config =
+                    server_config_builder(&config.0)?.with_cert_resolver(state.resolver());
                 rustls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-                let acceptor = state.axum_acceptor(Arc::new(rustls_config));
+                let acceptor =
+                    ClientCertAcceptor::new(state.axum_acceptor(Arc::new(rustls_config)));
 
                 tokio::spawn(async move {
                     loop {
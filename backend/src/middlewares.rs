@@ -47,11 +47,18 @@ pub async fn debug_cors_middleware(req: Request, next: Next) -> Result<Response,
 fn allow_methods_headers_credentials(headers: &mut http::HeaderMap) {
     headers.insert(
         "Access-Control-Allow-Methods",
-        "POST, GET, OPTIONS, PUT, DELETE, PROPFIND, PROPPATCH, MKCOL, MOVE, COPY"
+        "POST, GET, OPTIONS, PUT, PATCH, DELETE, PROPFIND, PROPPATCH, MKCOL, MOVE, COPY"
+            .parse()
+            .expect("infallible"),
+    );
+    headers.insert("Access-Control-Allow-Headers", "Accept, Content-Type, Content-Length, Accept-Encoding, XSRF-TOKEN, Authorization, Depth, Destination, Overwrite, If, If-Match, If-None-Match, If-Unmodified-Since, X-OC-Mtime, OC-Total-Length, Tus-Resumable, Upload-Length, Upload-Metadata, Upload-Offset, Upload-Checksum".parse().expect("infallible"));
+    // The writes give the entity tag of the resource, the tus resumable uploads their location and progress
+    headers.insert(
+        "Access-Control-Expose-Headers",
+        "ETag, Location, Tus-Resumable, Tus-Version, Tus-Extension, Tus-Checksum-Algorithm, Upload-Offset, Upload-Length, Upload-Expires"
             .parse()
             .expect("infallible"),
     );
-    headers.insert("Access-Control-Allow-Headers", "Accept, Content-Type, Content-Length, Accept-Encoding, XSRF-TOKEN, Authorization, Depth, Destination, Overwrite, X-OC-Mtime".parse().expect("infallible"));
     headers.insert(
         "Access-Control-Allow-Credentials",
         "true".parse().expect("infallible"),
@@ -9,7 +9,11 @@ use axum::{
 };
 use http::{HeaderMap, HeaderValue, StatusCode, header};
 use serde::Deserialize;
-use tokio::net::TcpListener;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::UnboundedSender,
+};
 
 pub async fn mock_proxied_server(listener: TcpListener) {
     let port = listener
@@ -52,6 +56,44 @@ async fn headers(headers: HeaderMap) -> impl IntoResponse {
     format!("HEADERS: {headers:?}")
 }
 
+/// Minimal SMTP relay accepting every e-mail, the received messages (headers and body) are sent to `mails`
+pub async fn mock_smtp_server(listener: TcpListener, mails: UnboundedSender<String>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let mails = mails.clone();
+        tokio::spawn(async move { smtp_session(stream, &mails).await });
+    }
+}
+
+async fn smtp_session(stream: TcpStream, mails: &UnboundedSender<String>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(b"220 mock smtp\r\n").await?;
+    while let Some(line) = lines.next_line().await? {
+        let command = line.to_ascii_uppercase();
+        let reply: &[u8] = if command.starts_with("DATA") {
+            writer.write_all(b"354 end data with .\r\n").await?;
+            let mut mail = String::new();
+            while let Some(line) = lines.next_line().await?
+                && line != "."
+            {
+                mail.push_str(&line);
+                mail.push('\n');
+            }
+            if mails.send(mail).is_err() {
+                return Ok(());
+            }
+            b"250 queued\r\n"
+        } else if command.starts_with("QUIT") {
+            writer.write_all(b"221 bye\r\n").await?;
+            return Ok(());
+        } else {
+            b"250 mock smtp\r\n"
+        };
+        writer.write_all(reply).await?;
+    }
+    Ok(())
+}
+
 pub async fn mock_oauth2_server(listener: TcpListener) {
     let app = Router::new()
         .route("/.well-known/openid-configuration", get(well_known_openid))
@@ -1,9 +1,9 @@
 use crate::{
     appstate::{ConfigState, MAXMIND_READER},
+    auth::{User, UserInfo, create_user_cookie, delegation::can_administrate, user_to_token},
     configuration::OpenIdConfig,
     errors::ErrResponse,
     extract::Host,
-    auth::{ADMINS_ROLE, User, UserInfo, create_user_cookie, user_to_token},
     utils::select_entries_by_value,
 };
 use axum::{
@@ -272,6 +272,7 @@ pub async fn oauth2_callback(
             family_name: user_data.family_name,
             email: user_data.email,
         }),
+        ..Default::default()
     };
 
     let user_token = user_to_token(&user, &config);
@@ -291,7 +292,7 @@ pub async fn oauth2_callback(
         } else {
             Redirect::to(&format!(
                 "/oauth2/oauth2.html?is_admin={}&xsrf_token={}&user={}",
-                user.roles.contains(&ADMINS_ROLE.to_owned()),
+                can_administrate(&user_token.roles, &config),
                 user_token.xsrf_token.unwrap_or("no_token".to_owned()),
                 user.login
             ))
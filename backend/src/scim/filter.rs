@@ -0,0 +1,387 @@
+use super::{
+    ScimError,
+    model::{GROUP_SCHEMA, USER_SCHEMA},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+/// A SCIM filter of the form `attribute eq "value"`, which is what identity providers use to look up resources
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    pub attribute: String,
+    pub value: String,
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Self, ScimError> {
+        let invalid = || {
+            ScimError::bad_request(
+                "invalidFilter",
+                "only `attribute eq value` filters are supported",
+            )
+        };
+        let (attribute, rest) = filter
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        let (operator, value) = rest
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        if !operator.eq_ignore_ascii_case("eq") {
+            return Err(invalid());
+        }
+        let value = value.trim();
+        // Quoted values are JSON strings, the others are booleans or numbers
+        let value = if value.starts_with('"') {
+            serde_json::from_str::<String>(value).map_err(|_| invalid())?
+        } else {
+            value.to_owned()
+        };
+        Ok(Filter {
+            attribute: attribute.to_owned(),
+            value,
+        })
+    }
+
+    /// Tells if one of the values of the (possibly multi-valued and dotted) attribute is equal to the filter value,
+    /// ignoring case as the attributes used for lookups (userName, displayName, emails) are case insensitive
+    pub fn matches(&self, resource: &Value) -> bool {
+        let mut values = vec![resource];
+        for segment in self.attribute.split('.') {
+            values = values
+                .into_iter()
+                .flat_map(|v| match v {
+                    Value::Array(items) => items.iter().collect(),
+                    v => vec![v],
+                })
+                .filter_map(|v| get_ignore_case(v, segment))
+                .collect();
+        }
+        values.into_iter().any(|v| match v {
+            Value::String(s) => s.eq_ignore_ascii_case(&self.value),
+            Value::Array(items) => items.iter().any(|i| {
+                i.as_str()
+                    .is_some_and(|s| s.eq_ignore_ascii_case(&self.value))
+            }),
+            v => v.to_string() == self.value,
+        })
+    }
+}
+
+fn get_ignore_case<'a>(value: &'a Value, attribute: &str) -> Option<&'a Value> {
+    value
+        .as_object()?
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(attribute))
+        .map(|(_, v)| v)
+}
+
+/// The key of the attribute in the object, keeping the case of an existing key
+fn key_ignore_case(object: &Map<String, Value>, attribute: &str) -> String {
+    object
+        .keys()
+        .find(|k| k.eq_ignore_ascii_case(attribute))
+        .cloned()
+        .unwrap_or_else(|| attribute.to_owned())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PatchOperation {
+    pub op: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Add,
+    Replace,
+    Remove,
+}
+
+/// A patch path : `attribute`, `attribute.sub`, `attribute[filter]` or `attribute[filter].sub`
+#[derive(Debug, PartialEq, Eq)]
+struct PatchPath {
+    attribute: String,
+    filter: Option<Filter>,
+    sub_attribute: Option<String>,
+}
+
+impl PatchPath {
+    fn parse(path: &str) -> Result<Self, ScimError> {
+        let invalid = || ScimError::bad_request("invalidPath", "invalid patch path");
+        if let Some((attribute, rest)) = path.split_once('[') {
+            let (filter, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            let sub_attribute = match rest {
+                "" => None,
+                rest => Some(rest.strip_prefix('.').ok_or_else(invalid)?.to_owned()),
+            };
+            return Ok(PatchPath {
+                attribute: attribute.to_owned(),
+                filter: Some(Filter::parse(filter)?),
+                sub_attribute,
+            });
+        }
+        let (attribute, sub_attribute) = match path.split_once('.') {
+            Some((attribute, sub)) => (attribute, Some(sub.to_owned())),
+            None => (path, None),
+        };
+        Ok(PatchPath {
+            attribute: attribute.to_owned(),
+            filter: None,
+            sub_attribute,
+        })
+    }
+}
+
+/// Apply the operations of a SCIM patch request to the JSON representation of a resource
+pub fn apply_patch(resource: &mut Value, operations: Vec<PatchOperation>) -> Result<(), ScimError> {
+    for operation in operations {
+        let op = match operation.op.to_ascii_lowercase().as_str() {
+            "add" => Op::Add,
+            "replace" => Op::Replace,
+            "remove" => Op::Remove,
+            _ => {
+                return Err(ScimError::bad_request(
+                    "invalidSyntax",
+                    "unknown patch operation",
+                ));
+            }
+        };
+        match operation.path {
+            Some(path) => {
+                // Attributes of the core schemas may be prefixed by the schema, the extensions are not supported
+                let path = [USER_SCHEMA, GROUP_SCHEMA]
+                    .iter()
+                    .find_map(|s| path.strip_prefix(s).and_then(|p| p.strip_prefix(':')))
+                    .unwrap_or(&path);
+                if path.starts_with("urn:") {
+                    continue;
+                }
+                apply_operation(resource, op, &PatchPath::parse(path)?, operation.value)?;
+            }
+            // Without path, the value gives the attributes to add or replace
+            None => {
+                let Some(Value::Object(attributes)) = operation.value else {
+                    return Err(ScimError::bad_request(
+                        "noTarget",
+                        "a patch operation without path requires an object value",
+                    ));
+                };
+                if op == Op::Remove {
+                    return Err(ScimError::bad_request(
+                        "noTarget",
+                        "a remove patch operation requires a path",
+                    ));
+                }
+                for (path, value) in attributes {
+                    if path.starts_with("urn:") {
+                        continue;
+                    }
+                    apply_operation(resource, op, &PatchPath::parse(&path)?, Some(value))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn apply_operation(
+    resource: &mut Value,
+    op: Op,
+    path: &PatchPath,
+    value: Option<Value>,
+) -> Result<(), ScimError> {
+    let object = resource
+        .as_object_mut()
+        .ok_or_else(|| ScimError::bad_request("invalidPath", "patch target is not an object"))?;
+    let key = key_ignore_case(object, &path.attribute);
+    if op != Op::Remove && value.is_none() {
+        return Err(ScimError::bad_request(
+            "invalidValue",
+            "patch operation requires a value",
+        ));
+    }
+
+    match (&path.filter, &path.sub_attribute) {
+        (None, None) => {
+            let current = object.get_mut(&key);
+            match (op, current, value) {
+                (Op::Remove, Some(Value::Array(items)), Some(Value::Array(removed))) => {
+                    items.retain(|i| !removed.iter().any(|r| same_value(i, r)));
+                }
+                (Op::Remove, _, _) => {
+                    object.remove(&key);
+                }
+                (Op::Add, Some(Value::Array(items)), Some(Value::Array(added))) => {
+                    for a in added {
+                        if !items.iter().any(|i| same_value(i, &a)) {
+                            items.push(a);
+                        }
+                    }
+                }
+                (_, Some(Value::Object(current)), Some(Value::Object(value))) => {
+                    current.extend(value);
+                }
+                (_, _, Some(value)) => {
+                    object.insert(key, value);
+                }
+                (_, _, None) => {}
+            }
+        }
+        (None, Some(sub_attribute)) => {
+            let target = object
+                .entry(key)
+                .or_insert_with(|| Value::Object(Map::new()));
+            let sub_path = PatchPath::parse(sub_attribute)?;
+            match target {
+                Value::Array(items) => {
+                    for item in items {
+                        apply_operation(item, op, &sub_path, value.clone())?;
+                    }
+                }
+                target => apply_operation(target, op, &sub_path, value)?,
+            }
+        }
+        (Some(filter), sub_attribute) => {
+            let items = object.entry(key).or_insert_with(|| Value::Array(vec![]));
+            let Value::Array(items) = items else {
+                return Err(ScimError::bad_request(
+                    "invalidFilter",
+                    "filtered patch path targets a single-valued attribute",
+                ));
+            };
+            match (op, sub_attribute) {
+                (Op::Remove, None) => items.retain(|i| !filter.matches(i)),
+                (Op::Remove, Some(sub_attribute)) => {
+                    for item in items.iter_mut().filter(|i| filter.matches(i)) {
+                        if let Some(item) = item.as_object_mut() {
+                            let key = key_ignore_case(item, sub_attribute);
+                            item.remove(&key);
+                        }
+                    }
+                }
+                (_, sub_attribute) => {
+                    let value = value.unwrap_or_default();
+                    let mut found = false;
+                    for item in items.iter_mut().filter(|i| filter.matches(i)) {
+                        found = true;
+                        set_item(item, sub_attribute.as_deref(), value.clone());
+                    }
+                    // Add the element described by the filter if there is none yet
+                    if !found {
+                        let mut item = json!({ &filter.attribute: filter.value });
+                        set_item(&mut item, sub_attribute.as_deref(), value);
+                        items.push(item);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn set_item(item: &mut Value, sub_attribute: Option<&str>, value: Value) {
+    match (item, sub_attribute, value) {
+        (Value::Object(item), Some(sub_attribute), value) => {
+            let key = key_ignore_case(item, sub_attribute);
+            item.insert(key, value);
+        }
+        (Value::Object(item), None, Value::Object(value)) => item.extend(value),
+        (item, _, value) => *item = value,
+    }
+}
+
+/// Elements of multi-valued attributes are identified by their value
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a.get("value"), b.get("value")) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(resource: &mut Value, operations: Value) -> Result<(), ScimError> {
+        let request: PatchRequest =
+            serde_json::from_value(json!({ "Operations": operations })).unwrap();
+        apply_patch(resource, request.operations)
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::parse(r#"userName eq "JDoe""#).unwrap();
+        assert_eq!(
+            filter,
+            Filter {
+                attribute: "userName".to_owned(),
+                value: "JDoe".to_owned()
+            }
+        );
+        assert!(filter.matches(&json!({"userName": "jdoe"})));
+        assert!(!filter.matches(&json!({"userName": "other"})));
+        let filter = Filter::parse(r#"emails.value eq "jdoe@atrium.io""#).unwrap();
+        assert!(filter.matches(
+            &json!({"emails": [{"value": "other@atrium.io"}, {"value": "jdoe@atrium.io"}]})
+        ));
+        assert!(
+            Filter::parse("active eq true")
+                .unwrap()
+                .matches(&json!({"active": true}))
+        );
+        assert!(Filter::parse(r#"userName sw "j""#).is_err());
+        assert!(Filter::parse("userName").is_err());
+    }
+
+    #[test]
+    fn test_patch_user() {
+        let mut user = json!({"userName": "jdoe", "active": true, "name": {"givenName": "John"}, "emails": [{"value": "jdoe@atrium.io", "type": "work"}]});
+        patch(
+            &mut user,
+            json!([
+                {"op": "Replace", "value": {"active": "False", "name.familyName": "Doe"}},
+                {"op": "replace", "path": "emails[type eq \"work\"].value", "value": "john.doe@atrium.io"},
+                {"op": "add", "path": "urn:ietf:params:scim:schemas:core:2.0:User:name.givenName", "value": "Johnny"},
+                {"op": "add", "path": "urn:ietf:params:scim:schemas:extension:enterprise:2.0:User:department", "value": "IT"}
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            user,
+            json!({"userName": "jdoe", "active": "False", "name": {"givenName": "Johnny", "familyName": "Doe"}, "emails": [{"value": "john.doe@atrium.io", "type": "work"}]})
+        );
+        assert!(patch(&mut user, json!([{"op": "remove"}])).is_err());
+        assert!(patch(&mut user, json!([{"op": "move", "path": "active"}])).is_err());
+    }
+
+    #[test]
+    fn test_patch_group_members() {
+        let mut group = json!({"displayName": "team", "members": [{"value": "jdoe"}]});
+        patch(
+            &mut group,
+            json!([
+                {"op": "add", "path": "members", "value": [{"value": "jdoe"}, {"value": "asmith"}, {"value": "bwayne"}]},
+                {"op": "remove", "path": "members[value eq \"jdoe\"]"},
+                {"op": "remove", "path": "members", "value": [{"value": "bwayne"}]}
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            group,
+            json!({"displayName": "team", "members": [{"value": "asmith"}]})
+        );
+        patch(&mut group, json!([{"op": "remove", "path": "members"}])).unwrap();
+        assert_eq!(group, json!({"displayName": "team"}));
+    }
+}
@@ -0,0 +1,506 @@
+pub mod filter;
+pub mod model;
+
+use crate::{
+    appstate::{ConfigFile, ConfigState, Reloader},
+    auth::{User, roles::Group, user::REVOKED_SESSIONS},
+    configuration::{Config, config_or_error},
+};
+use axum::{
+    Json,
+    extract::{ConnectInfo, FromRef, FromRequestParts, Path, Query, State},
+    response::{IntoResponse, Response},
+};
+use axum_extra::TypedHeader;
+use filter::{Filter, PatchRequest, apply_patch};
+use headers::{Authorization, authorization::Bearer};
+use http::{StatusCode, header::CONTENT_TYPE, request::Parts};
+use model::{
+    ERROR_SCHEMA, LIST_SCHEMA, ListResponse, SERVICE_PROVIDER_CONFIG_SCHEMA, ScimGroup, ScimUser,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tracing::info;
+
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+/// Identity providers may send concurrent requests : the configuration file updates are serialized
+static CONFIG_WRITE: Mutex<()> = Mutex::const_new(());
+
+/// An error in the SCIM format
+#[derive(Debug)]
+pub struct ScimError {
+    status: StatusCode,
+    scim_type: Option<&'static str>,
+    detail: &'static str,
+}
+
+impl ScimError {
+    pub fn new(status: StatusCode, detail: &'static str) -> Self {
+        Self {
+            status,
+            scim_type: None,
+            detail,
+        }
+    }
+
+    pub fn conflict(detail: &'static str) -> Self {
+        Self {
+            status: StatusCode::CONFLICT,
+            scim_type: Some("uniqueness"),
+            detail,
+        }
+    }
+
+    pub fn bad_request(scim_type: &'static str, detail: &'static str) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            scim_type: Some(scim_type),
+            detail,
+        }
+    }
+}
+
+impl From<(StatusCode, &'static str)> for ScimError {
+    fn from((status, detail): (StatusCode, &'static str)) -> Self {
+        Self::new(status, detail)
+    }
+}
+
+impl IntoResponse for ScimError {
+    fn into_response(self) -> Response {
+        let mut body = json!({
+            "schemas": [ERROR_SCHEMA],
+            "status": self.status.as_u16().to_string(),
+            "detail": self.detail,
+        });
+        if let (Some(scim_type), Some(body)) = (self.scim_type, body.as_object_mut()) {
+            body.insert("scimType".to_owned(), Value::from(scim_type));
+        }
+        ScimJson(self.status, body).into_response()
+    }
+}
+
+/// A JSON response with the SCIM content type
+pub struct ScimJson<T>(StatusCode, T);
+
+impl<T: Serialize> IntoResponse for ScimJson<T> {
+    fn into_response(self) -> Response {
+        (self.0, [(CONTENT_TYPE, SCIM_CONTENT_TYPE)], Json(self.1)).into_response()
+    }
+}
+
+/// The identity provider, authenticated by the bearer token of the SCIM configuration
+pub struct ScimClient;
+
+impl<S> FromRequestParts<S> for ScimClient
+where
+    S: Send + Sync,
+    ConfigState: FromRef<S>,
+    crate::OptionalJail: FromRef<S>,
+{
+    type Rejection = ScimError;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = ConfigState::from_ref(state);
+        let Some(scim_config) = config.scim_config.as_ref() else {
+            return Err(ScimError::new(StatusCode::NOT_FOUND, "SCIM is not enabled"));
+        };
+        if let Ok(TypedHeader(Authorization(bearer))) =
+            <TypedHeader<Authorization<Bearer>> as FromRequestParts<S>>::from_request_parts(
+                parts, state,
+            )
+            .await
+            && tokens_match(bearer.token(), &scim_config.bearer_token)
+        {
+            return Ok(ScimClient);
+        }
+        let addr = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|c| c.0);
+        #[cfg(target_os = "linux")]
+        if let (Some(jail), Some(addr)) = (crate::OptionalJail::from_ref(state), addr) {
+            jail.report_failure(addr.ip()).await;
+        }
+        info!("SCIM AUTHENTICATION ERROR from {:?}", addr.map(|a| a.ip()));
+        Err(ScimError::new(
+            StatusCode::UNAUTHORIZED,
+            "invalid SCIM bearer token",
+        ))
+    }
+}
+
+/// Compare the digests of the tokens so that the time taken does not tell how much of the token is right
+fn tokens_match(given: &str, expected: &str) -> bool {
+    !expected.is_empty() && Sha256::digest(given.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+fn base_url(config: &Config) -> String {
+    format!("{}/scim/v2", config.full_domain())
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+    pub filter: Option<String>,
+    pub start_index: Option<usize>,
+    pub count: Option<usize>,
+}
+
+/// Filter and paginate the resources, the start index being one based
+fn list_response<T: Serialize>(
+    resources: impl Iterator<Item = T>,
+    query: &ListQuery,
+) -> Result<ScimJson<ListResponse<Value>>, ScimError> {
+    let filter = query.filter.as_deref().map(Filter::parse).transpose()?;
+    let resources = resources
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| {
+            ScimError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "could not serialize resource",
+            )
+        })?
+        .into_iter()
+        .filter(|r| filter.as_ref().is_none_or(|f| f.matches(r)))
+        .collect::<Vec<_>>();
+    let start_index = query.start_index.unwrap_or(1).max(1);
+    let page = resources
+        .iter()
+        .skip(start_index - 1)
+        .take(query.count.unwrap_or(usize::MAX))
+        .cloned()
+        .collect::<Vec<_>>();
+    Ok(ScimJson(
+        StatusCode::OK,
+        ListResponse {
+            schemas: vec![LIST_SCHEMA.to_owned()],
+            total_results: resources.len(),
+            start_index,
+            items_per_page: page.len(),
+            resources: page,
+        },
+    ))
+}
+
+/// Save the configuration and make the running server take it into account
+async fn save(config: Config, config_file: &str, reloader: &Reloader) -> Result<(), ScimError> {
+    config.to_file_or_internal_server_error(config_file).await?;
+    reloader.schedule();
+    Ok(())
+}
+
+pub async fn service_provider_config() -> impl IntoResponse {
+    ScimJson(
+        StatusCode::OK,
+        json!({
+            "schemas": [SERVICE_PROVIDER_CONFIG_SCHEMA],
+            "patch": {"supported": true},
+            "bulk": {"supported": false, "maxOperations": 0, "maxPayloadSize": 0},
+            "filter": {"supported": true, "maxResults": i32::MAX},
+            "changePassword": {"supported": true},
+            "sort": {"supported": false},
+            "etag": {"supported": false},
+            "authenticationSchemes": [{
+                "type": "oauthbearertoken",
+                "name": "Bearer token",
+                "description": "The bearer token of the SCIM configuration"
+            }]
+        }),
+    )
+}
+
+pub async fn list_users(
+    State(config_file): State<ConfigFile>,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, ScimError> {
+    let config = config_or_error(&config_file).await?;
+    let (base_url, now) = (base_url(&config), now());
+    list_response(
+        config
+            .users
+            .iter()
+            .map(|u| ScimUser::from_user(u, &config, &base_url, now)),
+        &query,
+    )
+}
+
+pub async fn get_user(
+    State(config_file): State<ConfigFile>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ScimError> {
+    let config = config_or_error(&config_file).await?;
+    let user = config
+        .users
+        .iter()
+        .find(|u| u.login == id)
+        .ok_or(ScimError::new(StatusCode::NOT_FOUND, "user not found"))?;
+    Ok(ScimJson(
+        StatusCode::OK,
+        ScimUser::from_user(user, &config, &base_url(&config), now()),
+    ))
+}
+
+pub async fn create_user(
+    State(config_file): State<ConfigFile>,
+    State(reloader): State<Reloader>,
+    Json(payload): Json<ScimUser>,
+) -> Result<impl IntoResponse, ScimError> {
+    let _guard = CONFIG_WRITE.lock().await;
+    let mut config = config_or_error(&config_file).await?;
+    let now = now();
+    let mut user = User::default();
+    payload.apply_to(&mut user, &config.argon2, now)?;
+    if config.users.iter().any(|u| u.login == user.login) {
+        return Err(ScimError::conflict("user already exists"));
+    }
+    let response = ScimUser::from_user(&user, &config, &base_url(&config), now);
+    info!("SCIM USER CREATED: {}", user.login);
+    config.users.push(user);
+    save(config, &config_file, &reloader).await?;
+    Ok(ScimJson(StatusCode::CREATED, response))
+}
+
+pub async fn replace_user(
+    State(config_file): State<ConfigFile>,
+    State(reloader): State<Reloader>,
+    Path(id): Path<String>,
+    Json(payload): Json<ScimUser>,
+) -> Result<impl IntoResponse, ScimError> {
+    let _guard = CONFIG_WRITE.lock().await;
+    let config = config_or_error(&config_file).await?;
+    update_user(config, &config_file, &reloader, &id, |_, _| Ok(payload)).await
+}
+
+pub async fn patch_user(
+    State(config_file): State<ConfigFile>,
+    State(reloader): State<Reloader>,
+    Path(id): Path<String>,
+    Json(patch): Json<PatchRequest>,
+) -> Result<impl IntoResponse, ScimError> {
+    let _guard = CONFIG_WRITE.lock().await;
+    let config = config_or_error(&config_file).await?;
+    update_user(config, &config_file, &reloader, &id, |user, config| {
+        let mut resource =
+            serde_json::to_value(ScimUser::from_user(user, config, &base_url(config), now()))
+                .map_err(|_| {
+                    ScimError::new(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "could not serialize resource",
+                    )
+                })?;
+        apply_patch(&mut resource, patch.operations)?;
+        serde_json::from_value(resource)
+            .map_err(|_| ScimError::bad_request("invalidValue", "patched user is invalid"))
+    })
+    .await
+}
+
+/// Replace the user by the SCIM user given by `payload`, following a login change in the groups,
+/// and revoking the sessions of a deactivated user
+async fn update_user(
+    mut config: Config,
+    config_file: &str,
+    reloader: &Reloader,
+    id: &str,
+    payload: impl FnOnce(&User, &Config) -> Result<ScimUser, ScimError>,
+) -> Result<ScimJson<ScimUser>, ScimError> {
+    let now = now();
+    let mut user = config
+        .users
+        .iter()
+        .find(|u| u.login == id)
+        .cloned()
+        .ok_or(ScimError::new(StatusCode::NOT_FOUND, "user not found"))?;
+    let was_active = user.is_active(now);
+    payload(&user, &config)?.apply_to(&mut user, &config.argon2, now)?;
+    if user.login != id {
+        if config.users.iter().any(|u| u.login == user.login) {
+            return Err(ScimError::conflict("user already exists"));
+        }
+        for member in config.groups.iter_mut().flat_map(|g| g.members.iter_mut()) {
+            if *member == id {
+                member.clone_from(&user.login);
+            }
+        }
+        REVOKED_SESSIONS.insert(id.to_owned(), now);
+    }
+    if was_active && !user.is_active(now) {
+        REVOKED_SESSIONS.insert(user.login.clone(), now);
+    }
+    let response = ScimUser::from_user(&user, &config, &base_url(&config), now);
+    for existing in config.users.iter_mut().filter(|u| u.login == id) {
+        *existing = user.clone();
+    }
+    info!("SCIM USER UPDATED: {}", user.login);
+    save(config, config_file, reloader).await?;
+    Ok(ScimJson(StatusCode::OK, response))
+}
+
+pub async fn delete_user(
+    State(config_file): State<ConfigFile>,
+    State(reloader): State<Reloader>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ScimError> {
+    let _guard = CONFIG_WRITE.lock().await;
+    let mut config = config_or_error(&config_file).await?;
+    let count = config.users.len();
+    config.users.retain(|u| u.login != id);
+    if config.users.len() == count {
+        return Err(ScimError::new(StatusCode::NOT_FOUND, "user not found"));
+    }
+    for group in config.groups.iter_mut() {
+        group.members.retain(|m| *m != id);
+    }
+    REVOKED_SESSIONS.insert(id.clone(), now());
+    info!("SCIM USER DELETED: {id}");
+    save(config, &config_file, &reloader).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_groups(
+    State(config_file): State<ConfigFile>,
+    Query(query): Query<ListQuery>,
+) -> Result<impl IntoResponse, ScimError> {
+    let config = config_or_error(&config_file).await?;
+    let base_url = base_url(&config);
+    list_response(
+        config
+            .groups
+            .iter()
+            .map(|g| ScimGroup::from_group(g, &base_url)),
+        &query,
+    )
+}
+
+pub async fn get_group(
+    State(config_file): State<ConfigFile>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ScimError> {
+    let config = config_or_error(&config_file).await?;
+    let group = config
+        .groups
+        .iter()
+        .find(|g| g.name == id)
+        .ok_or(ScimError::new(StatusCode::NOT_FOUND, "group not found"))?;
+    Ok(ScimJson(
+        StatusCode::OK,
+        ScimGroup::from_group(group, &base_url(&config)),
+    ))
+}
+
+pub async fn create_group(
+    State(config_file): State<ConfigFile>,
+    State(reloader): State<Reloader>,
+    Json(payload): Json<ScimGroup>,
+) -> Result<impl IntoResponse, ScimError> {
+    let _guard = CONFIG_WRITE.lock().await;
+    let mut config = config_or_error(&config_file).await?;
+    let mut group = Group::default();
+    payload.apply_to(&mut group, &config)?;
+    if config.groups.iter().any(|g| g.name == group.name) {
+        return Err(ScimError::conflict("group already exists"));
+    }
+    let response = ScimGroup::from_group(&group, &base_url(&config));
+    info!("SCIM GROUP CREATED: {}", group.name);
+    config.groups.push(group);
+    save(config, &config_file, &reloader).await?;
+    Ok(ScimJson(StatusCode::CREATED, response))
+}
+
+pub async fn replace_group(
+    State(config_file): State<ConfigFile>,
+    State(reloader): State<Reloader>,
+    Path(id): Path<String>,
+    Json(payload): Json<ScimGroup>,
+) -> Result<impl IntoResponse, ScimError> {
+    let _guard = CONFIG_WRITE.lock().await;
+    let config = config_or_error(&config_file).await?;
+    update_group(config, &config_file, &reloader, &id, |_, _| Ok(payload)).await
+}
+
+pub async fn patch_group(
+    State(config_file): State<ConfigFile>,
+    State(reloader): State<Reloader>,
+    Path(id): Path<String>,
+    Json(patch): Json<PatchRequest>,
+) -> Result<impl IntoResponse, ScimError> {
+    let _guard = CONFIG_WRITE.lock().await;
+    let config = config_or_error(&config_file).await?;
+    update_group(config, &config_file, &reloader, &id, |group, config| {
+        let mut resource = serde_json::to_value(ScimGroup::from_group(group, &base_url(config)))
+            .map_err(|_| {
+                ScimError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "could not serialize resource",
+                )
+            })?;
+        apply_patch(&mut resource, patch.operations)?;
+        serde_json::from_value(resource)
+            .map_err(|_| ScimError::bad_request("invalidValue", "patched group is invalid"))
+    })
+    .await
+}
+
+/// Replace the group by the SCIM group given by `payload`, following a name change in the nesting groups
+async fn update_group(
+    mut config: Config,
+    config_file: &str,
+    reloader: &Reloader,
+    id: &str,
+    payload: impl FnOnce(&Group, &Config) -> Result<ScimGroup, ScimError>,
+) -> Result<ScimJson<ScimGroup>, ScimError> {
+    let mut group = config
+        .groups
+        .iter()
+        .find(|g| g.name == id)
+        .cloned()
+        .ok_or(ScimError::new(StatusCode::NOT_FOUND, "group not found"))?;
+    payload(&group, &config)?.apply_to(&mut group, &config)?;
+    if group.name != id {
+        if config.groups.iter().any(|g| g.name == group.name) {
+            return Err(ScimError::conflict("group already exists"));
+        }
+        for nested in config.groups.iter_mut().flat_map(|g| g.groups.iter_mut()) {
+            if *nested == id {
+                nested.clone_from(&group.name);
+            }
+        }
+    }
+    let response = ScimGroup::from_group(&group, &base_url(&config));
+    for existing in config.groups.iter_mut().filter(|g| g.name == id) {
+        *existing = group.clone();
+    }
+    info!("SCIM GROUP UPDATED: {}", group.name);
+    save(config, config_file, reloader).await?;
+    Ok(ScimJson(StatusCode::OK, response))
+}
+
+pub async fn delete_group(
+    State(config_file): State<ConfigFile>,
+    State(reloader): State<Reloader>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ScimError> {
+    let _guard = CONFIG_WRITE.lock().await;
+    let mut config = config_or_error(&config_file).await?;
+    let count = config.groups.len();
+    config.groups.retain(|g| g.name != id);
+    if config.groups.len() == count {
+        return Err(ScimError::new(StatusCode::NOT_FOUND, "group not found"));
+    }
+    for group in config.groups.iter_mut() {
+        group.groups.retain(|g| *g != id);
+    }
+    info!("SCIM GROUP DELETED: {id}");
+    save(config, &config_file, &reloader).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,371 @@
+use super::ScimError;
+use crate::{
+    auth::{User, UserInfo, password, roles::Group},
+    configuration::{Argon2Config, Config},
+    utils::is_default,
+};
+use http::StatusCode;
+use serde::{Deserialize, Deserializer, Serialize};
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+pub const SERVICE_PROVIDER_CONFIG_SCHEMA: &str =
+    "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig";
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultiValued {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub display: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "is_default")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub primary: Option<bool>,
+    #[serde(rename = "$ref", default, skip_serializing_if = "is_default")]
+    pub reference: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Name {
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub given_name: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub family_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub resource_type: String,
+    pub location: String,
+}
+
+/// A SCIM user, identified by the login of the Atrium user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub id: String,
+    pub user_name: String,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub name: Option<Name>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub emails: Vec<MultiValued>,
+    #[serde(default = "active_default", deserialize_with = "bool_or_string")]
+    pub active: bool,
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub roles: Vec<MultiValued>,
+    #[serde(default, skip_deserializing, skip_serializing_if = "is_default")]
+    pub groups: Vec<MultiValued>,
+    #[serde(default, skip_deserializing, skip_serializing_if = "is_default")]
+    pub meta: Option<Meta>,
+}
+
+fn active_default() -> bool {
+    true
+}
+
+/// Some identity providers send the booleans as strings ("True", "False")
+fn bool_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+    match BoolOrString::deserialize(deserializer)? {
+        BoolOrString::Bool(b) => Ok(b),
+        BoolOrString::String(s) if s.trim().eq_ignore_ascii_case("true") => Ok(true),
+        BoolOrString::String(s) if s.trim().eq_ignore_ascii_case("false") => Ok(false),
+        BoolOrString::String(_) => Err(serde::de::Error::custom("expected a boolean")),
+    }
+}
+
+impl ScimUser {
+    pub fn from_user(user: &User, config: &Config, base_url: &str, now: i64) -> Self {
+        let info = user.info.clone().unwrap_or_default();
+        ScimUser {
+            schemas: vec![USER_SCHEMA.to_owned()],
+            id: user.login.clone(),
+            user_name: user.login.clone(),
+            name: (!info.given_name.is_empty() || !info.family_name.is_empty()).then(|| Name {
+                given_name: info.given_name,
+                family_name: info.family_name,
+            }),
+            emails: if info.email.is_empty() {
+                vec![]
+            } else {
+                vec![MultiValued {
+                    value: info.email,
+                    primary: Some(true),
+                    ..Default::default()
+                }]
+            },
+            active: user.is_active(now),
+            password: None,
+            roles: user
+                .roles
+                .iter()
+                .map(|r| MultiValued {
+                    value: r.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+            groups: config
+                .groups
+                .iter()
+                .filter(|g| g.members.contains(&user.login))
+                .map(|g| MultiValued {
+                    value: g.name.clone(),
+                    display: Some(g.name.clone()),
+                    reference: Some(format!("{base_url}/Groups/{}", g.name)),
+                    ..Default::default()
+                })
+                .collect(),
+            meta: Some(Meta {
+                resource_type: "User".to_owned(),
+                location: format!("{base_url}/Users/{}", user.login),
+            }),
+        }
+    }
+
+    /// Set the attributes of the user from the SCIM user.
+    /// The password is kept if not given, and a deactivated user is disabled, so that it can be activated again.
+    pub fn apply_to(
+        self,
+        user: &mut User,
+        argon2: &Argon2Config,
+        now: i64,
+    ) -> Result<(), ScimError> {
+        let login = self.user_name.trim();
+        if login.is_empty() {
+            return Err(ScimError::bad_request(
+                "invalidValue",
+                "userName is required",
+            ));
+        }
+        user.login = login.to_owned();
+        let name = self.name.unwrap_or_default();
+        let info = UserInfo {
+            given_name: name.given_name.trim().to_owned(),
+            family_name: name.family_name.trim().to_owned(),
+            email: self
+                .emails
+                .iter()
+                .find(|e| e.primary == Some(true))
+                .or(self.emails.first())
+                .map(|e| e.value.trim().to_owned())
+                .unwrap_or_default(),
+        };
+        user.info = (info != UserInfo::default()).then_some(info);
+        user.roles = self
+            .roles
+            .into_iter()
+            .map(|r| r.value.trim().to_owned())
+            .filter(|r| !r.is_empty())
+            .collect();
+        user.disabled = !self.active;
+        if self.active && user.is_expired(now) {
+            user.expires_at = None;
+        }
+        if let Some(new_password) = self.password.filter(|p| !p.trim().is_empty()) {
+            user.password = password::hash(&new_password, argon2).map_err(|_| {
+                ScimError::new(StatusCode::INTERNAL_SERVER_ERROR, "password hash failed")
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// A SCIM group, identified by the name of the Atrium group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub id: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub members: Vec<MultiValued>,
+    #[serde(default, skip_deserializing, skip_serializing_if = "is_default")]
+    pub meta: Option<Meta>,
+}
+
+impl ScimGroup {
+    pub fn from_group(group: &Group, base_url: &str) -> Self {
+        let users = group.members.iter().map(|m| MultiValued {
+            value: m.clone(),
+            kind: Some("User".to_owned()),
+            reference: Some(format!("{base_url}/Users/{m}")),
+            ..Default::default()
+        });
+        let groups = group.groups.iter().map(|g| MultiValued {
+            value: g.clone(),
+            kind: Some("Group".to_owned()),
+            reference: Some(format!("{base_url}/Groups/{g}")),
+            ..Default::default()
+        });
+        ScimGroup {
+            schemas: vec![GROUP_SCHEMA.to_owned()],
+            id: group.name.clone(),
+            display_name: group.name.clone(),
+            members: users.chain(groups).collect(),
+            meta: Some(Meta {
+                resource_type: "Group".to_owned(),
+                location: format!("{base_url}/Groups/{}", group.name),
+            }),
+        }
+    }
+
+    /// Set the name and the members of the group, the roles given by the group are not managed by SCIM.
+    /// A member without type is a nested group only if such a group exists and no user has this login.
+    pub fn apply_to(self, group: &mut Group, config: &Config) -> Result<(), ScimError> {
+        let name = self.display_name.trim();
+        if name.is_empty() {
+            return Err(ScimError::bad_request(
+                "invalidValue",
+                "displayName is required",
+            ));
+        }
+        group.name = name.to_owned();
+        group.members.clear();
+        group.groups.clear();
+        for member in self.members {
+            let value = member.value.trim().to_owned();
+            let is_group = match member.kind.as_deref() {
+                Some(kind) => kind.eq_ignore_ascii_case("Group"),
+                None => {
+                    config.groups.iter().any(|g| g.name == value)
+                        && !config.users.iter().any(|u| u.login == value)
+                }
+            };
+            let list = if is_group {
+                &mut group.groups
+            } else {
+                &mut group.members
+            };
+            if !value.is_empty() && !list.contains(&value) {
+                list.push(value);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub schemas: Vec<String>,
+    pub total_results: usize,
+    pub start_index: usize,
+    pub items_per_page: usize,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_round_trip() {
+        let config = Config {
+            groups: vec![Group {
+                name: "staff".to_owned(),
+                members: vec!["jdoe".to_owned()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let user = User {
+            login: "jdoe".to_owned(),
+            password: "hash".to_owned(),
+            roles: vec!["USERS".to_owned()],
+            info: Some(UserInfo {
+                given_name: "John".to_owned(),
+                family_name: "Doe".to_owned(),
+                email: "john.doe@atrium.io".to_owned(),
+            }),
+            ..Default::default()
+        };
+        let scim_user = ScimUser::from_user(&user, &config, "http://atrium.io/scim/v2", 0);
+        assert_eq!(
+            scim_user.groups,
+            vec![MultiValued {
+                value: "staff".to_owned(),
+                display: Some("staff".to_owned()),
+                reference: Some("http://atrium.io/scim/v2/Groups/staff".to_owned()),
+                ..Default::default()
+            }]
+        );
+        let mut applied = User {
+            password: "hash".to_owned(),
+            ..Default::default()
+        };
+        scim_user
+            .apply_to(&mut applied, &Argon2Config::default(), 0)
+            .unwrap();
+        assert_eq!(applied, user);
+    }
+
+    #[test]
+    fn test_user_deactivation() {
+        let scim_user: ScimUser =
+            serde_json::from_str(r#"{"userName":"jdoe","active":"False"}"#).unwrap();
+        let mut user = User::default();
+        scim_user
+            .clone()
+            .apply_to(&mut user, &Argon2Config::default(), 100)
+            .unwrap();
+        // The user is deactivated right away, and is not expired so that it is not removed
+        assert!(user.disabled);
+        assert!(!user.is_active(100));
+        assert!(!user.is_expired(100));
+        assert!(!ScimUser::from_user(&user, &Config::default(), "", 100).active);
+        // Reactivating enables the user again
+        ScimUser {
+            active: true,
+            ..scim_user
+        }
+        .apply_to(&mut user, &Argon2Config::default(), 200)
+        .unwrap();
+        assert!(user.is_active(200));
+    }
+
+    #[test]
+    fn test_group_members() {
+        let config = Config {
+            groups: vec![Group {
+                name: "nested".to_owned(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let scim_group: ScimGroup = serde_json::from_str(
+            r#"{"displayName":"team","members":[{"value":"jdoe"},{"value":"nested"},{"value":"other","type":"Group"}]}"#,
+        )
+        .unwrap();
+        let mut group = Group {
+            roles: vec!["TEAM".to_owned()],
+            ..Default::default()
+        };
+        scim_group.apply_to(&mut group, &config).unwrap();
+        assert_eq!(
+            group,
+            Group {
+                name: "team".to_owned(),
+                members: vec!["jdoe".to_owned()],
+                groups: vec!["nested".to_owned(), "other".to_owned()],
+                roles: vec!["TEAM".to_owned()],
+            }
+        );
+    }
+}
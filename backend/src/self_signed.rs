@@ -1,8 +1,15 @@
 use crate::CONFIG_FILE;
-use atrium::errors::Error;
+use atrium::{
+    auth::client_cert::{ClientCertAcceptor, server_config_builder},
+    errors::Error,
+};
 use axum::{extract::connect_info::IntoMakeServiceWithConnectInfo, routing::MethodRouter};
-use axum_server::{Handle, tls_rustls::RustlsConfig};
-use std::{net::SocketAddr, path::Path};
+use axum_server::{
+    Handle,
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use std::{net::SocketAddr, path::Path, sync::Arc};
 use tokio::fs;
 use tracing::info;
 
@@ -17,13 +24,23 @@ pub async fn serve_with_self_signed_cert(
 ) -> Result<(), Error> {
     // Certificates
     let (cert, key) = load_or_generate_cert().await?;
-    let rustls_config = RustlsConfig::from_pem(cert, key).await?;
+    let certs = CertificateDer::pem_slice_iter(&cert)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Error("could not parse certificate"))?;
+    let key = PrivateKeyDer::from_pem_slice(&key).map_err(|_| Error("could not parse key"))?;
+    let config = atrium::configuration::load_config(CONFIG_FILE).await?;
+    let mut server_config = server_config_builder(&config.0)?
+        .with_single_cert(certs, key)
+        .map_err(|_| Error("invalid certificate or key"))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let rustls_config = RustlsConfig::from_config(Arc::new(server_config));
 
     // Main server
     let addr = format!("{ip}:{port}").parse::<std::net::SocketAddr>()?;
 
-    // Start the server with TLS
-    Ok(axum_server::bind_rustls(addr, rustls_config)
+    // Start the server with TLS, making the client certificates available to the requests
+    Ok(axum_server::bind(addr)
+        .acceptor(ClientCertAcceptor::new(RustlsAcceptor::new(rustls_config)))
         .handle(handle)
         .serve(app)
         .await?)
@@ -16,14 +16,16 @@ use tower::{ServiceBuilder, ServiceExt};
 use crate::jail::Jail;
 use crate::{
     apps::{add_app, delete_app, get_apps, proxy_handler},
-    appstate::{AppState, Client, InsecureSkipVerifyClient},
+    appstate::{AppState, Client, InsecureSkipVerifyClient, Reloader},
     auth::{
-        AdminToken, auth_middleware, cookie_to_body, dav_auth_middleware, get_share_token,
-        xsrf_middleware,
+        ScopedAdminToken, auth_middleware, cookie_to_body, dav_auth_middleware, get_share_token,
+        unlock_share, xsrf_middleware,
     },
     configuration::{HostType, load_config},
     davs::{
         model::{add_dav, delete_dav, get_davs},
+        trash::{self, empty_trash, list_trash, purge_trash_item, restore_trash_item},
+        versions::{self, download_version, list_versions, restore_version},
         webdav_handler,
     },
     dir_server::dir_handler,
@@ -31,12 +33,37 @@ use crate::{
     middlewares::{cors_middleware, debug_cors_middleware, inject_security_headers},
 };
 use crate::{
-    auth::{add_user, delete_user, get_users, list_services, local_auth, logout, whoami},
+    auth::{
+        add_user,
+        bulk::{bulk_export_users, bulk_import_users},
+        challenge::login_challenge,
+        cookie_keys::{previous_keys, reencrypt_cookie_middleware, rotate_cookie_key},
+        delete_user, get_users,
+        impersonation::{impersonate, impersonation_audit_middleware, stop_impersonation},
+        import_htpasswd,
+        invitation::{accept_invitation, create_invitation, remove_expired_users},
+        list_services, local_auth, logout,
+        reset::{create_reset_link, reset_password},
+        share_registry::{
+            self, ShareRegistry, admin_list_shares, admin_revoke_share, admin_share_history,
+            list_shares, revoke_share, share_history,
+        },
+        whoami,
+    },
     oauth2::{oauth2_available, oauth2_callback, oauth2_login},
     onlyoffice::{onlyoffice_callback, onlyoffice_page},
+    scim::{
+        ScimClient, create_group, create_user, delete_group, delete_user as scim_delete_user,
+        get_group, get_user, list_groups, list_users, patch_group, patch_user, replace_group,
+        replace_user, service_provider_config,
+    },
     sysinfo::system_info,
 };
 
+static EXPIRED_USERS_CLEANUP: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
+static DAVS_PURGE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
 pub struct Server {
     pub router: MethodRouter,
     pub port: u16,
@@ -67,13 +94,43 @@ impl Server {
             });
         }
 
+        // Start the expired users and shares cleanup task once a day, only once even if the configuration is reloaded
+        if !EXPIRED_USERS_CLEANUP.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            let config_file = config_file.to_owned();
+            tokio::spawn(async move {
+                let mut interval =
+                    tokio::time::interval(tokio::time::Duration::from_secs(24 * 3600));
+                loop {
+                    interval.tick().await;
+                    remove_expired_users(&config_file).await;
+                    share_registry::purge_expired(&config_file).await;
+                }
+            });
+        }
+
+        // Start the purge of the trash bins and of the versions of the davs every hour, only once even if the configuration is reloaded
+        if !DAVS_PURGE.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            let config_file = config_file.to_owned();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+                loop {
+                    interval.tick().await;
+                    trash::purge_expired(&config_file).await;
+                    versions::purge_expired(&config_file).await;
+                }
+            });
+        }
+
         let state = AppState::new(
             axum_extra::extract::cookie::Key::from(
                 config.0.cookie_key.as_ref().expect("cookie key").as_bytes(),
             ),
+            previous_keys(&config.0, time::OffsetDateTime::now_utc().unix_timestamp()),
             config.0,
             config.1,
             config_file.to_owned(),
+            ShareRegistry::for_config_file(config_file).await?,
+            Reloader::new(tx.clone()),
             #[cfg(target_os = "linux")]
             jail,
         );
@@ -88,6 +145,31 @@ impl Server {
                     cookie_to_body,
                 )),
             )
+            .route("/api/user/stop_impersonation", post(stop_impersonation))
+            .route("/api/user/shares", get(list_shares))
+            .route("/api/user/shares/{share_id}", delete(revoke_share))
+            .route("/api/user/shares/{share_id}/history", get(share_history))
+            .route(
+                "/api/user/trash/{dav_id}",
+                get(list_trash).delete(empty_trash),
+            )
+            .route(
+                "/api/user/trash/{dav_id}/{item_id}",
+                delete(purge_trash_item),
+            )
+            .route(
+                "/api/user/trash/{dav_id}/{item_id}/restore",
+                post(restore_trash_item),
+            )
+            .route("/api/user/versions/{dav_id}", get(list_versions))
+            .route(
+                "/api/user/versions/{dav_id}/{version_id}",
+                get(download_version),
+            )
+            .route(
+                "/api/user/versions/{dav_id}/{version_id}/restore",
+                post(restore_version),
+            )
             .route_layer(middleware::from_fn_with_state(
                 state.clone(),
                 xsrf_middleware,
@@ -97,23 +179,65 @@ impl Server {
         let admin_router = Router::new()
             .route("/api/admin/users", get(get_users).post(add_user))
             .route("/api/admin/users/{user_login}", delete(delete_user))
+            .route("/api/admin/users/import/htpasswd", post(import_htpasswd))
+            .route(
+                "/api/admin/bulk/users",
+                get(bulk_export_users).post(bulk_import_users),
+            )
+            .route("/api/admin/invitations", post(create_invitation))
+            .route(
+                "/api/admin/users/{user_login}/reset",
+                post(create_reset_link),
+            )
             .route("/api/admin/apps", get(get_apps).post(add_app))
             .route("/api/admin/apps/{app_id}", delete(delete_app))
             .route("/api/admin/davs", get(get_davs).post(add_dav))
             .route("/api/admin/davs/{dav_id}", delete(delete_dav))
+            .route("/api/admin/cookie_key/rotate", post(rotate_cookie_key))
+            .route("/api/admin/impersonate/{user_login}", post(impersonate))
+            .route("/api/admin/shares", get(admin_list_shares))
+            .route("/api/admin/shares/{share_id}", delete(admin_revoke_share))
+            .route(
+                "/api/admin/shares/{share_id}/history",
+                get(admin_share_history),
+            )
             .route_layer(
                 ServiceBuilder::new()
-                    .layer(
-                        middleware::from_extractor_with_state::<AdminToken, AppState>(
-                            state.clone(),
-                        ),
-                    )
+                    .layer(middleware::from_extractor_with_state::<
+                        ScopedAdminToken,
+                        AppState,
+                    >(state.clone()))
                     .layer(middleware::from_fn_with_state(
                         state.clone(),
                         xsrf_middleware,
                     )),
             );
 
+        let scim_router = Router::new()
+            .route(
+                "/scim/v2/ServiceProviderConfig",
+                get(service_provider_config),
+            )
+            .route("/scim/v2/Users", get(list_users).post(create_user))
+            .route(
+                "/scim/v2/Users/{id}",
+                get(get_user)
+                    .put(replace_user)
+                    .patch(patch_user)
+                    .delete(scim_delete_user),
+            )
+            .route("/scim/v2/Groups", get(list_groups).post(create_group))
+            .route(
+                "/scim/v2/Groups/{id}",
+                get(get_group)
+                    .put(replace_group)
+                    .patch(patch_group)
+                    .delete(delete_group),
+            )
+            .route_layer(
+                middleware::from_extractor_with_state::<ScimClient, AppState>(state.clone()),
+            );
+
         let main_router = Router::new()
             .route(
                 "/reload",
@@ -125,6 +249,10 @@ impl Server {
                 }),
             )
             .route("/auth/local", post(local_auth))
+            .route("/auth/challenge", get(login_challenge))
+            .route("/auth/invitation", post(accept_invitation))
+            .route("/auth/reset", post(reset_password))
+            .route("/auth/share/unlock", post(unlock_share))
             .route("/auth/oauth2login", get(oauth2_login))
             .route("/auth/oauth2callback", get(oauth2_callback))
             .route("/auth/oauth2available", get(oauth2_available))
@@ -132,6 +260,7 @@ impl Server {
             // We use merge instead of nest as it is still a little bit faster
             .merge(admin_router)
             .merge(user_router)
+            .merge(scim_router)
             .route("/onlyoffice/save", post(onlyoffice_callback))
             .route("/onlyoffice", get(onlyoffice_page))
             .route(
@@ -214,6 +343,14 @@ impl Server {
                 state.clone(),
                 inject_security_headers,
             ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                impersonation_audit_middleware,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                reencrypt_cookie_middleware,
+            ))
             .with_state(state);
 
         if debug_mode {
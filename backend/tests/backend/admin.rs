@@ -1,3 +1,8 @@
+use atrium::{
+    apps::App,
+    auth::{User, WhoAmI, bulk::BulkImportReport, delegation::AdminScope},
+    configuration::Config,
+};
 use hyper::StatusCode;
 
 use crate::helpers::{TestApp, login_and_get_xsrf_token};
@@ -547,3 +552,464 @@ async fn davs_api_for_admin_user_test() {
     assert_eq!(response.status(), StatusCode::OK);
     assert!(!response.text().await.unwrap().contains(r#""id":201"#));
 }
+
+#[tokio::test]
+async fn import_htpasswd_and_upgrade_hash_test() {
+    // Arrange
+    let mut app = TestApp::spawn(None).await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+
+    // Import an htpasswd file with a bcrypt user and an unsupported apr1 user
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/users/import/htpasswd?roles=USERS",
+            app.port
+        ))
+        .body("legacy:$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe\napache:$apr1$abc$def\n")
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = response.text().await.unwrap();
+    assert!(report.contains(r#""created":["legacy"]"#));
+    assert!(report.contains(r#""skipped":[{"line":2,"reason":"unsupported hash format"}]"#));
+
+    // Reload the configuration
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    app.is_ready().await;
+
+    // Log in with the imported user and check that the hash was upgraded to argon2
+    login_and_get_xsrf_token(&app, "legacy").await;
+    let fp = format!("{}.yaml", &app.id);
+    let config = Config::from_file(&fp)
+        .await
+        .expect("failed to read config file");
+    let user = config
+        .users
+        .iter()
+        .find(|u| u.login == "legacy")
+        .expect("imported user not found");
+    assert!(user.password.starts_with("$argon2id$"));
+    assert_eq!(user.roles, vec!["USERS".to_owned()]);
+}
+
+#[tokio::test]
+async fn delegated_admin_test() {
+    // Arrange : give the administration of app 1 and of the USERS role to users
+    let mut app = TestApp::spawn(None).await;
+    let fp = format!("{}.yaml", &app.id);
+    let mut config = Config::from_file(&fp)
+        .await
+        .expect("failed to read config file");
+    config.admin_scopes = vec![AdminScope {
+        role: "USERS".to_owned(),
+        apps: vec![1],
+        roles: vec!["USERS".to_owned()],
+        ..Default::default()
+    }];
+    config
+        .to_file(&fp)
+        .await
+        .expect("failed to write config file");
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    app.is_ready().await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "user").await;
+
+    // Only the apps of the scope are listed, and no dav
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/admin/apps", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let apps = response.json::<Vec<App>>().await.unwrap();
+    assert_eq!(apps.iter().map(|a| a.id).collect::<Vec<_>>(), vec![1]);
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/admin/davs", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "[]");
+
+    // Apps outside of the scope cannot be altered
+    let response = app
+        .client
+        .delete(format!("http://atrium.io:{}/api/admin/apps/2", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // The apps of the scope can be renamed, but not moved to another host or target
+    let mut scoped_app = apps.first().expect("app of the scope").clone();
+    scoped_app.name = "Renamed".to_owned();
+    for (host, status) in [
+        (scoped_app.host.clone(), StatusCode::CREATED),
+        ("hijacked".to_owned(), StatusCode::FORBIDDEN),
+    ] {
+        let response = app
+            .client
+            .post(format!("http://atrium.io:{}/api/admin/apps", app.port))
+            .json(&App {
+                host,
+                ..scoped_app.clone()
+            })
+            .header("xsrf-token", &xsrf_token)
+            .send()
+            .await
+            .expect("failed to execute request");
+        assert_eq!(response.status(), status);
+    }
+
+    // Users can be created with the delegated roles only
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/api/admin/users", app.port))
+        .body(r#"{"login":"newbie","password":"verystrongpassword","roles":["USERS"]}"#)
+        .header("Content-Type", "application/json")
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/api/admin/users", app.port))
+        .body(r#"{"login":"boss","password":"verystrongpassword","roles":["ADMINS"]}"#)
+        .header("Content-Type", "application/json")
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/api/admin/users", app.port))
+        .body(r#"{"login":"admin","password":"newpassword","roles":["USERS"]}"#)
+        .header("Content-Type", "application/json")
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Only the users holding the delegated roles are listed
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/admin/users", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let users = response.json::<Vec<User>>().await.unwrap();
+    assert!(users.iter().any(|u| u.login == "newbie"));
+    assert!(!users.iter().any(|u| u.login == "admin"));
+
+    // Importing users stays reserved to the global admins
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/users/import/htpasswd",
+            app.port
+        ))
+        .body("")
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn bulk_import_export_test() {
+    // Arrange
+    let mut app = TestApp::spawn(None).await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let fp = format!("{}.yaml", &app.id);
+    let csv = "login,given_name,family_name,email,roles,password
+student1,Ada,Lovelace,ada@atrium.io,USERS;STUDENTS,
+student2,Alan,Turing,alan@atrium.io,USERS,password
+";
+
+    // Act and Assert : a dry run reports without saving
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/bulk/users?format=csv&dry_run=true",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .body(csv)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = response.json::<BulkImportReport>().await.unwrap();
+    assert!(report.dry_run);
+    assert_eq!(report.created, vec!["student1", "student2"]);
+    let config = Config::from_file(&fp)
+        .await
+        .expect("failed to read config file");
+    assert!(!config.users.iter().any(|u| u.login == "student1"));
+
+    // Act : import the users
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/bulk/users?format=csv",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .body(csv)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = response.json::<BulkImportReport>().await.unwrap();
+    let generated = report
+        .generated_passwords
+        .into_iter()
+        .find(|g| g.login == "student1")
+        .and_then(|g| g.password)
+        .expect("generated password for student1");
+
+    // Assert : the users can log in with the generated or given password
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    app.is_ready().await;
+    login_and_get_xsrf_token(&app, "student2").await;
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/local", app.port))
+        .json(&serde_json::json!({"login": "student1", "password": generated}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Act and Assert : the export redacts the passwords
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let response = app
+        .client
+        .get(format!(
+            "http://atrium.io:{}/api/admin/bulk/users?format=csv",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let export = response.text().await.unwrap();
+    assert!(export.contains("student1,Ada,Lovelace,ada@atrium.io,USERS;STUDENTS,REDACTED"));
+    assert!(!export.contains("$argon2"));
+}
+
+#[tokio::test]
+async fn cookie_key_rotation_test() {
+    // Arrange
+    let mut app = TestApp::spawn(None).await;
+    let user_xsrf_token = login_and_get_xsrf_token(&app, "user").await;
+
+    // Act : a normal user cannot rotate the cookie key
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/cookie_key/rotate",
+            app.port
+        ))
+        .header("xsrf-token", &user_xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Act : rotate the key as admin
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let filepath = format!("{}.yaml", &app.id);
+    let old_key = Config::from_file(&filepath).await.unwrap().cookie_key;
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/cookie_key/rotate",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    // The new key is used after the automatic reload
+    app.is_ready().await;
+
+    // Assert : the previous key is kept for the grace period
+    let config = Config::from_file(&filepath).await.unwrap();
+    assert_ne!(config.cookie_key, old_key);
+    assert_eq!(
+        config
+            .previous_cookie_keys
+            .iter()
+            .map(|k| Some(k.key.clone()))
+            .collect::<Vec<_>>(),
+        vec![old_key]
+    );
+
+    // Assert : the session opened before the rotation is still valid, and its cookie is re-encrypted
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        response
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .any(|c| c.to_str().unwrap().starts_with("ATRIUM_AUTH="))
+    );
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("set-cookie").is_none());
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/admin/users", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn impersonation_test() {
+    // Arrange
+    let app = TestApp::spawn(None).await;
+    let user_xsrf_token = login_and_get_xsrf_token(&app, "user").await;
+
+    // Act : a normal user cannot impersonate
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/impersonate/admin",
+            app.port
+        ))
+        .header("xsrf-token", &user_xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Act : impersonate the user as admin
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/impersonate/user",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let auth = response.json::<atrium::auth::AuthResponse>().await.unwrap();
+    assert!(!auth.is_admin);
+    let impersonated_xsrf_token = auth.xsrf_token.unwrap();
+
+    // Assert : whoami tells that the session is impersonated
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let whoami = response.json::<WhoAmI>().await.unwrap();
+    assert_eq!(whoami.user.login, "user");
+    assert_eq!(whoami.impersonated_by, Some("admin".to_owned()));
+
+    // Assert : the impersonated session cannot access the admin API
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/admin/users", app.port))
+        .header("xsrf-token", &impersonated_xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Act : go back to the admin session
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/user/stop_impersonation",
+            app.port
+        ))
+        .header("xsrf-token", &impersonated_xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let auth = response.json::<atrium::auth::AuthResponse>().await.unwrap();
+    assert!(auth.is_admin);
+    assert_eq!(auth.xsrf_token, Some(xsrf_token.clone()));
+
+    // Assert : the admin session is restored
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    let whoami = response.json::<WhoAmI>().await.unwrap();
+    assert_eq!(whoami.user.login, "admin");
+    assert_eq!(whoami.impersonated_by, None);
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/admin/users", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Assert : a session that is not impersonated cannot be stopped
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/user/stop_impersonation",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
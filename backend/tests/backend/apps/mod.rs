@@ -257,14 +257,26 @@ async fn redirect_test() {
         letsencrypt_email: "foo@bar.com".to_owned(),
         http_port: app.port,
         cookie_key: None,
+        previous_cookie_keys: vec![],
         log_to_file: false,
         jail: Default::default(),
+        argon2: Default::default(),
         apps,
         davs: vec![],
         users: vec![],
+        groups: vec![],
+        role_hierarchy: Default::default(),
+        admin_scopes: vec![],
+        consumed_invitations: vec![],
         session_duration_days: None,
         onlyoffice_config: None,
         openid_config: None,
+        scim_config: None,
+        client_cert_config: None,
+        login_challenge: None,
+        smtp_config: None,
+        propfind_depth_infinity_limit: None,
+        fsync_uploads: false,
         single_proxy: false,
     };
     config.to_file(&filepath).await.unwrap();
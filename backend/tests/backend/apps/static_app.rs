@@ -1,4 +1,6 @@
 use crate::helpers::{TestApp, login_and_get_xsrf_token};
+use atrium::configuration::Config;
+use http::StatusCode;
 
 #[tokio::test]
 async fn static_app_test() {
@@ -100,3 +102,41 @@ async fn secured_static_app_test() {
             .contains("This is statically served !")
     );
 }
+
+#[tokio::test]
+async fn client_cert_required_static_app_test() {
+    // Arrange
+    let mut app = TestApp::spawn(None).await;
+    let filepath = format!("{}.yaml", &app.id);
+    let mut config = Config::from_file(&filepath).await.unwrap();
+    for static_app in config.apps.iter_mut().filter(|a| a.host == "static-app") {
+        static_app.require_client_cert = true;
+    }
+    config.to_file(&filepath).await.unwrap();
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    app.is_ready().await;
+
+    // Act : plain http connections never carry a client certificate
+    let response = app
+        .client
+        .get(format!("http://static-app.atrium.io:{}", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // The apps not requiring a certificate are still served
+    let response = app
+        .client
+        .get(format!("http://secured-static-app.atrium.io:{}", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_ne!(response.status(), StatusCode::FORBIDDEN);
+}
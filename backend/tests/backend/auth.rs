@@ -1,5 +1,15 @@
 use atrium::{
-    auth::{User, share::ShareResponse},
+    auth::{
+        User,
+        challenge::{ChallengeResponse, solve},
+        invitation::{InvitationResponse, remove_expired_users},
+        reset::ResetResponse,
+        roles::Group,
+        share::{ShareResponse, ShareUnlockResponse},
+        share_registry::{ShareAccess, ShareRecord},
+    },
+    configuration::{Config, LoginChallengeConfig, SmtpConfig, SmtpTls},
+    mocks::mock_smtp_server,
     sysinfo::SystemInfo,
 };
 use hyper::StatusCode;
@@ -490,6 +500,46 @@ async fn whoami_test() {
     assert_eq!(user.password, "REDACTED");
 }
 
+#[tokio::test]
+async fn whoami_effective_roles_test() {
+    // Arrange : add a group and a role hierarchy to the configuration
+    let mut app = TestApp::spawn(None).await;
+    let fp = format!("{}.yaml", &app.id);
+    let mut config = Config::from_file(&fp)
+        .await
+        .expect("failed to read config file");
+    config.groups = vec![Group {
+        name: "staff".to_owned(),
+        members: vec!["admin".to_owned()],
+        roles: vec!["STAFF".to_owned()],
+        ..Default::default()
+    }];
+    config.role_hierarchy = [("ADMINS".to_owned(), vec!["USERS".to_owned()])].into();
+    config
+        .to_file(&fp)
+        .await
+        .expect("failed to write config file");
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    app.is_ready().await;
+    login_and_get_xsrf_token(&app, "admin").await;
+
+    // Act and Assert : the roles from the group and from the hierarchy are given
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut user = response.json::<User>().await.unwrap();
+    user.roles.sort();
+    assert_eq!(user.roles, vec!["ADMINS", "STAFF", "USERS"]);
+}
+
 #[tokio::test]
 async fn logout_test() {
     // Arrange
@@ -515,3 +565,865 @@ async fn logout_test() {
             .contains("ATRIUM_AUTH=; Path=/; Domain=atrium.io; Max-Age=0;")
     );
 }
+
+#[tokio::test]
+async fn invitation_test() {
+    // Arrange
+    let app = TestApp::spawn(None).await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+
+    // Assert : the validity of the link is bounded
+    for valid_for_days in [0, -1, 366, i64::MAX] {
+        let response = app
+            .client
+            .post(format!(
+                "http://atrium.io:{}/api/admin/invitations",
+                app.port
+            ))
+            .header("xsrf-token", &xsrf_token)
+            .json(&serde_json::json!({"login": "guest", "valid_for_days": valid_for_days}))
+            .send()
+            .await
+            .expect("failed to execute request");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // Act : create an invitation
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/invitations",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .json(&serde_json::json!({"login": "guest", "roles": ["USERS"]}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let invitation = response.json::<InvitationResponse>().await.unwrap();
+    let token = urlencoding::decode(
+        invitation
+            .url
+            .split_once("token=")
+            .expect("token in invitation url")
+            .1,
+    )
+    .unwrap()
+    .into_owned();
+
+    // Assert : a forged token is refused
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/invitation", app.port))
+        .json(&serde_json::json!({"token": "forged", "password": "guestpassword"}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Assert : the invitation creates the user, logs them in with the given roles
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/invitation", app.port))
+        .json(&serde_json::json!({"token": token, "password": "guestpassword"}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    let user = response.json::<User>().await.unwrap();
+    assert_eq!(user.login, "guest");
+    assert_eq!(user.roles, vec!["USERS"]);
+
+    // Assert : the invitation cannot be used twice
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/invitation", app.port))
+        .json(&serde_json::json!({"token": token, "password": "otherpassword"}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // Assert : not even once the invited user is removed
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let response = app
+        .client
+        .delete(format!(
+            "http://atrium.io:{}/api/admin/users/guest",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/invitation", app.port))
+        .json(&serde_json::json!({"token": token, "password": "otherpassword"}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn password_reset_test() {
+    // Arrange : create a reset link for the user
+    let mut app = TestApp::spawn(None).await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/admin/users/user/reset",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let reset = response.json::<ResetResponse>().await.unwrap();
+    let token = urlencoding::decode(
+        reset
+            .url
+            .split_once("token=")
+            .expect("token in reset url")
+            .1,
+    )
+    .unwrap()
+    .into_owned();
+
+    // Log in as the user, the session must be revoked by the reset
+    login_and_get_xsrf_token(&app, "user").await;
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    // Sessions are revoked with a one second precision
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    // Act : reset the password
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/reset", app.port))
+        .json(&serde_json::json!({"token": token, "password": "newpassword"}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Assert : the previous session is revoked
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.text().await.unwrap(), "user session was revoked");
+
+    // Assert : the link cannot be used twice
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/reset", app.port))
+        .json(&serde_json::json!({"token": token, "password": "otherpassword"}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Assert : the user can log in with the new password
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    app.is_ready().await;
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/local", app.port))
+        .body(r#"{"login":"user","password":"newpassword"}"#)
+        .header("Content-Type", "application/json")
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn expired_user_test() {
+    // Arrange : log the user in, then make the user account expired
+    let mut app = TestApp::spawn(None).await;
+    login_and_get_xsrf_token(&app, "user").await;
+    let fp = format!("{}.yaml", &app.id);
+    let mut config = Config::from_file(&fp)
+        .await
+        .expect("failed to read config file");
+    for user in config.users.iter_mut().filter(|u| u.login == "user") {
+        user.expires_at = Some(1);
+    }
+    config
+        .to_file(&fp)
+        .await
+        .expect("failed to write config file");
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    app.is_ready().await;
+
+    // Act and Assert : the session of the user is not valid anymore
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Act and Assert : the user cannot log in anymore
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/local", app.port))
+        .body(r#"{"login":"user","password":"password"}"#)
+        .header("Content-Type", "application/json")
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.text().await.unwrap(), "user account is expired");
+
+    // Act and Assert : the expired user is removed by the cleanup
+    remove_expired_users(&fp).await;
+    let config = Config::from_file(&fp)
+        .await
+        .expect("failed to read config file");
+    assert!(!config.users.iter().any(|u| u.login == "user"));
+    assert!(config.users.iter().any(|u| u.login == "admin"));
+}
+
+#[tokio::test]
+async fn login_challenge_test() {
+    // Arrange : require a proof of work after one failure
+    let mut app = TestApp::spawn(None).await;
+    let fp = format!("{}.yaml", &app.id);
+    let mut config = Config::from_file(&fp)
+        .await
+        .expect("failed to read config file");
+    config.login_challenge = Some(LoginChallengeConfig {
+        after_failures: 1,
+        difficulty: 8,
+        max_difficulty: 8,
+    });
+    config
+        .to_file(&fp)
+        .await
+        .expect("failed to write config file");
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    app.is_ready().await;
+
+    // Act : fail to log in
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/local", app.port))
+        .body(r#"{"login":"user","password":"wrong"}"#)
+        .header("Content-Type", "application/json")
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_ne!(response.status(), StatusCode::OK);
+
+    // Assert : the right password is not enough anymore
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/local", app.port))
+        .body(r#"{"login":"user","password":"password"}"#)
+        .header("Content-Type", "application/json")
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+
+    // Act : get and solve the challenge
+    let response = app
+        .client
+        .get(format!(
+            "http://atrium.io:{}/auth/challenge?login=user",
+            app.port
+        ))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let challenge = response.json::<ChallengeResponse>().await.unwrap();
+    assert_eq!(challenge.difficulty, 8);
+    let challenge = challenge.challenge.unwrap();
+    let solution = solve(&challenge, 8);
+
+    // Assert : the login succeeds with the solution, which cannot be reused
+    let body = serde_json::json!({
+        "login": "user",
+        "password": "password",
+        "challenge": challenge,
+        "solution": solution,
+    })
+    .to_string();
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/local", app.port))
+        .body(body.clone())
+        .header("Content-Type", "application/json")
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .client
+        .post(format!("http://atrium.io:{}/auth/local", app.port))
+        .body(body)
+        .header("Content-Type", "application/json")
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+
+    // Assert : the basic auth of the davs is throttled too, the failures of the IP being kept
+    let client = reqwest::Client::builder()
+        .resolve(
+            "secured-files.atrium.io",
+            format!("127.0.0.1:{}", app.port).parse().unwrap(),
+        )
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!("http://secured-files.atrium.io:{}/", app.port))
+        .basic_auth("user", Some("password"))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn share_registry_test() {
+    // Arrange
+    let app = TestApp::spawn(None).await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+
+    // Act : share a directory for a day
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/user/get_share_token",
+            app.port
+        ))
+        .header("Content-Type", "application/json")
+        .header("xsrf-token", &xsrf_token)
+        .body(
+            r#"{"hostname":"secured-files.atrium.io","path":"/dira","share_with":"guest","share_for_days":1,"id":"forged"}"#,
+        )
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let share_token = response.json::<ShareResponse>().await.unwrap().token;
+
+    // Assert : the share is recorded, with an id given by the server
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/shares", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let shares = response.json::<Vec<ShareRecord>>().await.unwrap();
+    assert_eq!(shares.len(), 1);
+    let share = shares.first().unwrap();
+    assert_ne!(share.id, "forged");
+    assert_eq!(share.created_by, "admin");
+    assert_eq!(share.hostname, "secured-files.atrium.io");
+    assert_eq!(share.share_with, Some("guest".to_owned()));
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/admin/shares", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(
+        response.json::<Vec<ShareRecord>>().await.unwrap(),
+        shares.clone()
+    );
+
+    // Assert : the share token can be used
+    let client = reqwest::Client::builder()
+        .resolve(
+            "secured-files.atrium.io",
+            format!("127.0.0.1:{}", app.port).parse().unwrap(),
+        )
+        .cookie_store(false)
+        .build()
+        .unwrap();
+    let url = format!(
+        "http://secured-files.atrium.io:{}/dira/file1?token={share_token}",
+        app.port
+    );
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Act : a user cannot revoke the share of another user
+    let user_xsrf_token = login_and_get_xsrf_token(&app, "user").await;
+    let response = app
+        .client
+        .delete(format!(
+            "http://atrium.io:{}/api/user/shares/{}",
+            app.port, share.id
+        ))
+        .header("xsrf-token", &user_xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Act : revoke the share as admin
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let response = app
+        .client
+        .delete(format!(
+            "http://atrium.io:{}/api/admin/shares/{}",
+            app.port, share.id
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Assert : the share token cannot be used anymore
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/shares", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert!(
+        response
+            .json::<Vec<ShareRecord>>()
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn protected_share_test() {
+    // Arrange : share a directory with a password, for a single download
+    let app = TestApp::spawn(None).await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/user/get_share_token",
+            app.port
+        ))
+        .header("Content-Type", "application/json")
+        .header("xsrf-token", &xsrf_token)
+        .body(
+            r#"{"hostname":"secured-files.atrium.io","path":"/dira","share_for_days":1,"password":"secret","one_time":true}"#,
+        )
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let share_token = response.json::<ShareResponse>().await.unwrap().token;
+
+    // The password is not given back in the share list
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/shares", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    let shares = response.json::<Vec<ShareRecord>>().await.unwrap();
+    let share = shares.first().unwrap();
+    assert_eq!(share.password, "REDACTED");
+    assert_eq!(share.max_downloads, Some(1));
+
+    // A recipient with a fresh browser
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve(
+            "atrium.io",
+            format!("127.0.0.1:{}", app.port).parse().unwrap(),
+        )
+        .resolve(
+            "secured-files.atrium.io",
+            format!("127.0.0.1:{}", app.port).parse().unwrap(),
+        )
+        .cookie_store(true)
+        .build()
+        .unwrap();
+
+    // Act and Assert : the browser is sent to the password page
+    let url = format!(
+        "http://secured-files.atrium.io:{}/dira/file1?token={share_token}",
+        app.port
+    );
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.starts_with(&format!(
+        "http://atrium.io:{}/share/share.html?token=",
+        app.port
+    )));
+
+    // Act and Assert : a wrong password is refused
+    let unlock = |password: &str| {
+        serde_json::json!({
+            "token": share_token,
+            "password": password,
+            "path": "/dira/file1",
+        })
+        .to_string()
+    };
+    let resp = client
+        .post(format!("http://atrium.io:{}/auth/share/unlock", app.port))
+        .header("Content-Type", "application/json")
+        .body(unlock("wrong"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    // Act and Assert : with the right password, the file can be downloaded once
+    let resp = client
+        .post(format!("http://atrium.io:{}/auth/share/unlock", app.port))
+        .header("Content-Type", "application/json")
+        .body(unlock("secret"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let url = resp.json::<ShareUnlockResponse>().await.unwrap().url;
+    assert!(url.starts_with(&format!(
+        "http://secured-files.atrium.io:{}/dira/file1?token=",
+        app.port
+    )));
+    // A search is not a download
+    let resp = client
+        .get(format!(
+            "http://secured-files.atrium.io:{}/dira?token={share_token}&q=file",
+            app.port
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    // A range request gets the whole file, and is counted
+    let resp = client
+        .get(&url)
+        .header("Range", "bytes=1-")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn file_request_share_test() {
+    // Arrange : request files in a directory, with a prefix and a size limit
+    let app = TestApp::spawn(None).await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let get_share_token = |body: &'static str| {
+        app.client
+            .post(format!(
+                "http://atrium.io:{}/api/user/get_share_token",
+                app.port
+            ))
+            .header("Content-Type", "application/json")
+            .header("xsrf-token", &xsrf_token)
+            .body(body)
+            .send()
+    };
+    // A file request cannot be writable
+    let response = get_share_token(
+        r#"{"hostname":"secured-files.atrium.io","path":"/dira","share_for_days":1,"writable":true,"upload_only":true}"#,
+    )
+    .await
+    .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    // A file request cannot be made on a read only dav
+    let response = get_share_token(
+        r#"{"hostname":"secured-files-2.atrium.io","path":"/","share_for_days":1,"upload_only":true}"#,
+    )
+    .await
+    .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = get_share_token(
+        r#"{"hostname":"secured-files.atrium.io","path":"/dira","share_for_days":1,"upload_only":true,"upload_prefix":"client_","max_upload_size":10}"#,
+    )
+    .await
+    .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let share_token = response.json::<ShareResponse>().await.unwrap().token;
+
+    // Recipients with their own browsers
+    let new_client = || {
+        reqwest::Client::builder()
+            .resolve(
+                "secured-files.atrium.io",
+                format!("127.0.0.1:{}", app.port).parse().unwrap(),
+            )
+            .cookie_store(true)
+            .build()
+            .unwrap()
+    };
+    let client = new_client();
+    let url = |path: &str| {
+        format!(
+            "http://secured-files.atrium.io:{}{path}?token={share_token}",
+            app.port
+        )
+    };
+    let propfind = |client: &reqwest::Client| {
+        client.request(
+            hyper::Method::from_bytes(b"PROPFIND").unwrap(),
+            url("/dira"),
+        )
+    };
+
+    // Act and Assert : the existing files cannot be seen
+    let resp = propfind(&client).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+    assert!(!resp.text().await.unwrap().contains("file1"));
+    let resp = client.get(url("/dira/file1")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = client.get(url("/dira")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // Act and Assert : a file can be uploaded, within the size limit, and gets the prefix
+    let resp = client
+        .put(url("/dira/report.txt"))
+        .body("report")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let resp = client
+        .put(url("/dira/big.txt"))
+        .body("more than ten bytes")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    // The existing files cannot be overwritten
+    let resp = client
+        .put(url("/dira/file1"))
+        .body("erased")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    // The files uploaded during the session can be uploaded again
+    let resp = client
+        .put(url("/dira/report.txt"))
+        .body("report")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    // The properties of the shared folder cannot be modified
+    let resp = client
+        .request(
+            hyper::Method::from_bytes(b"PROPPATCH").unwrap(),
+            url("/dira"),
+        )
+        .body(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:atrium:test">
+  <D:set><D:prop><Z:color>red</Z:color></D:prop></D:set>
+</D:propertyupdate>"#,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // Act and Assert : the uploaded file can be seen, but not modified with other methods
+    let resp = propfind(&client).send().await.unwrap();
+    let listing = resp.text().await.unwrap();
+    assert!(listing.contains("client_report.txt"));
+    assert!(!listing.contains("file1"));
+    let resp = client
+        .get(url("/dira/client_report.txt"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.text().await.unwrap(), "report");
+    let resp = client
+        .get(format!("{}&q=file", url("/dira")))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.text().await.unwrap(), "[]");
+    let resp = client
+        .delete(url("/dira/client_report.txt"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // Act and Assert : another recipient does not see the uploaded file
+    let resp = propfind(&new_client()).send().await.unwrap();
+    assert!(!resp.text().await.unwrap().contains("client_report.txt"));
+}
+
+#[tokio::test]
+async fn share_access_history_test() {
+    // Arrange : send the e-mails to a local SMTP stand-in
+    let mut app = TestApp::spawn(None).await;
+    let smtp_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let smtp_port = smtp_listener.local_addr().unwrap().port();
+    let (mails_sender, mut mails) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(mock_smtp_server(smtp_listener, mails_sender));
+    let fp = format!("{}.yaml", &app.id);
+    let mut config = Config::from_file(&fp)
+        .await
+        .expect("failed to read config file");
+    config.smtp_config = Some(SmtpConfig {
+        host: "127.0.0.1".to_owned(),
+        port: Some(smtp_port),
+        tls: SmtpTls::No,
+        from: "atrium@atrium.io".to_owned(),
+        ..Default::default()
+    });
+    config
+        .to_file(&fp)
+        .await
+        .expect("failed to write config file");
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    app.is_ready().await;
+
+    // Share a directory, asking to be notified of the downloads
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let response = app
+        .client
+        .post(format!(
+            "http://atrium.io:{}/api/user/get_share_token",
+            app.port
+        ))
+        .header("Content-Type", "application/json")
+        .header("xsrf-token", &xsrf_token)
+        .body(r#"{"hostname":"secured-files.atrium.io","path":"/dira","share_for_days":1,"notify":true}"#)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let share_token = response.json::<ShareResponse>().await.unwrap().token;
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/shares", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    let share = response.json::<Vec<ShareRecord>>().await.unwrap().remove(0);
+    assert_eq!(share.notify_email.as_deref(), Some("admin@atrium.io"));
+
+    // Act : download a file with the share link
+    let resp = reqwest::Client::builder()
+        .resolve(
+            "secured-files.atrium.io",
+            format!("127.0.0.1:{}", app.port).parse().unwrap(),
+        )
+        .build()
+        .unwrap()
+        .get(format!(
+            "http://secured-files.atrium.io:{}/dira/file1?token={share_token}",
+            app.port
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // Assert : the creator of the share is notified by e-mail
+    let mail = tokio::time::timeout(std::time::Duration::from_secs(10), mails.recv())
+        .await
+        .expect("no e-mail received")
+        .unwrap();
+    assert!(mail.contains("To: admin@atrium.io"));
+    assert!(mail.contains("Share accessed : /dira/file1"));
+
+    // Assert : the access is in the history of the share
+    let response = app
+        .client
+        .get(format!(
+            "http://atrium.io:{}/api/user/shares/{}/history",
+            app.port, share.id
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let history = response.json::<Vec<ShareAccess>>().await.unwrap();
+    assert_eq!(history.len(), 1);
+    let access = history.first().unwrap();
+    assert_eq!(access.method, "GET");
+    assert_eq!(access.path, "/dira/file1");
+    assert_eq!(access.status, 200);
+    assert_eq!(access.by, "admin (downloading)");
+
+    // Assert : the history is only given to the creator of the share and the admins
+    let response = app
+        .client
+        .get(format!(
+            "http://atrium.io:{}/api/admin/shares/{}/history",
+            app.port, share.id
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.json::<Vec<ShareAccess>>().await.unwrap(), history);
+    let xsrf_token = login_and_get_xsrf_token(&app, "user").await;
+    let response = app
+        .client
+        .get(format!(
+            "http://atrium.io:{}/api/user/shares/{}/history",
+            app.port, share.id
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -405,6 +405,28 @@ async fn get_dir_search() -> BoxResult<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn get_dir_search_json() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let resp = app
+        .client
+        .get(format!(
+            "http://files1.atrium.io:{}?q={}",
+            app.port, "FILE1"
+        ))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    let items = resp.json::<Vec<serde_json::Value>>().await?;
+    let mut names = items
+        .iter()
+        .filter_map(|item| item.get("name").and_then(serde_json::Value::as_str))
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    assert_eq!(names, ["dira/file1", "dira/subdira/file1", "dirb/file1"]);
+    Ok(())
+}
+
 #[tokio::test]
 async fn get_dir_search_not_existing() -> BoxResult<()> {
     let app = TestApp::spawn(None).await;
@@ -585,6 +607,121 @@ async fn put_file_alter_modtime() -> BoxResult<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn put_file_interrupted_keeps_previous_content() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    for host in ["files1", "files2"] {
+        let url = format!("http://{host}.atrium.io:{}/myfile", app.port);
+        let resp = app.client.put(&url).body("previous").send().await?;
+        assert_eq!(resp.status(), 201);
+
+        // The client disconnects in the middle of the upload
+        let chunks: [Result<Vec<u8>, io::Error>; 2] = [
+            Ok(b"next".to_vec()),
+            Err(io::Error::other("client disconnected")),
+        ];
+        let resp = app
+            .client
+            .put(&url)
+            .body(reqwest::Body::wrap_stream(futures::stream::iter(chunks)))
+            .send()
+            .await;
+        assert!(resp.is_err() || resp.is_ok_and(|r| r.status() == 500));
+        assert_eq!(app.client.get(&url).send().await?.text().await?, "previous");
+
+        let resp = app.client.put(&url).body("next").send().await?;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(app.client.get(&url).send().await?.text().await?, "next");
+        // The temporary files of the uploads are not served
+        let body = propfind(&app, &format!("http://{host}.atrium.io:{}/", app.port))
+            .send()
+            .await?
+            .text()
+            .await?;
+        assert!(!body.contains(".atrium_upload"));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn conditional_writes() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    for host in ["files1", "files2"] {
+        let url = format!("http://{host}.atrium.io:{}/conditional", app.port);
+        let resp = app
+            .client
+            .put(&url)
+            .header("If-None-Match", "*")
+            .body("first")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 201);
+        let first_etag = resp.headers()["ETag"].clone();
+        // Someone else created the file meanwhile
+        let resp = app
+            .client
+            .put(&url)
+            .header("If-None-Match", "*")
+            .body("other")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 412);
+
+        let resp = app
+            .client
+            .put(&url)
+            .header("If-Match", &first_etag)
+            .body("second version")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 201);
+        let second_etag = resp.headers()["ETag"].clone();
+        assert_ne!(first_etag, second_etag);
+        // The update based on the first version would be lost
+        let resp = app
+            .client
+            .put(&url)
+            .header("If-Match", &first_etag)
+            .body("lost update")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 412);
+        assert_eq!(
+            app.client.get(&url).send().await?.text().await?,
+            "second version"
+        );
+
+        let resp = mv(&app, &url)
+            .header("Destination", format!("{url}-moved"))
+            .header("If-Unmodified-Since", "Mon, 01 Jan 2001 00:00:00 GMT")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 412);
+        let resp = app
+            .client
+            .delete(&url)
+            .header("If-Match", &first_etag)
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 412);
+        let resp = app
+            .client
+            .delete(&url)
+            .header("If-Match", &second_etag)
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 204);
+        let resp = app
+            .client
+            .delete(&url)
+            .header("If-Match", "*")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 412);
+    }
+    Ok(())
+}
+
 #[tokio::test]
 async fn delete_file() -> BoxResult<()> {
     let app = TestApp::spawn(None).await;
@@ -731,6 +868,113 @@ async fn propfind_file_encrypted() -> BoxResult<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn propfind_prop() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let url = format!("http://files1.atrium.io:{}/dira/file1", app.port);
+    let resp = propfind(&app, &url)
+        .body(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propfind xmlns:D="DAV:" xmlns:Z="urn:atrium:test">
+  <D:prop><D:getetag/><D:getcontenttype/><D:creationdate/><Z:missing/></D:prop>
+</D:propfind>"#,
+        )
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text().await?;
+    assert!(body.contains("<D:getetag>\""));
+    assert!(body.contains("<D:getcontenttype>application/octet-stream</D:getcontenttype>"));
+    assert!(body.contains("<D:creationdate>"));
+    // Only the asked properties are returned, the unknown ones with a 404 status
+    assert!(!body.contains("<D:displayname>"));
+    assert!(body.contains(r#"<missing xmlns="urn:atrium:test"/>"#));
+    assert!(body.contains("<D:status>HTTP/1.1 404 Not Found</D:status>"));
+
+    // The names of the properties, including the quota of the collections
+    let url = format!("http://files1.atrium.io:{}/dira", app.port);
+    let resp = propfind(&app, &url)
+        .header("depth", "0")
+        .body(r#"<propfind xmlns="DAV:"><propname/></propfind>"#)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text().await?;
+    assert!(body.contains("<D:displayname/>"));
+    assert!(body.contains("<D:quota-used-bytes/>"));
+    assert!(!body.contains("<D:displayname>dira</D:displayname>"));
+
+    // The quota is only given on demand
+    let resp = propfind(&app, &url).header("depth", "0").send().await?;
+    assert!(!resp.text().await?.contains("quota-used-bytes"));
+    app.client
+        .put(format!("{url}/file1"))
+        .body(b"abc".to_vec())
+        .send()
+        .await?;
+    let resp = propfind(&app, &url)
+        .header("depth", "0")
+        .body(
+            r#"<D:propfind xmlns:D="DAV:"><D:allprop/><D:include><D:quota-used-bytes/></D:include></D:propfind>"#,
+        )
+        .send()
+        .await?;
+    let body = resp.text().await?;
+    assert!(body.contains("<D:quota-used-bytes>3</D:quota-used-bytes>"));
+    assert!(body.contains("<D:displayname>dira</D:displayname>"));
+
+    let resp = propfind(&app, &url).body("<D:propfind").send().await?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn propfind_depth_infinity() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let url = format!("http://files1.atrium.io:{}/dira", app.port);
+    let resp = propfind(&app, &url)
+        .header("depth", "infinity")
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text().await?;
+    assert!(body.contains("<D:href>/dira/subdira/file1</D:href>"));
+
+    // The listings exceeding the configured limit are refused
+    let url = format!("http://files1.atrium.io:{}/", app.port);
+    let resp = propfind(&app, &url)
+        .header("depth", "infinity")
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text().await?.contains("propfind-finite-depth"));
+
+    let resp = propfind(&app, &url).header("depth", "2").send().await?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn propfind_large_dir() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let url = format!("http://files1.atrium.io:{}/large/", app.port);
+    mkcol(&app, &url).send().await?;
+    for i in 0..300 {
+        app.client
+            .put(format!("{url}file{i}"))
+            .body(i.to_string())
+            .send()
+            .await?;
+    }
+    let resp = propfind(&app, &url).send().await?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text().await?;
+    assert!(body.ends_with("</D:multistatus>"));
+    assert_eq!(body.matches("<D:response>").count(), 301);
+    assert!(body.contains("<D:href>/large/file299</D:href>"));
+    Ok(())
+}
+
 #[tokio::test]
 async fn proppatch_file_no_modtime() -> BoxResult<()> {
     let app = TestApp::spawn(None).await;
@@ -784,6 +1028,168 @@ async fn proppatch_404() -> BoxResult<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn proppatch_dead_properties() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let url = format!("http://files1.atrium.io:{}/dira/file1", app.port);
+    let win32_attributes =
+        r#"<Win32FileAttributes xmlns="urn:schemas-microsoft-com:">00000020</Win32FileAttributes>"#;
+    let favorite = r#"<favorite xmlns="http://owncloud.org/ns">1</favorite>"#;
+
+    // Set properties on a file and on a folder
+    for url in [&url, &format!("http://files1.atrium.io:{}/dira", app.port)] {
+        let resp = proppatch(&app, url)
+            .body(
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:schemas-microsoft-com:" xmlns:oc="http://owncloud.org/ns">
+    <D:set>
+        <D:prop>
+            <Z:Win32FileAttributes>00000020</Z:Win32FileAttributes>
+            <oc:favorite>1</oc:favorite>
+        </D:prop>
+    </D:set>
+</D:propertyupdate>"#,
+            )
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 207);
+        let body = resp.text().await?;
+        assert!(body.contains(r#"<Win32FileAttributes xmlns="urn:schemas-microsoft-com:"/>"#));
+        assert!(body.contains("<D:status>HTTP/1.1 200 OK</D:status>"));
+    }
+    let resp = propfind(&app, &url).send().await?;
+    let body = resp.text().await?;
+    assert!(body.contains(win32_attributes));
+    assert!(body.contains(favorite));
+    let resp = propfind(&app, &format!("http://files1.atrium.io:{}/dira", app.port))
+        .send()
+        .await?;
+    let body = resp.text().await?;
+    assert_eq!(body.matches(favorite).count(), 2);
+    assert!(!body.contains(".atrium_properties"));
+    let resp = app
+        .client
+        .get(format!(
+            "http://files1.atrium.io:{}/dira/.atrium_properties",
+            app.port
+        ))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 404);
+
+    // The values are written with their own namespaces, and the root cannot have dead properties
+    let root_url = format!("http://files1.atrium.io:{}/", app.port);
+    for url in [&url, &root_url] {
+        let resp = proppatch(&app, url)
+            .body(
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:a="urn:atrium">
+    <D:set><D:prop><a:author><a:name>Tom &amp; Jerry</a:name></a:author></D:prop></D:set>
+</D:propertyupdate>"#,
+            )
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 207);
+    }
+    let resp = propfind(&app, &url).send().await?;
+    assert!(resp.text().await?.contains(
+        r#"<author xmlns="urn:atrium"><name xmlns="urn:atrium">Tom &amp; Jerry</name></author>"#
+    ));
+    let resp = propfind(&app, &root_url)
+        .header("Depth", "0")
+        .send()
+        .await?;
+    assert!(!resp.text().await?.contains("urn:atrium"));
+
+    // The live properties cannot be set, and then nothing is changed
+    let resp = proppatch(&app, &url)
+        .body(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:oc="http://owncloud.org/ns">
+    <D:remove><D:prop><oc:favorite/></D:prop></D:remove>
+    <D:set><D:prop><D:getcontentlength>12</D:getcontentlength></D:prop></D:set>
+</D:propertyupdate>"#,
+        )
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text().await?;
+    assert!(body.contains("<D:status>HTTP/1.1 403 Forbidden</D:status>"));
+    assert!(body.contains("<D:status>HTTP/1.1 424 Failed Dependency</D:status>"));
+    let resp = propfind(&app, &url).send().await?;
+    assert!(resp.text().await?.contains(favorite));
+
+    // The properties are moved and copied with the resource
+    let moved_url = format!("http://files1.atrium.io:{}/dira/file1_moved", app.port);
+    let resp = mv(&app, &url)
+        .header("Destination", &moved_url)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+    let resp = copy(&app, &moved_url)
+        .header("Destination", &url)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+    for url in [&url, &moved_url] {
+        let resp = propfind(&app, url).send().await?;
+        assert!(resp.text().await?.contains(win32_attributes));
+    }
+    let copied_dir_url = format!("http://files1.atrium.io:{}/dira_copy/", app.port);
+    let resp = copy(&app, &format!("http://files1.atrium.io:{}/dira", app.port))
+        .header("Destination", &copied_dir_url)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+    let resp = propfind(&app, &copied_dir_url).send().await?;
+    assert_eq!(resp.text().await?.matches(favorite).count(), 3);
+
+    // And deleted with it
+    let resp = app.client.delete(&moved_url).send().await?;
+    assert_eq!(resp.status(), 204);
+    let resp = app
+        .client
+        .put(&moved_url)
+        .body(b"abc".to_vec())
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+    let resp = propfind(&app, &moved_url).send().await?;
+    assert!(!resp.text().await?.contains(win32_attributes));
+    Ok(())
+}
+
+#[tokio::test]
+async fn put_keeps_dead_properties() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let url = format!("http://files1.atrium.io:{}/dira/file2", app.port);
+    let favorite = r#"<favorite xmlns="http://owncloud.org/ns">1</favorite>"#;
+    let resp = proppatch(&app, &url)
+        .body(
+            r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:oc="http://owncloud.org/ns">
+    <D:set><D:prop><oc:favorite>1</oc:favorite></D:prop></D:set>
+</D:propertyupdate>"#,
+        )
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 207);
+
+    // The upload replaces the content of the file, not its properties
+    let resp = app
+        .client
+        .put(&url)
+        .body(b"new content".to_vec())
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+    let resp = propfind(&app, &url).send().await?;
+    let body = resp.text().await?;
+    assert!(body.contains(favorite));
+    assert!(body.contains("<D:getcontentlength>11</D:getcontentlength>"));
+    Ok(())
+}
+
 #[tokio::test]
 async fn mkcol_dir() -> BoxResult<()> {
     let app = TestApp::spawn(None).await;
@@ -1027,15 +1433,45 @@ async fn lock_unlock_unexisting_file() -> BoxResult<()> {
     let app = TestApp::spawn(None).await;
     let url = format!("http://files1.atrium.io:{}/file3", app.port);
 
+    // The lock creates an empty resource
     let resp = lock(&app, &url).send().await?;
     assert_eq!(resp.status(), 201);
+    let resp_get = app.client.get(&url).send().await?;
+    assert_eq!(resp_get.status(), 200);
+    assert!(resp_get.bytes().await?.is_empty());
 
+    // Which is removed by the unlock since it was not written
     let lock_token = resp.headers().get("lock-token").unwrap();
     let unlock_resp = unlock(&app, &url)
         .header("Lock-Token", lock_token)
         .send()
         .await?;
-    assert_eq!(unlock_resp.status(), 404);
+    assert_eq!(unlock_resp.status(), 204);
+    let resp = app.client.get(&url).send().await?;
+    assert_eq!(resp.status(), 404);
+
+    // Unless it was written while locked
+    let resp = lock(&app, &url).send().await?;
+    let lock_token = resp.headers().get("lock-token").unwrap().to_str()?;
+    let resp_put = app
+        .client
+        .put(&url)
+        .header("If", format!("({lock_token})"))
+        .body(b"abc".to_vec())
+        .send()
+        .await?;
+    assert_eq!(resp_put.status(), 201);
+    unlock(&app, &url)
+        .header("Lock-Token", lock_token)
+        .send()
+        .await?;
+    let resp = app.client.get(&url).send().await?;
+    assert_eq!(resp.text().await?, "abc");
+
+    // A lock on an unmapped URL needs an existing collection
+    let url = format!("http://files1.atrium.io:{}/dirz/file3", app.port);
+    let resp = lock(&app, &url).send().await?;
+    assert_eq!(resp.status(), 409);
 
     Ok(())
 }
@@ -1053,7 +1489,12 @@ async fn unlock_with_invalid_token() -> BoxResult<()> {
         .header("Lock-Token", "<invalidtoken>")
         .send()
         .await?;
-    assert_eq!(resp.status(), 403); // 403 Forbidden
+    assert_eq!(resp.status(), 409); // 409 Conflict
+    assert!(
+        resp.text()
+            .await?
+            .contains("<D:lock-token-matches-request-uri>")
+    );
 
     Ok(())
 }
@@ -1080,34 +1521,244 @@ async fn lock_expiration() -> BoxResult<()> {
     Ok(())
 }
 
-#[cfg(unix)]
-use std::os::unix::fs::symlink as symlink_dir;
-#[cfg(windows)]
-use std::os::windows::fs::symlink_dir;
-
 #[tokio::test]
-async fn default_not_allow_symlinks() -> BoxResult<()> {
+async fn locked_file_write_requires_token() -> BoxResult<()> {
     let app = TestApp::spawn(None).await;
-    std::fs::create_dir_all(format!("./data/{}/dir_symlink", app.id))?;
-    std::fs::write(
-        format!("./data/{}/dir_symlink/file1", app.id),
-        b"Lorem ipsum",
-    )?;
-    let srcdir = std::fs::canonicalize(std::path::PathBuf::from(format!(
-        "./data/{}/dir_symlink",
-        app.id
-    )))
-    .expect("couldn't canonicalize path");
-    symlink_dir(srcdir, format!("./data/{}/dir1/dirc", app.id)).expect("couldn't create symlink");
-    let resp = app
-        .client
-        .get(format!("http://files1.atrium.io:{}/dirc", app.port))
+    let url = format!("http://files1.atrium.io:{}/dira/file1", app.port);
+    let resp = lock(&app, &url).send().await?;
+    let lock_token = resp.headers().get("lock-token").unwrap().to_str()?;
+    let if_header = format!("({lock_token})");
+
+    // The writes without the lock token are refused
+    let resp = app.client.put(&url).body(b"abc".to_vec()).send().await?;
+    assert_eq!(resp.status(), 423);
+    assert!(resp.text().await?.contains("<D:lock-token-submitted>"));
+    let resp = app.client.delete(&url).send().await?;
+    assert_eq!(resp.status(), 423);
+    let dir_url = format!("http://files1.atrium.io:{}/dira", app.port);
+    let resp = mv(&app, &dir_url)
+        .header(
+            "Destination",
+            format!("http://files1.atrium.io:{}/dira_moved", app.port),
+        )
         .send()
         .await?;
-    assert_eq!(resp.status(), 404);
+    assert_eq!(resp.status(), 423);
+    let resp = copy(
+        &app,
+        &format!("http://files1.atrium.io:{}/dira/file2", app.port),
+    )
+    .header("Destination", &url)
+    .send()
+    .await?;
+    assert_eq!(resp.status(), 423);
+
+    // An If header that does not match fails
     let resp = app
         .client
-        .get(format!("http://files1.atrium.io:{}/dirc/file1", app.port))
+        .put(&url)
+        .header("If", "(<opaquelocktoken:unknown>)")
+        .body(b"abc".to_vec())
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 412);
+    let resp = app
+        .client
+        .put(&url)
+        .header("If", "(<opaquelocktoken")
+        .body(b"abc".to_vec())
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 400);
+
+    // The lock is advertised
+    let resp = propfind(&app, &url).send().await?;
+    let body = resp.text().await?;
+    assert!(body.contains("<D:supportedlock>"));
+    assert!(body.contains(&format!(
+        "<D:locktoken><D:href>{}</D:href>",
+        lock_token.trim_matches(['<', '>'])
+    )));
+
+    // The writes with the lock token are allowed
+    let resp = app
+        .client
+        .put(&url)
+        .header("If", &if_header)
+        .body(b"abc".to_vec())
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+    let resp = app.client.get(&url).send().await?;
+    let etag = resp.headers().get("etag").unwrap().to_str()?.to_owned();
+    assert_eq!(resp.text().await?, "abc");
+    let resp = app
+        .client
+        .delete(&url)
+        .header("If", format!("<{url}> ({lock_token} [{etag}])"))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 204);
+    // The lock is gone with the resource
+    let resp = app.client.put(&url).body(b"abc".to_vec()).send().await?;
+    assert_eq!(resp.status(), 201);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lock_collection() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let url = format!("http://files1.atrium.io:{}/dira", app.port);
+    let file_url = format!("http://files1.atrium.io:{}/dira/file2", app.port);
+    let lockinfo = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:lockinfo xmlns:D="DAV:">
+  <D:lockscope><D:shared/></D:lockscope>
+  <D:locktype><D:write/></D:locktype>
+  <D:owner><D:href>mailto:admin@atrium.io</D:href></D:owner>
+</D:lockinfo>"#;
+
+    // Shared locks can be taken on a collection and its members
+    let resp = lock(&app, &url)
+        .header("Depth", "infinity")
+        .body(lockinfo)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    let lock_token = resp
+        .headers()
+        .get("lock-token")
+        .unwrap()
+        .to_str()?
+        .to_owned();
+    let body = resp.text().await?;
+    assert!(body.contains("<D:lockscope><D:shared/></D:lockscope>"));
+    assert!(body.contains("<D:depth>infinity</D:depth>"));
+    assert!(body.contains("<D:owner><D:href>mailto:admin@atrium.io</D:href></D:owner>"));
+    let resp = lock(&app, &file_url).body(lockinfo).send().await?;
+    assert_eq!(resp.status(), 200);
+    // But not exclusive ones
+    let resp = lock(&app, &file_url).send().await?;
+    assert_eq!(resp.status(), 423);
+    assert!(resp.text().await?.contains("<D:no-conflicting-lock>"));
+    let resp = lock(&app, &url).header("Depth", "1").send().await?;
+    assert_eq!(resp.status(), 400);
+
+    // Adding a member to the collection requires the token
+    let new_url = format!("http://files1.atrium.io:{}/dira/file3", app.port);
+    let resp = app
+        .client
+        .put(&new_url)
+        .body(b"abc".to_vec())
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 423);
+    let resp = mkcol(
+        &app,
+        &format!("http://files1.atrium.io:{}/dira/dir", app.port),
+    )
+    .send()
+    .await?;
+    assert_eq!(resp.status(), 423);
+    let resp = app
+        .client
+        .put(&new_url)
+        .header("If", format!("({lock_token})"))
+        .body(b"abc".to_vec())
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+
+    // The lock can be refreshed
+    let resp = lock(&app, &file_url)
+        .header("If", format!("({lock_token})"))
+        .header("Timeout", "Second-100")
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("timeout").unwrap(), "Second-100");
+    assert!(resp.headers().get("lock-token").is_none());
+    let resp = lock(&app, &file_url)
+        .header("If", "(<opaquelocktoken:unknown>)")
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 412);
+
+    // And removed from a member of the collection
+    let resp = unlock(&app, &new_url)
+        .header("Lock-Token", &lock_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 204);
+    let resp = app
+        .client
+        .put(&new_url)
+        .body(b"abcd".to_vec())
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn lock_survives_reload() -> BoxResult<()> {
+    let mut app = TestApp::spawn(None).await;
+    let url = format!("http://files1.atrium.io:{}/dira/file1", app.port);
+    let resp = lock(&app, &url).send().await?;
+    assert_eq!(resp.status(), 200);
+    let lock_token = resp
+        .headers()
+        .get("lock-token")
+        .unwrap()
+        .to_str()?
+        .to_owned();
+
+    app.client
+        .get(format!("http://atrium.io:{}/reload", app.port))
+        .send()
+        .await?;
+    app.is_ready().await;
+
+    let resp = app.client.put(&url).body(b"abc".to_vec()).send().await?;
+    assert_eq!(resp.status(), 423);
+    let resp = unlock(&app, &url)
+        .header("Lock-Token", &lock_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 204);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+use std::os::unix::fs::symlink as symlink_dir;
+#[cfg(windows)]
+use std::os::windows::fs::symlink_dir;
+
+#[tokio::test]
+async fn default_not_allow_symlinks() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    std::fs::create_dir_all(format!("./data/{}/dir_symlink", app.id))?;
+    std::fs::write(
+        format!("./data/{}/dir_symlink/file1", app.id),
+        b"Lorem ipsum",
+    )?;
+    let srcdir = std::fs::canonicalize(std::path::PathBuf::from(format!(
+        "./data/{}/dir_symlink",
+        app.id
+    )))
+    .expect("couldn't canonicalize path");
+    symlink_dir(srcdir, format!("./data/{}/dir1/dirc", app.id)).expect("couldn't create symlink");
+    let resp = app
+        .client
+        .get(format!("http://files1.atrium.io:{}/dirc", app.port))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 404);
+    let resp = app
+        .client
+        .get(format!("http://files1.atrium.io:{}/dirc/file1", app.port))
         .send()
         .await?;
     assert_eq!(resp.status(), 404);
@@ -1344,3 +1995,460 @@ async fn secured_dav_basic_auth_and_token_test() {
         .expect("failed to execute request");
     assert!(response.status() == StatusCode::UNAUTHORIZED);
 }
+
+#[tokio::test]
+async fn tus_resumable_upload() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    // files2 is encrypted, its uploads are assembled from a segment per PATCH
+    for host in ["files1", "files2"] {
+        let base = format!("http://{host}.atrium.io:{}", app.port);
+        let create = || {
+            app.client
+                .post(format!("{base}/dira/"))
+                .header("Tus-Resumable", "1.0.0")
+                .header("Upload-Length", "6")
+                .header("Upload-Metadata", "filename dmlkZW8ubXA0")
+        };
+        // The preconditions apply to the file to be written
+        let resp = create().header("If-Match", "*").send().await?;
+        assert_eq!(resp.status(), 412);
+        let resp = create().send().await?;
+        assert_eq!(resp.status(), 201);
+        let location = resp
+            .headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .expect("upload location")
+            .to_owned();
+        let url = format!("{base}{location}");
+        let patch = |offset: &str, body: &'static str| {
+            app.client
+                .patch(&url)
+                .header("Tus-Resumable", "1.0.0")
+                .header("Content-Type", "application/offset+octet-stream")
+                .header("Upload-Offset", offset)
+                .body(body)
+        };
+
+        let resp = app.client.patch(&url).body("abc").send().await?;
+        assert_eq!(resp.status(), 412);
+        let resp = patch("0", "abc").send().await?;
+        assert_eq!(resp.status(), 204);
+        assert_eq!(resp.headers()["Upload-Offset"], "3");
+        let resp = patch("0", "abc").send().await?;
+        assert_eq!(resp.status(), 409);
+        // A body not matching its checksum is discarded
+        let resp = patch("3", "deX")
+            .header("Upload-Checksum", "sha1 WJwiM1o4HxItEpIl9cC6MFbtWBE=")
+            .send()
+            .await?;
+        assert_eq!(resp.status().as_u16(), 460);
+        let resp = app
+            .client
+            .head(&url)
+            .header("Tus-Resumable", "1.0.0")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["Upload-Offset"], "3");
+        assert_eq!(resp.headers()["Upload-Length"], "6");
+        // The file appears once complete
+        let file_url = format!("{base}/dira/video.mp4");
+        assert_eq!(app.client.get(&file_url).send().await?.status(), 404);
+        let resp = patch("3", "def")
+            .header("Upload-Checksum", "sha1 WJwiM1o4HxItEpIl9cC6MFbtWBE=")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 204);
+        assert_eq!(
+            app.client.get(&file_url).send().await?.text().await?,
+            "abcdef"
+        );
+        let resp = app
+            .client
+            .head(&url)
+            .header("Tus-Resumable", "1.0.0")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 404);
+
+        // The uploads in progress are not served
+        let resp = propfind(&app, &format!("{base}/")).send().await?;
+        assert!(!resp.text().await?.contains(".atrium_uploads"));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn tus_upload_locked_target() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let base = format!("http://files1.atrium.io:{}", app.port);
+    let file_url = format!("{base}/dira/file1");
+    let create = || {
+        app.client
+            .post(format!("{base}/dira/"))
+            .header("Tus-Resumable", "1.0.0")
+            .header("Upload-Length", "3")
+            .header("Upload-Metadata", "filename ZmlsZTE=")
+    };
+    let patch = |location: &str| {
+        app.client
+            .patch(format!("{base}{location}"))
+            .header("Tus-Resumable", "1.0.0")
+            .header("Content-Type", "application/offset+octet-stream")
+            .header("Upload-Offset", "0")
+            .body("abc")
+    };
+    let location = |resp: &reqwest::Response| {
+        resp.headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+            .expect("upload location")
+    };
+
+    // A target locked during the upload is reported before the data is sent
+    let resp = create().send().await?;
+    assert_eq!(resp.status(), 201);
+    let unlocked_upload = location(&resp);
+    let resp = lock(&app, &file_url).send().await?;
+    assert_eq!(resp.status(), 200);
+    let lock_token = resp
+        .headers()
+        .get("lock-token")
+        .expect("Lock-Token header missing")
+        .to_str()?
+        .to_owned();
+    let resp = patch(&unlocked_upload).send().await?;
+    assert_eq!(resp.status(), 423);
+
+    // The lock holder submits its token at the creation only
+    let resp = create().send().await?;
+    assert_eq!(resp.status(), 423);
+    let resp = create()
+        .header("If", format!("<{file_url}> ({lock_token})"))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+    let resp = patch(&location(&resp)).send().await?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(app.client.get(&file_url).send().await?.text().await?, "abc");
+    Ok(())
+}
+
+#[tokio::test]
+async fn nextcloud_chunked_upload() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    // files2 is encrypted, the chunks are assembled through DavFile
+    for host in ["files1", "files2"] {
+        let base = format!("http://{host}.atrium.io:{}", app.port);
+        let transfer = format!("{base}/remote.php/dav/uploads/admin/transfer-1");
+        let resp = app
+            .client
+            .put(format!("{transfer}/00001"))
+            .body("abc")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 404);
+        assert_eq!(mkcol(&app, &transfer).send().await?.status(), 201);
+        assert_eq!(mkcol(&app, &transfer).send().await?.status(), 405);
+
+        // The chunks are sent in parallel
+        let put = |name: &str, body: &'static str| {
+            app.client
+                .put(format!("{transfer}/{name}"))
+                .body(body)
+                .send()
+        };
+        let (resp3, resp1, resp2) = tokio::join!(
+            put("00003", "ghi"),
+            put("00001", "abc"),
+            put("00002", "def")
+        );
+        for resp in [resp3?, resp1?, resp2?] {
+            assert_eq!(resp.status(), 201);
+        }
+
+        // The total length detects a missing chunk
+        let file_url = format!("{base}/dira/chunked.bin");
+        let resp = mv(&app, &format!("{transfer}/.file"))
+            .header("Destination", &file_url)
+            .header("OC-Total-Length", "12")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 400);
+        assert_eq!(app.client.get(&file_url).send().await?.status(), 404);
+        // The preconditions apply to the assembled file
+        let resp = mv(&app, &format!("{transfer}/.file"))
+            .header("Destination", &file_url)
+            .header("If-Match", "*")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 412);
+
+        let resp = mv(&app, &format!("{transfer}/.file"))
+            .header("Destination", &file_url)
+            .header("OC-Total-Length", "9")
+            .header("X-OC-Mtime", "1700000000")
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 201);
+        assert_eq!(resp.headers()["X-OC-Mtime"], "accepted");
+        assert!(resp.headers().contains_key("ETag"));
+        assert_eq!(
+            app.client.get(&file_url).send().await?.text().await?,
+            "abcdefghi"
+        );
+        // The transfer is removed once assembled
+        let resp = mv(&app, &format!("{transfer}/.file"))
+            .header("Destination", &file_url)
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 404);
+
+        // An abandoned transfer can be deleted
+        assert_eq!(mkcol(&app, &transfer).send().await?.status(), 201);
+        let resp = app.client.delete(&transfer).send().await?;
+        assert_eq!(resp.status(), 204);
+        assert_eq!(app.client.delete(&transfer).send().await?.status(), 404);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn trash_bin() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let base = format!("http://files2.atrium.io:{}", app.port);
+    let trash_url = format!("http://atrium.io:{}/api/user/trash/2", app.port);
+    let list = async || {
+        let items = app
+            .client
+            .get(&trash_url)
+            .header("xsrf-token", &xsrf_token)
+            .send()
+            .await?
+            .json::<Vec<serde_json::Value>>()
+            .await?;
+        let ids = items
+            .iter()
+            .filter_map(|item| item.get("id").and_then(serde_json::Value::as_str))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        Ok::<_, Box<dyn std::error::Error>>((items, ids))
+    };
+
+    let resp = app
+        .client
+        .put(format!("{base}/trashed/doc.txt"))
+        .body("content")
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 201);
+    let resp = app.client.delete(format!("{base}/trashed")).send().await?;
+    assert_eq!(resp.status(), 204);
+    let resp = app
+        .client
+        .get(format!("{base}/trashed/doc.txt"))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 404);
+    let (items, ids) = list().await?;
+    assert_eq!(ids.len(), 1);
+    let item = items.first().expect("deleted folder");
+    assert_eq!(
+        item.get("path").and_then(serde_json::Value::as_str),
+        Some("trashed")
+    );
+    assert_eq!(item.get("is_dir"), Some(&serde_json::Value::Bool(true)));
+    // The trash bin is not served
+    let resp = propfind(&app, &format!("{base}/")).send().await?;
+    assert!(!resp.text().await?.contains(".atrium_trash"));
+
+    // The original path must be free to restore a resource
+    assert_eq!(
+        mkcol(&app, &format!("{base}/trashed"))
+            .send()
+            .await?
+            .status(),
+        201
+    );
+    let restore_url = |id: &str| format!("{trash_url}/{id}/restore");
+    let first_id = ids.first().expect("deleted folder id").clone();
+    let resp = app
+        .client
+        .post(restore_url(&first_id))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 409);
+    let resp = app.client.delete(format!("{base}/trashed")).send().await?;
+    assert_eq!(resp.status(), 204);
+    let resp = app
+        .client
+        .post(restore_url(&first_id))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        app.client
+            .get(format!("{base}/trashed/doc.txt"))
+            .send()
+            .await?
+            .text()
+            .await?,
+        "content"
+    );
+
+    // The remaining deleted folder is purged for good
+    let (_, ids) = list().await?;
+    let [empty_id] = ids.as_slice() else {
+        return Err("one deleted folder expected".into());
+    };
+    let resp = app
+        .client
+        .delete(format!("{trash_url}/{empty_id}"))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    assert!(list().await?.1.is_empty());
+    let resp = app
+        .client
+        .post(restore_url("../trashed"))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 404);
+
+    // files1 has no trash bin
+    let resp = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/trash/1", app.port))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[tokio::test]
+async fn file_versions() -> BoxResult<()> {
+    let app = TestApp::spawn(None).await;
+    let xsrf_token = login_and_get_xsrf_token(&app, "admin").await;
+    let base = format!("http://files2.atrium.io:{}", app.port);
+    let versions_url = format!("http://atrium.io:{}/api/user/versions/2", app.port);
+    let list = async || {
+        let versions = app
+            .client
+            .get(format!("{versions_url}?path=versioned/doc.txt"))
+            .header("xsrf-token", &xsrf_token)
+            .send()
+            .await?
+            .json::<Vec<serde_json::Value>>()
+            .await?;
+        Ok::<_, Box<dyn std::error::Error>>(
+            versions
+                .iter()
+                .filter_map(|version| version.get("id").and_then(serde_json::Value::as_u64))
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    assert_eq!(
+        mkcol(&app, &format!("{base}/versioned"))
+            .send()
+            .await?
+            .status(),
+        201
+    );
+    for content in ["v1", "v2"] {
+        let resp = app
+            .client
+            .put(format!("{base}/versioned/doc.txt"))
+            .body(content)
+            .send()
+            .await?;
+        assert_eq!(resp.status(), 201);
+    }
+    let ids = list().await?;
+    let [id] = ids.as_slice() else {
+        return Err("one version expected".into());
+    };
+
+    // The versions are browsable read only, decrypted as the other files of the dav
+    let collection = format!("{base}/.atrium_versions/versioned/doc.txt");
+    let resp = propfind(&app, &collection).send().await?;
+    assert_eq!(resp.status(), 207);
+    assert!(resp.text().await?.contains(&format!(
+        "<D:href>/.atrium_versions/versioned/doc.txt/{id}</D:href>"
+    )));
+    let resp = app.client.get(format!("{collection}/{id}")).send().await?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await?, "v1");
+    let resp = app
+        .client
+        .put(format!("{collection}/{id}"))
+        .body("tampered")
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 403);
+    let resp = app.client.delete(&collection).send().await?;
+    assert_eq!(resp.status(), 403);
+    let resp = mv(&app, &format!("{base}/versioned/doc.txt"))
+        .header("Destination", format!("{collection}/1"))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 403);
+
+    let resp = app
+        .client
+        .get(format!("{versions_url}/{id}?path=versioned/doc.txt"))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await?, "v1");
+
+    // Restoring a version keeps the replaced content as a version
+    let resp = app
+        .client
+        .post(format!(
+            "{versions_url}/{id}/restore?path=versioned/doc.txt"
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        app.client
+            .get(format!("{base}/versioned/doc.txt"))
+            .send()
+            .await?
+            .text()
+            .await?,
+        "v1"
+    );
+    assert_eq!(list().await?.len(), 2);
+
+    // The paths are checked, and files1 is not versioned
+    let resp = app
+        .client
+        .get(format!("{versions_url}?path=../dir1/dira/file1"))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 400);
+    let resp = app
+        .client
+        .get(format!(
+            "http://atrium.io:{}/api/user/versions/1?path=dira/file1",
+            app.port
+        ))
+        .header("xsrf-token", &xsrf_token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
@@ -7,8 +7,8 @@ use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, util::Subs
 use atrium::{
     apps::App,
     auth::User,
-    configuration::{Config, OnlyOfficeConfig, OpenIdConfig, TlsMode},
-    davs::model::Dav,
+    configuration::{Argon2Config, Config, OnlyOfficeConfig, OpenIdConfig, ScimConfig, TlsMode},
+    davs::{model::Dav, trash::TrashConfig, versions::VersioningConfig},
     mocks::{mock_oauth2_server, mock_proxied_server},
     server::Server,
     utils::random_string,
@@ -150,6 +150,8 @@ impl Drop for TestApp {
     fn drop(&mut self) {
         self.server_handle.abort();
         std::fs::remove_file(format!("{}.yaml", self.id)).ok();
+        std::fs::remove_file(format!("{}.shares.yaml", self.id)).ok();
+        std::fs::remove_file(format!("{}.share_history.jsonl", self.id)).ok();
         std::fs::remove_dir_all(format!("./data/{}", self.id)).ok();
     }
 }
@@ -265,6 +267,9 @@ pub fn create_default_config(
             allow_symlinks: false,
             roles: vec!["ADMINS".to_owned(), "USERS".to_owned()],
             passphrase: None,
+            tags: vec![],
+            trash: None,
+            versioning: None,
             key: None,
         },
         Dav {
@@ -279,6 +284,15 @@ pub fn create_default_config(
             allow_symlinks: true,
             roles: vec!["ADMINS".to_owned()],
             passphrase: Some("ABCD123".to_owned()),
+            tags: vec![],
+            trash: Some(TrashConfig {
+                retention_days: 30,
+                max_size: None,
+            }),
+            versioning: Some(VersioningConfig {
+                max_versions: 3,
+                max_age_days: None,
+            }),
             key: None,
         },
         Dav {
@@ -293,6 +307,9 @@ pub fn create_default_config(
             allow_symlinks: true,
             roles: vec!["ADMINS".to_owned(), "USERS".to_owned()],
             passphrase: None,
+            tags: vec![],
+            trash: None,
+            versioning: None,
             key: None,
         },
         Dav {
@@ -307,6 +324,9 @@ pub fn create_default_config(
             allow_symlinks: true,
             roles: vec!["ADMINS".to_owned()],
             passphrase: None,
+            tags: vec![],
+            trash: None,
+            versioning: None,
             key: None,
         },
         Dav {
@@ -321,6 +341,9 @@ pub fn create_default_config(
             allow_symlinks: true,
             roles: vec!["ADMINS".to_owned()],
             passphrase: None,
+            tags: vec![],
+            trash: None,
+            versioning: None,
             key: None,
         },
     ];
@@ -334,6 +357,7 @@ pub fn create_default_config(
                 email:"admin@atrium.io".to_owned(),
                 ..Default::default()
             }),
+            ..Default::default()
         },
         User {
             login: "user".to_owned(),
@@ -351,11 +375,22 @@ pub fn create_default_config(
         letsencrypt_email: "foo@bar.com".to_owned(),
         http_port: *main_port,
         cookie_key: None,
+        previous_cookie_keys: vec![],
         log_to_file: false,
         jail: Default::default(),
+        // Match the parameters of the test users hashes, so that logging in does not rewrite the configuration
+        argon2: Argon2Config {
+            memory_cost: 4096,
+            time_cost: 3,
+            parallelism: 1,
+        },
         apps,
         davs,
         users,
+        groups: Default::default(),
+        role_hierarchy: Default::default(),
+        admin_scopes: Default::default(),
+        consumed_invitations: Default::default(),
         session_duration_days: None,
         single_proxy: false,
         onlyoffice_config: Some(OnlyOfficeConfig {
@@ -369,6 +404,15 @@ pub fn create_default_config(
             )),
             ..Default::default()
         }),
+        scim_config: Some(ScimConfig {
+            bearer_token: "scim-test-token".to_owned(),
+        }),
+        client_cert_config: None,
+        login_challenge: None,
+        smtp_config: None,
+        // The depth infinity listing of dira fits, the one of the root of files1 does not
+        propfind_depth_infinity_limit: Some(8),
+        fsync_uploads: false,
     }
 }
 
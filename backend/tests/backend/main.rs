@@ -1,7 +1,8 @@
 mod admin;
 mod apps;
+mod auth;
 mod davs;
 mod davs_litmus;
 mod helpers;
 mod oauth2;
-mod auth;
+mod scim;
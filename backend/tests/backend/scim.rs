@@ -0,0 +1,173 @@
+use atrium::configuration::Config;
+use hyper::StatusCode;
+use serde_json::{Value, json};
+
+use crate::helpers::{TestApp, login_and_get_xsrf_token};
+
+const SCIM_TOKEN: &str = "scim-test-token";
+
+#[tokio::test]
+async fn scim_authentication_test() {
+    // Arrange
+    let app = TestApp::spawn(None).await;
+
+    // Act and Assert : the SCIM API requires the bearer token
+    for token in [None, Some("wrong-token")] {
+        let mut request = app
+            .client
+            .get(format!("http://atrium.io:{}/scim/v2/Users", app.port));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.expect("failed to execute request");
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body = response.json::<Value>().await.unwrap();
+        assert_eq!(body.pointer("/status"), Some(&json!("401")));
+    }
+
+    // Act and Assert : a logged in admin cannot use it either
+    login_and_get_xsrf_token(&app, "admin").await;
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/scim/v2/Users", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn scim_provisioning_test() {
+    // Arrange
+    let mut app = TestApp::spawn(None).await;
+    let scim_url = format!("http://atrium.io:{}/scim/v2", app.port);
+
+    // Act : create a user and a group
+    let response = app
+        .client
+        .post(format!("{scim_url}/Users"))
+        .bearer_auth(SCIM_TOKEN)
+        .header("Content-Type", "application/scim+json")
+        .json(&json!({
+            "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+            "userName": "jdoe",
+            "name": {"givenName": "John", "familyName": "Doe"},
+            "emails": [{"value": "john.doe@atrium.io", "primary": true}],
+            "password": "password",
+            "roles": [{"value": "USERS"}]
+        }))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response.json::<Value>().await.unwrap();
+    assert_eq!(body.pointer("/id"), Some(&json!("jdoe")));
+    assert_eq!(body.pointer("/password"), None);
+    let response = app
+        .client
+        .post(format!("{scim_url}/Groups"))
+        .bearer_auth(SCIM_TOKEN)
+        .json(&json!({"displayName": "staff", "members": [{"value": "jdoe"}]}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Assert : the user cannot be created twice, and can be found with a filter
+    let response = app
+        .client
+        .post(format!("{scim_url}/Users"))
+        .bearer_auth(SCIM_TOKEN)
+        .json(&json!({"userName": "jdoe"}))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body = response.json::<Value>().await.unwrap();
+    assert_eq!(body.pointer("/scimType"), Some(&json!("uniqueness")));
+    let response = app
+        .client
+        .get(format!(
+            "{scim_url}/Users?filter={}",
+            urlencoding::encode(r#"userName eq "JDOE""#)
+        ))
+        .bearer_auth(SCIM_TOKEN)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.json::<Value>().await.unwrap();
+    assert_eq!(body.pointer("/totalResults"), Some(&json!(1)));
+    assert_eq!(
+        body.pointer("/Resources/0/groups/0/value"),
+        Some(&json!("staff"))
+    );
+
+    // Assert : the configuration is reloaded and the user can log in
+    app.is_ready().await;
+    login_and_get_xsrf_token(&app, "jdoe").await;
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    // Sessions are revoked with a one second precision
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+    // Act : deactivate the user
+    let response = app
+        .client
+        .patch(format!("{scim_url}/Users/jdoe"))
+        .bearer_auth(SCIM_TOKEN)
+        .json(&json!({
+            "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+            "Operations": [{"op": "Replace", "value": {"active": "False"}}]
+        }))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.json::<Value>().await.unwrap();
+    assert_eq!(body.pointer("/active"), Some(&json!(false)));
+
+    // Assert : the session of the user is revoked
+    let response = app
+        .client
+        .get(format!("http://atrium.io:{}/api/user/whoami", app.port))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Act : delete the user
+    let response = app
+        .client
+        .delete(format!("{scim_url}/Users/jdoe"))
+        .bearer_auth(SCIM_TOKEN)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // Assert : the user is removed from the configuration and from its groups
+    let response = app
+        .client
+        .get(format!("{scim_url}/Users/jdoe"))
+        .bearer_auth(SCIM_TOKEN)
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let config = Config::from_file(&format!("{}.yaml", app.id))
+        .await
+        .expect("failed to read config file");
+    assert!(!config.users.iter().any(|u| u.login == "jdoe"));
+    assert!(
+        config
+            .groups
+            .iter()
+            .any(|g| g.name == "staff" && g.members.is_empty())
+    );
+}
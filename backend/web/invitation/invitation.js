@@ -0,0 +1,24 @@
+const form = document.getElementById("invitation");
+const message = document.getElementById("message");
+const token = new URLSearchParams(window.location.search).get("token");
+
+form.addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const password = document.getElementById("password").value;
+  if (password !== document.getElementById("confirmation").value) {
+    message.textContent = "Passwords do not match";
+    return;
+  }
+  const response = await fetch("/auth/invitation", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ token, password }),
+  });
+  if (response.ok) {
+    // Make the new account known to the server before going on
+    await fetch("/reload");
+    window.location.replace("/");
+  } else {
+    message.textContent = await response.text();
+  }
+});
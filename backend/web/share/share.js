@@ -0,0 +1,21 @@
+const form = document.getElementById("unlock");
+const message = document.getElementById("message");
+const params = new URLSearchParams(window.location.search);
+const token = params.get("token");
+const path = params.get("path");
+
+form.addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const password = document.getElementById("password").value;
+  const response = await fetch("/auth/share/unlock", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ token, password, path }),
+  });
+  if (response.ok) {
+    const { url } = await response.json();
+    window.location.replace(url);
+  } else {
+    message.textContent = await response.text();
+  }
+});